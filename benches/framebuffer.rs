@@ -0,0 +1,99 @@
+/* Compares the bit packed framebuffer against the previous
+ * BitMatrix based vram, drawing the same sprite rows with the
+ * exact same wrap/clip rules DXYN uses.
+ *
+ * Run it with: cargo bench --bench framebuffer
+ */
+extern crate bit_vec;
+extern crate bitmatrix;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use bit_vec::BitVec;
+use bitmatrix::BitMatrix;
+use rusty_chip_8::framebuffer::Framebuffer;
+
+const ITERATIONS: usize = 200_000;
+const SPRITE: [u8; 15] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x3C, 0x7E, 0xFF, 0xDB, 0xFF, 0x7E, 0x3C, 0x81, 0x42, 0x24,
+];
+
+// Same algorithm render_sprites_x_y used before the framebuffer
+fn draw_bitmatrix(vram: &mut BitMatrix, x_pos: usize, y_pos: usize) -> bool {
+    let mut collision = false;
+    for (y, byte) in SPRITE.iter().enumerate() {
+        let pixel_vec = BitVec::from_bytes(&[*byte]);
+        let target_y = y + y_pos;
+        for x in 0..8 {
+            let target_x = x + x_pos;
+            if target_x >= 64 || target_y >= 32 {
+                break
+            }
+            let pixel = pixel_vec[x];
+            let is_set = vram[(target_x, target_y)];
+            if pixel && is_set {
+                collision = true;
+            }
+            vram.set((target_x, target_y), pixel ^ is_set);
+        }
+    }
+    collision
+}
+
+fn draw_framebuffer(vram: &mut Framebuffer, x_pos: usize, y_pos: usize) -> bool {
+    let mut collision = false;
+    for (y, byte) in SPRITE.iter().enumerate() {
+        let target_y = y + y_pos;
+        if target_y >= 32 {
+            break
+        }
+        collision |= vram.draw_sprite_row(x_pos, target_y, *byte);
+    }
+    collision
+}
+
+fn time<F: FnMut(usize)>(mut f: F) -> Duration {
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        f(i);
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, old: Duration, new: Duration) {
+    println!("{}", name);
+    println!("  bitmatrix:   {:>10.2?} ({:.1} ns/iter)", old, old.as_nanos() as f64 / ITERATIONS as f64);
+    println!("  framebuffer: {:>10.2?} ({:.1} ns/iter)", new, new.as_nanos() as f64 / ITERATIONS as f64);
+    println!("  speedup:     {:>10.1}x", old.as_secs_f64() / new.as_secs_f64());
+}
+
+fn main() {
+    let mut matrix = BitMatrix::new(64, 32);
+    let mut framebuffer = Framebuffer::new(64, 32);
+
+    // Positions walk the whole screen, including the clipped edges
+    let old = time(|i| {
+        black_box(draw_bitmatrix(&mut matrix, i % 64, (i / 64) % 32));
+    });
+    let new = time(|i| {
+        black_box(draw_framebuffer(&mut framebuffer, i % 64, (i / 64) % 32));
+    });
+    report("DXYN, 15 rows sprite", old, new);
+
+    let on = [0, 127, 0, 255];
+    let off = [9, 0, 0, 255];
+    let old = time(|_| {
+        let mut pixels = Vec::with_capacity(64 * 32 * 4);
+        for j in 0..32 {
+            for i in 0..64 {
+                pixels.extend_from_slice(if matrix[(i, j)] { &on } else { &off });
+            }
+        }
+        black_box(pixels);
+    });
+    let new = time(|_| {
+        black_box(framebuffer.to_rgba(on, off));
+    });
+    report("Full screen to RGBA", old, new);
+}
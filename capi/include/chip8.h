@@ -0,0 +1,158 @@
+#ifndef RUSTY_CHIP_8_H
+#define RUSTY_CHIP_8_H
+
+/* Generated by cbindgen from capi/src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define CHIP8_API_VERSION 1
+
+typedef enum Chip8Status {
+  CHIP8_STATUS_OK = 0,
+  CHIP8_STATUS_NULL_POINTER = 1,
+  CHIP8_STATUS_INVALID_ARGUMENT = 2,
+  CHIP8_STATUS_BUFFER_TOO_SMALL = 3,
+  CHIP8_STATUS_INVALID_STATE = 4,
+  CHIP8_STATUS_STACK_OVERFLOW = 16,
+  CHIP8_STATUS_STACK_UNDERFLOW = 17,
+  CHIP8_STATUS_OUT_OF_BOUNDS = 18,
+  CHIP8_STATUS_INVALID_INSTRUCTION = 19,
+} Chip8Status;
+
+typedef struct Chip8 Chip8;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+int chip8_api_version(void);
+
+struct Chip8 *chip8_new(void);
+
+/**
+ * The defaults of one of the interpreters, `variant` is any of the names
+ * the python module takes ("chip8", "vip", "chip48"...). Returns null
+ * when the name is unknown.
+ *
+ * # Safety
+ *
+ * `variant` has to be a nul terminated string (or null).
+ */
+struct Chip8 *chip8_new_with_variant(const char *variant);
+
+/**
+ * # Safety
+ *
+ * `chip8` has to come from `chip8_new` or `chip8_new_with_variant` (or be
+ * null) and not be used afterwards.
+ */
+void chip8_free(struct Chip8 *chip8);
+
+/**
+ * Starts over running the ROM, the machine stays the same.
+ *
+ * # Safety
+ *
+ * `chip8` has to be a live handle and `rom` has to point to `length` bytes.
+ */
+enum Chip8Status chip8_load_rom(struct Chip8 *chip8, const uint8_t *rom, size_t length);
+
+/**
+ * # Safety
+ *
+ * `chip8` has to be a live handle.
+ */
+enum Chip8Status chip8_reset(struct Chip8 *chip8);
+
+/**
+ * Runs a single instruction.
+ *
+ * # Safety
+ *
+ * `chip8` has to be a live handle.
+ */
+enum Chip8Status chip8_step(struct Chip8 *chip8);
+
+/**
+ * Runs 1/60 of a second worth of instructions and timer ticks.
+ *
+ * # Safety
+ *
+ * `chip8` has to be a live handle.
+ */
+enum Chip8Status chip8_run_frame(struct Chip8 *chip8);
+
+/**
+ * `key` is 0x0-0xF, seen by the machine on its next instruction.
+ *
+ * # Safety
+ *
+ * `chip8` has to be a live handle.
+ */
+enum Chip8Status chip8_set_key(struct Chip8 *chip8, uint8_t key, bool pressed);
+
+/**
+ * Address of the instruction the machine stopped at, or -1 when it didn't.
+ *
+ * # Safety
+ *
+ * `chip8` has to be a live handle.
+ */
+int chip8_fault_pc(const struct Chip8 *chip8);
+
+/**
+ * # Safety
+ *
+ * `chip8` has to be a live handle.
+ */
+bool chip8_sound_active(const struct Chip8 *chip8);
+
+/**
+ * One byte per pixel, 1 when lit, rows one after the other.
+ * `width` and `height` get the display size, `length` has to be at least
+ * width * height.
+ *
+ * # Safety
+ *
+ * `chip8` has to be a live handle, `pixels` has to point to `length`
+ * writable bytes, `width` and `height` can be null.
+ */
+enum Chip8Status chip8_get_framebuffer(const struct Chip8 *chip8,
+                                       uint8_t *pixels,
+                                       size_t length,
+                                       size_t *width,
+                                       size_t *height);
+
+/**
+ * Writes the machine state (JSON, not nul terminated) and its size on
+ * `written`. When the buffer is null or too small only the size to allocate
+ * is written, so it can be called once with no buffer to ask for it.
+ *
+ * # Safety
+ *
+ * `chip8` has to be a live handle, `buffer` has to point to `length`
+ * writable bytes (or be null) and `written` has to be valid.
+ */
+enum Chip8Status chip8_save_state(struct Chip8 *chip8,
+                                  uint8_t *buffer,
+                                  size_t length,
+                                  size_t *written);
+
+/**
+ * Goes back to what chip8_save_state wrote, the machine is rebuilt from it.
+ *
+ * # Safety
+ *
+ * `chip8` has to be a live handle and `state` has to point to `length` bytes.
+ */
+enum Chip8Status chip8_load_state(struct Chip8 *chip8, const uint8_t *state, size_t length);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUSTY_CHIP_8_H */
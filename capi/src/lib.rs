@@ -0,0 +1,308 @@
+/* C API, for embedding the emulator in C and C++ programs.
+ *
+ * A Chip8 handle wraps a whole machine, it comes from chip8_new
+ * (or chip8_new_with_variant) and goes back with chip8_free. Every call returns a Chip8Status,
+ * CHIP8_STATUS_OK or the reason it failed, the fault codes mean the
+ * machine stopped on a bad ROM and only a reset or a new ROM makes
+ * it run again.
+ */
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::slice;
+use std::time::Duration;
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::fault::Fault;
+use rusty_chip_8::keyboard::State;
+use rusty_chip_8::machine::{MachineConfig, Variant};
+use rusty_chip_8::savestate::SaveState;
+
+// Bumped whenever a function changes in a way old callers would break
+pub const CHIP8_API_VERSION: c_int = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chip8Status {
+    Ok = 0,
+    NullPointer = 1,
+    InvalidArgument = 2,
+    // The size needed is written back, call again with a bigger buffer
+    BufferTooSmall = 3,
+    InvalidState = 4,
+    // The machine stopped on one of these, see chip8_fault_pc
+    StackOverflow = 16,
+    StackUnderflow = 17,
+    OutOfBounds = 18,
+    InvalidInstruction = 19,
+}
+
+impl From<Fault> for Chip8Status {
+    fn from(fault: Fault) -> Self {
+        match fault {
+            Fault::StackOverflow { .. } => Chip8Status::StackOverflow,
+            Fault::StackUnderflow { .. } => Chip8Status::StackUnderflow,
+            Fault::OutOfBounds { .. } => Chip8Status::OutOfBounds,
+            Fault::InvalidInstruction { .. } => Chip8Status::InvalidInstruction,
+        }
+    }
+}
+
+// Opaque to C, only ever handled through a pointer
+pub struct Chip8 {
+    bus: Bus,
+}
+
+impl Chip8 {
+    fn status(&self) -> Chip8Status {
+        self.bus.fault().map_or(Chip8Status::Ok, Chip8Status::from)
+    }
+}
+
+// Turns the handle into a reference, or returns NULL_POINTER from the function
+macro_rules! handle {
+    ($chip8:expr) => {
+        match $chip8.as_mut() {
+            Some(chip8) => chip8,
+            None => return Chip8Status::NullPointer,
+        }
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_api_version() -> c_int {
+    CHIP8_API_VERSION
+}
+
+// A default machine with no ROM loaded, free it with chip8_free
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8 {
+    let chip8 = Chip8 { bus: Bus::with_rom(&[]) };
+    Box::into_raw(Box::new(chip8))
+}
+
+/// The defaults of one of the interpreters, `variant` is any of the names
+/// the python module takes ("chip8", "vip", "chip48"...). Returns null
+/// when the name is unknown.
+///
+/// # Safety
+///
+/// `variant` has to be a nul terminated string (or null).
+#[no_mangle]
+pub unsafe extern "C" fn chip8_new_with_variant(variant: *const c_char) -> *mut Chip8 {
+    if variant.is_null() {
+        return std::ptr::null_mut();
+    }
+    let variant = match CStr::from_ptr(variant).to_str() {
+        Ok(variant) => serde_json::Value::from(variant),
+        Err(_) => return std::ptr::null_mut(),
+    };
+    match serde_json::from_value::<Variant>(variant) {
+        Ok(variant) => {
+            let bus = Bus::with_config(&[], MachineConfig::new(variant)).expect("The variant defaults are valid");
+            Box::into_raw(Box::new(Chip8 { bus }))
+        },
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `chip8` has to come from `chip8_new` or `chip8_new_with_variant` (or be
+/// null) and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// Starts over running the ROM, the machine stays the same.
+///
+/// # Safety
+///
+/// `chip8` has to be a live handle and `rom` has to point to `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, rom: *const u8, length: usize) -> Chip8Status {
+    let chip8 = handle!(chip8);
+    if rom.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    chip8.bus.load_rom(slice::from_raw_parts(rom, length));
+    Chip8Status::Ok
+}
+
+/// # Safety
+///
+/// `chip8` has to be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_reset(chip8: *mut Chip8) -> Chip8Status {
+    let chip8 = handle!(chip8);
+    chip8.bus.reset();
+    Chip8Status::Ok
+}
+
+/// Runs a single instruction.
+///
+/// # Safety
+///
+/// `chip8` has to be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> Chip8Status {
+    let chip8 = handle!(chip8);
+    chip8.bus.step();
+    chip8.status()
+}
+
+/// Runs 1/60 of a second worth of instructions and timer ticks.
+///
+/// # Safety
+///
+/// `chip8` has to be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8) -> Chip8Status {
+    let chip8 = handle!(chip8);
+    chip8.bus.run_for(Duration::from_secs_f64(1.0 / 60.0));
+    chip8.status()
+}
+
+/// `key` is 0x0-0xF, seen by the machine on its next instruction.
+///
+/// # Safety
+///
+/// `chip8` has to be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: bool) -> Chip8Status {
+    let chip8 = handle!(chip8);
+    if key > 0xF {
+        return Chip8Status::InvalidArgument;
+    }
+    let state = if pressed { State::PRESSED } else { State::RELEASED };
+    let time = chip8.bus.time();
+    chip8.bus.queue_key(key, state, time);
+    Chip8Status::Ok
+}
+
+/// Address of the instruction the machine stopped at, or -1 when it didn't.
+///
+/// # Safety
+///
+/// `chip8` has to be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_fault_pc(chip8: *const Chip8) -> c_int {
+    let fault = match chip8.as_ref().and_then(|chip8| chip8.bus.fault()) {
+        Some(fault) => fault,
+        None => return -1,
+    };
+    let pc = match fault {
+        Fault::StackOverflow { pc, .. } | Fault::StackUnderflow { pc } | Fault::OutOfBounds { pc, .. }
+            | Fault::InvalidInstruction { pc, .. } => pc,
+    };
+    c_int::from(pc)
+}
+
+/// # Safety
+///
+/// `chip8` has to be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_active(chip8: *const Chip8) -> bool {
+    chip8.as_ref().is_some_and(|chip8| chip8.bus.state.sound > 0)
+}
+
+/// One byte per pixel, 1 when lit, rows one after the other.
+/// `width` and `height` get the display size, `length` has to be at least
+/// width * height.
+///
+/// # Safety
+///
+/// `chip8` has to be a live handle, `pixels` has to point to `length`
+/// writable bytes, `width` and `height` can be null.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_framebuffer(
+    chip8: *const Chip8,
+    pixels: *mut u8,
+    length: usize,
+    width: *mut usize,
+    height: *mut usize,
+) -> Chip8Status {
+    let chip8 = match chip8.as_ref() {
+        Some(chip8) => chip8,
+        None => return Chip8Status::NullPointer,
+    };
+    let vram = chip8.bus.get_vram();
+    if let Some(width) = width.as_mut() {
+        *width = vram.width();
+    }
+    if let Some(height) = height.as_mut() {
+        *height = vram.height();
+    }
+    if pixels.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    if length < vram.width() * vram.height() {
+        return Chip8Status::BufferTooSmall;
+    }
+
+    let pixels = slice::from_raw_parts_mut(pixels, length);
+    for y in 0..vram.height() {
+        for x in 0..vram.width() {
+            pixels[y * vram.width() + x] = vram.get(x, y) as u8;
+        }
+    }
+    Chip8Status::Ok
+}
+
+/// Writes the machine state (JSON, not nul terminated) and its size on
+/// `written`. When the buffer is null or too small only the size to allocate
+/// is written, so it can be called once with no buffer to ask for it.
+///
+/// # Safety
+///
+/// `chip8` has to be a live handle, `buffer` has to point to `length`
+/// writable bytes (or be null) and `written` has to be valid.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(
+    chip8: *mut Chip8,
+    buffer: *mut u8,
+    length: usize,
+    written: *mut usize,
+) -> Chip8Status {
+    let chip8 = handle!(chip8);
+    let written = match written.as_mut() {
+        Some(written) => written,
+        None => return Chip8Status::NullPointer,
+    };
+    let json = match chip8.bus.save_state().to_json() {
+        Ok(json) => json,
+        Err(_) => return Chip8Status::InvalidState,
+    };
+
+    if buffer.is_null() || length < json.len() {
+        *written = json.len();
+        return Chip8Status::BufferTooSmall;
+    }
+    *written = json.len();
+    slice::from_raw_parts_mut(buffer, json.len()).copy_from_slice(json.as_bytes());
+    Chip8Status::Ok
+}
+
+/// Goes back to what chip8_save_state wrote, the machine is rebuilt from it.
+///
+/// # Safety
+///
+/// `chip8` has to be a live handle and `state` has to point to `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(chip8: *mut Chip8, state: *const u8, length: usize) -> Chip8Status {
+    let chip8 = handle!(chip8);
+    if state.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    let state = std::str::from_utf8(slice::from_raw_parts(state, length)).ok()
+        .and_then(|json| SaveState::from_json(json).ok());
+    match state {
+        Some(state) => match chip8.bus.load_state(&state) {
+            Ok(()) => chip8.status(),
+            Err(_) => Chip8Status::InvalidState,
+        },
+        None => Chip8Status::InvalidState,
+    }
+}
@@ -0,0 +1,85 @@
+/* Runs a couple of ROMs through the C API, exits non zero on the first failure */
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "chip8.h"
+
+#define CHECK(condition) \
+    if (!(condition)) { \
+        fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #condition); \
+        return 1; \
+    }
+
+int main(void) {
+    /* 200: F00A V0 = key, 202: F029 I = glyph of V0, 204: D005 draw it, 206: 1206 loop */
+    const uint8_t draw[] = { 0xF0, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06 };
+    /* 200: 00EE return with nothing on the stack */
+    const uint8_t underflow[] = { 0x00, 0xEE };
+    /* 200: 2200 calls itself until the stack is full */
+    const uint8_t recurse[] = { 0x22, 0x00 };
+    /* V0 = 1, then 5XY1 which is not an instruction */
+    const uint8_t invalid[] = { 0x60, 0x01, 0x50, 0x01 };
+    uint8_t pixels[64 * 32];
+    size_t width = 0, height = 0, size = 0;
+
+    CHECK(chip8_api_version() == CHIP8_API_VERSION);
+    Chip8 *chip8 = chip8_new();
+    CHECK(chip8 != NULL);
+    CHECK(chip8_load_rom(chip8, draw, sizeof(draw)) == CHIP8_STATUS_OK);
+    CHECK(chip8_run_frame(chip8) == CHIP8_STATUS_OK);
+    CHECK(chip8_set_key(chip8, 0x10, true) == CHIP8_STATUS_INVALID_ARGUMENT);
+
+    /* Key 0 draws the 0 glyph, its first row is 4 pixels wide */
+    CHECK(chip8_set_key(chip8, 0x0, true) == CHIP8_STATUS_OK);
+    CHECK(chip8_run_frame(chip8) == CHIP8_STATUS_OK);
+    CHECK(chip8_get_framebuffer(chip8, pixels, 16, &width, &height) == CHIP8_STATUS_BUFFER_TOO_SMALL);
+    CHECK(chip8_get_framebuffer(chip8, pixels, sizeof(pixels), &width, &height) == CHIP8_STATUS_OK);
+    CHECK(width == 64 && height == 32);
+    CHECK(pixels[0] == 1 && pixels[3] == 1 && pixels[4] == 0);
+
+    /* Asking for the size first, then saving for real */
+    CHECK(chip8_save_state(chip8, NULL, 0, &size) == CHIP8_STATUS_BUFFER_TOO_SMALL);
+    uint8_t *state = malloc(size);
+    CHECK(chip8_save_state(chip8, state, size, &size) == CHIP8_STATUS_OK);
+
+    CHECK(chip8_reset(chip8) == CHIP8_STATUS_OK);
+    CHECK(chip8_get_framebuffer(chip8, pixels, sizeof(pixels), NULL, NULL) == CHIP8_STATUS_OK);
+    CHECK(pixels[0] == 0);
+    CHECK(chip8_load_state(chip8, state, size) == CHIP8_STATUS_OK);
+    CHECK(chip8_get_framebuffer(chip8, pixels, sizeof(pixels), NULL, NULL) == CHIP8_STATUS_OK);
+    CHECK(pixels[0] == 1);
+    CHECK(chip8_load_state(chip8, state, size / 2) == CHIP8_STATUS_INVALID_STATE);
+    free(state);
+
+    /* Faults come back as status codes, and stay until the next ROM */
+    CHECK(chip8_fault_pc(chip8) == -1);
+    CHECK(chip8_load_rom(chip8, underflow, sizeof(underflow)) == CHIP8_STATUS_OK);
+    CHECK(chip8_step(chip8) == CHIP8_STATUS_STACK_UNDERFLOW);
+    CHECK(chip8_run_frame(chip8) == CHIP8_STATUS_STACK_UNDERFLOW);
+    CHECK(chip8_fault_pc(chip8) == 0x200);
+    CHECK(chip8_load_rom(chip8, invalid, sizeof(invalid)) == CHIP8_STATUS_OK);
+    CHECK(chip8_run_frame(chip8) == CHIP8_STATUS_INVALID_INSTRUCTION);
+    CHECK(chip8_fault_pc(chip8) == 0x202);
+
+    CHECK(chip8_step(NULL) == CHIP8_STATUS_NULL_POINTER);
+    chip8_free(chip8);
+
+    /* The VIP only has room for 12 calls, the default machine for 16 */
+    CHECK(chip8_new_with_variant("nope") == NULL);
+    CHECK(chip8_new_with_variant(NULL) == NULL);
+    Chip8 *vip = chip8_new_with_variant("vip");
+    chip8 = chip8_new();
+    CHECK(vip != NULL);
+    CHECK(chip8_load_rom(vip, recurse, sizeof(recurse)) == CHIP8_STATUS_OK);
+    CHECK(chip8_load_rom(chip8, recurse, sizeof(recurse)) == CHIP8_STATUS_OK);
+    for (int call = 0; call < 12; call++) {
+        CHECK(chip8_step(vip) == CHIP8_STATUS_OK);
+        CHECK(chip8_step(chip8) == CHIP8_STATUS_OK);
+    }
+    CHECK(chip8_step(vip) == CHIP8_STATUS_STACK_OVERFLOW);
+    CHECK(chip8_step(chip8) == CHIP8_STATUS_OK);
+    chip8_free(vip);
+    chip8_free(chip8);
+    puts("ok");
+    return 0;
+}
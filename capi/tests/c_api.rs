@@ -0,0 +1,41 @@
+/* Builds tests/c/test.c against the static library and include/chip8.h,
+ * the same way a C program embedding the emulator would
+ */
+use std::fs;
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn test_c_program() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // The test binary sits in target/<profile>/deps, the library one level up
+    let mut lib_dir = env::current_exe().unwrap();
+    lib_dir.pop();
+    lib_dir.pop();
+    let out = env::temp_dir().join(format!("chip8_capi_test_{}", std::process::id()));
+
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(crate_dir.join("tests").join("c").join("test.c"))
+        .arg("-I").arg(crate_dir.join("include"))
+        .arg(lib_dir.join("librusty_chip_8_capi.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&out)
+        .status()
+        .expect("Could not run the C compiler");
+    assert!(status.success(), "C test program did not build");
+
+    let output = Command::new(&out).output().unwrap();
+    let _ = fs::remove_file(&out);
+    assert!(output.status.success(), "C test program failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "ok");
+}
+
+// The build generates the header into OUT_DIR, the checked in one has to match it
+#[test]
+fn test_header_is_up_to_date() {
+    let generated = PathBuf::from(env!("OUT_DIR")).join("chip8.h");
+    let checked_in = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("include").join("chip8.h");
+    assert!(fs::read_to_string(&generated).unwrap() == fs::read_to_string(&checked_in).unwrap(),
+        "include/chip8.h is out of date, copy {} over it", generated.display());
+}
@@ -0,0 +1,13 @@
+#!/bin/sh
+# Replaces the bundled program list with the latest upstream CHIP-8 database,
+# rebuild afterwards so it gets included in the binary.
+# platforms.json is maintained here (the variants depend on it), so it is left alone
+set -e
+
+UPSTREAM=https://raw.githubusercontent.com/chip-8/chip-8-database/master/database
+cd "$(dirname "$0")"
+
+for file in programs.json sha1-hashes.json; do
+    curl -fsSL "$UPSTREAM/$file" -o "$file.tmp"
+    mv "$file.tmp" "$file"
+done
@@ -0,0 +1,522 @@
+/* libretro core, so the emulator can run inside RetroArch
+ * and any other libretro frontend.
+ *
+ * The frontend owns the main loop and calls retro_run once per
+ * 60hz frame, the core answers with a XRGB8888 frame, a frame
+ * worth of audio and reads the joypad into the 16 keys.
+ * There is only ever one machine, kept on a global.
+ */
+use std::ffi::{c_void, CStr};
+use std::os::raw::{c_char, c_uint};
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::keyboard::State;
+use rusty_chip_8::machine::{ConfigOverrides, MachineConfig};
+use rusty_chip_8::romdb::RomDatabase;
+use rusty_chip_8::savestate::SaveState;
+use rusty_chip_8::timing::ClockRate;
+
+// Values from libretro.h
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+const RETRO_REGION_NTSC: c_uint = 0;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44100.0;
+const BEEP_HZ: f64 = 440.0;
+const BEEP_VOLUME: i16 = 0x1000;
+
+// XRGB8888 is a native endian u32, so on little endian the bytes are B, G, R, X
+const PIXEL_ON: [u8; 4] = [0xFF, 0xFF, 0xFF, 0x00];
+const PIXEL_OFF: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
+
+/* RetroPad button ids (B, Y, Select, Start, Up, Down, Left, Right,
+ * A, X, L, R, L2, R2, L3, R3) to keys, the d-pad and A/B land on
+ * 5 8 7 9 and 6 4, which is what most games use for moving around
+ */
+pub const JOYPAD_KEYS: [u8; 16] = [
+    0x4, 0x1, 0xB, 0x0, 0x5, 0x8, 0x7, 0x9,
+    0x6, 0x2, 0x3, 0xC, 0xA, 0xD, 0xE, 0xF,
+];
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct RetroVariable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+pub type RetroEnvironment = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefresh = extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSample = extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatch = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPoll = extern "C" fn();
+pub type RetroInputState = extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+/* Core options, "auto" keeps whatever the ROM database (or the
+ * default machine) says, keys and values must be nul terminated
+ */
+const OPTIONS: [(&[u8], &[u8]); 3] = [
+    (b"rusty_chip_8_display_wait\0",
+     b"Display wait quirk; auto|enabled|disabled\0"),
+    (b"rusty_chip_8_key_wait_release\0",
+     b"Key wait release quirk; auto|enabled|disabled\0"),
+    (b"rusty_chip_8_speed\0",
+     b"Instructions per frame; auto|5|10|15|20|30|50|100|200|500|1000\0"),
+];
+
+struct Core {
+    bus: Bus,
+    // The machine before the core options, what "auto" goes back to
+    config: MachineConfig,
+    // Save states carry the ROM too
+    rom_size: usize,
+    frame: Vec<u8>,
+    audio: Vec<i16>,
+    // Where the square wave is, in cycles, so it doesn't click between frames
+    phase: f64,
+    buttons: [bool; 16],
+}
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn core() -> MutexGuard<'static, Option<Core>> {
+    CORE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => environment(cmd, data),
+        None => false,
+    }
+}
+
+// The value the frontend has for an option, None if it has none
+fn option(key: &[u8]) -> Option<String> {
+    let mut variable = RetroVariable { key: key.as_ptr() as *const c_char, value: ptr::null() };
+    if !environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut RetroVariable as *mut c_void)
+        || variable.value.is_null() {
+        return None;
+    }
+    let value = unsafe { CStr::from_ptr(variable.value) };
+    Some(value.to_string_lossy().into_owned())
+}
+
+fn quirk_option(key: &[u8], auto: bool) -> bool {
+    match option(key).as_deref() {
+        Some("enabled") => true,
+        Some("disabled") => false,
+        _ => auto,
+    }
+}
+
+impl Core {
+    fn new(rom: &[u8]) -> Core {
+        let rom_info = RomDatabase::bundled().lookup_rom(rom);
+        let config = MachineConfig::resolve(None, None, rom_info.as_ref(), &ConfigOverrides::default());
+        // A database entry that can't be built falls back to the plain machine
+        let (bus, config) = match Bus::with_config(rom, config.clone()) {
+            Ok(bus) => (bus, config),
+            Err(_) => (Bus::with_rom(rom), MachineConfig::default()),
+        };
+
+        let mut core = Core {
+            bus,
+            config,
+            rom_size: rom.len(),
+            frame: Vec::new(),
+            audio: Vec::new(),
+            phase: 0.0,
+            buttons: [false; 16],
+        };
+        core.apply_options();
+        core
+    }
+
+    // Takes effect right away, without restarting the machine
+    fn apply_options(&mut self) {
+        let quirks = &mut self.bus.state.quirks;
+        quirks.display_wait = quirk_option(OPTIONS[0].0, self.config.quirks.display_wait);
+        quirks.key_wait_release = quirk_option(OPTIONS[1].0, self.config.quirks.key_wait_release);
+
+        let rate = match option(OPTIONS[2].0).and_then(|value| value.parse().ok()) {
+            Some(ipf) => ClockRate::InstructionsPerFrame(ipf),
+            None => self.config.rate,
+        };
+        self.bus.timing.set_rate(rate);
+    }
+
+    fn poll_input(&mut self) {
+        let callbacks = callbacks();
+        if let Some(input_poll) = callbacks.input_poll {
+            input_poll();
+        }
+        let input_state = match callbacks.input_state {
+            Some(input_state) => input_state,
+            None => return,
+        };
+
+        for (id, key) in JOYPAD_KEYS.iter().enumerate() {
+            let pressed = input_state(0, RETRO_DEVICE_JOYPAD, 0, id as c_uint) != 0;
+            if pressed != self.buttons[id] {
+                self.buttons[id] = pressed;
+                let state = if pressed { State::PRESSED } else { State::RELEASED };
+                let time = self.bus.time();
+                self.bus.queue_key(*key, state, time);
+            }
+        }
+    }
+
+    fn render(&mut self) {
+        let vram = self.bus.get_vram();
+        let (width, height) = (vram.width(), vram.height());
+        self.frame.resize(width * height * 4, 0);
+        vram.write_rgba(0..height, PIXEL_ON, PIXEL_OFF, &mut self.frame);
+
+        if let Some(video_refresh) = callbacks().video_refresh {
+            video_refresh(self.frame.as_ptr() as *const c_void, width as c_uint, height as c_uint, width * 4);
+        }
+    }
+
+    // A square wave while the sound timer is running, silence otherwise
+    fn play_audio(&mut self) {
+        let frames = (SAMPLE_RATE / FPS) as usize;
+        self.audio.clear();
+        for _ in 0..frames {
+            let sample = if self.bus.state.sound == 0 {
+                0
+            } else if self.phase < 0.5 {
+                BEEP_VOLUME
+            } else {
+                -BEEP_VOLUME
+            };
+            // Stereo, left and right
+            self.audio.push(sample);
+            self.audio.push(sample);
+            self.phase = (self.phase + BEEP_HZ / SAMPLE_RATE).fract();
+        }
+
+        if let Some(audio_sample_batch) = callbacks().audio_sample_batch {
+            audio_sample_batch(self.audio.as_ptr(), frames);
+        }
+    }
+
+    fn run_frame(&mut self) {
+        let mut updated = false;
+        environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void);
+        if updated {
+            self.apply_options();
+        }
+
+        self.poll_input();
+        self.bus.run_for(Duration::from_secs_f64(1.0 / FPS));
+        self.render();
+        self.play_audio();
+    }
+
+    /* Save states are the JSON from the library, behind a 4 byte
+     * length, the frontend wants to know the size up front so this
+     * is the biggest it could get for this machine
+     */
+    fn state_size(&self) -> usize {
+        let vram = self.bus.get_vram();
+        let bytes = self.bus.memory.size() + self.rom_size + vram.bitmap_stride() * vram.height();
+        // Every byte is at most "255," and the rest is small
+        4 + bytes * 4 + self.bus.config().stack_depth * 6 + 0x10000
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: RetroEnvironment) {
+    CALLBACKS.lock().unwrap_or_else(|err| err.into_inner()).environment = Some(callback);
+
+    let mut variables: Vec<RetroVariable> = OPTIONS.iter()
+        .map(|(key, value)| RetroVariable {
+            key: key.as_ptr() as *const c_char,
+            value: value.as_ptr() as *const c_char,
+        })
+        .collect();
+    // The list ends on an empty entry
+    variables.push(RetroVariable { key: ptr::null(), value: ptr::null() });
+    callback(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: RetroVideoRefresh) {
+    CALLBACKS.lock().unwrap_or_else(|err| err.into_inner()).video_refresh = Some(callback);
+}
+
+// Audio always goes out in batches
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: RetroAudioSample) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: RetroAudioSampleBatch) {
+    CALLBACKS.lock().unwrap_or_else(|err| err.into_inner()).audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: RetroInputPoll) {
+    CALLBACKS.lock().unwrap_or_else(|err| err.into_inner()).input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: RetroInputState) {
+    CALLBACKS.lock().unwrap_or_else(|err| err.into_inner()).input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *core() = None;
+}
+
+/// # Safety
+///
+/// `info` has to point to a `retro_system_info` the core can write to.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    if info.is_null() {
+        return;
+    }
+    *info = RetroSystemInfo {
+        library_name: b"Rusty Chip-8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8|sc8|xo8\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` has to point to a `retro_system_av_info` the core can write to.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    if info.is_null() {
+        return;
+    }
+    let (width, height) = match core().as_ref() {
+        Some(core) => (core.bus.get_vram().width(), core.bus.get_vram().height()),
+        None => {
+            let config = MachineConfig::default();
+            (config.display_width, config.display_height)
+        },
+    };
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: width as c_uint,
+            base_height: height as c_uint,
+            max_width: width as c_uint,
+            max_height: height as c_uint,
+            aspect_ratio: width as f32 / height as f32,
+        },
+        timing: RetroSystemTiming { fps: FPS, sample_rate: SAMPLE_RATE },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = core().as_mut() {
+        core.bus.reset();
+        core.apply_options();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    if let Some(core) = core().as_mut() {
+        core.run_frame();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    core().as_ref().map_or(0, |core| core.state_size())
+}
+
+/// # Safety
+///
+/// `data` has to point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let mut core = core();
+    let core = match core.as_mut() {
+        Some(core) => core,
+        None => return false,
+    };
+    let json = match core.bus.save_state().to_json() {
+        Ok(json) => json,
+        Err(_) => return false,
+    };
+    if data.is_null() || 4 + json.len() > size {
+        return false;
+    }
+
+    let out = slice::from_raw_parts_mut(data as *mut u8, size);
+    out[..4].copy_from_slice(&(json.len() as u32).to_le_bytes());
+    out[4..4 + json.len()].copy_from_slice(json.as_bytes());
+    for byte in &mut out[4 + json.len()..] {
+        *byte = 0;
+    }
+    true
+}
+
+/// # Safety
+///
+/// `data` has to point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() || size < 4 {
+        return false;
+    }
+    let bytes = slice::from_raw_parts(data as *const u8, size);
+    let length = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let json = match bytes[4..].get(..length).and_then(|json| std::str::from_utf8(json).ok()) {
+        Some(json) => json,
+        None => return false,
+    };
+    let state = match SaveState::from_json(json) {
+        Ok(state) => state,
+        Err(_) => return false,
+    };
+
+    match core().as_mut() {
+        Some(core) => core.bus.load_state(&state).is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+///
+/// `game` has to be null or point to a valid `retro_game_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size);
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        return false;
+    }
+    *core() = Some(Core::new(rom));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *core() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+// The buffer stays put across resets and save states, the frontend may hold on to it
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match core().as_mut() {
+        Some(core) if id == RETRO_MEMORY_SYSTEM_RAM => core.bus.memory.bytes_mut().as_mut_ptr() as *mut c_void,
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match core().as_ref() {
+        Some(core) if id == RETRO_MEMORY_SYSTEM_RAM => core.bus.memory.size(),
+        _ => 0,
+    }
+}
@@ -0,0 +1,223 @@
+/* A tiny libretro frontend, loads the built core the same
+ * way RetroArch would and drives it without a window
+ */
+use std::env;
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_uint;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use libloading::{Library, Symbol};
+use rusty_chip_8_libretro::{RetroGameInfo, RetroSystemAvInfo, RetroSystemInfo, JOYPAD_KEYS};
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD_UP: usize = 4;
+
+struct Host {
+    pixel_format: Option<c_uint>,
+    variables: bool,
+    frame: Vec<u32>,
+    frame_size: (c_uint, c_uint),
+    audio_frames: usize,
+    loud_samples: usize,
+    buttons: [bool; 16],
+}
+
+static HOST: Mutex<Host> = Mutex::new(Host {
+    pixel_format: None,
+    variables: false,
+    frame: Vec::new(),
+    frame_size: (0, 0),
+    audio_frames: 0,
+    loud_samples: 0,
+    buttons: [false; 16],
+});
+
+extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    let mut host = HOST.lock().unwrap();
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+            host.pixel_format = Some(unsafe { *(data as *const c_uint) });
+            true
+        },
+        RETRO_ENVIRONMENT_SET_VARIABLES => {
+            host.variables = true;
+            true
+        },
+        // No option values, the core keeps its defaults
+        _ => false,
+    }
+}
+
+extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let mut host = HOST.lock().unwrap();
+    host.frame_size = (width, height);
+    host.frame.clear();
+    for y in 0..height as usize {
+        let row = unsafe { (data as *const u8).add(y * pitch) as *const u32 };
+        for x in 0..width as usize {
+            let pixel = unsafe { *row.add(x) };
+            host.frame.push(pixel);
+        }
+    }
+}
+
+extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = unsafe { std::slice::from_raw_parts(data, frames * 2) };
+    let mut host = HOST.lock().unwrap();
+    host.audio_frames = frames;
+    host.loud_samples = samples.iter().filter(|sample| **sample != 0).count();
+    frames
+}
+
+extern "C" fn input_poll() {}
+
+extern "C" fn input_state(port: c_uint, _device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    let host = HOST.lock().unwrap();
+    (port == 0 && host.buttons[id as usize % 16]) as i16
+}
+
+// The test binary sits in target/<profile>/deps, the core one level up
+fn core_path() -> PathBuf {
+    let mut path = env::current_exe().unwrap();
+    path.pop();
+    path.pop();
+    path.push(format!("{}rusty_chip_8_libretro{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX));
+    path
+}
+
+fn set_button(id: usize, pressed: bool) {
+    HOST.lock().unwrap().buttons[id] = pressed;
+}
+
+#[test]
+fn test_core_runs_headless() {
+    /*
+     * 200: 6100 V1 = 0
+     * 202: 6200 V2 = 0
+     * 204: 6310 V3 = 0x10
+     * 206: F318 sound = V3
+     * 208: F00A V0 = key
+     * 20A: F029 I = glyph of V0
+     * 20C: D125 draw it at (V1, V2)
+     * 20E: 120E loop forever
+     */
+    let rom: [u8; 16] = [
+        0x61, 0x00, 0x62, 0x00, 0x63, 0x10, 0xF3, 0x18,
+        0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x25, 0x12, 0x0E,
+    ];
+
+    unsafe {
+        let core = Library::new(core_path()).expect("Could not load the core, is it built?");
+        let api_version: Symbol<extern "C" fn() -> c_uint> = core.get(b"retro_api_version").unwrap();
+        assert_eq!(api_version(), 1);
+
+        let set_environment: Symbol<extern "C" fn(extern "C" fn(c_uint, *mut c_void) -> bool)> =
+            core.get(b"retro_set_environment").unwrap();
+        let set_video_refresh: Symbol<extern "C" fn(extern "C" fn(*const c_void, c_uint, c_uint, usize))> =
+            core.get(b"retro_set_video_refresh").unwrap();
+        let set_audio_sample: Symbol<extern "C" fn(extern "C" fn(i16, i16))> =
+            core.get(b"retro_set_audio_sample").unwrap();
+        let set_audio_sample_batch: Symbol<extern "C" fn(extern "C" fn(*const i16, usize) -> usize)> =
+            core.get(b"retro_set_audio_sample_batch").unwrap();
+        let set_input_poll: Symbol<extern "C" fn(extern "C" fn())> = core.get(b"retro_set_input_poll").unwrap();
+        let set_input_state: Symbol<extern "C" fn(extern "C" fn(c_uint, c_uint, c_uint, c_uint) -> i16)> =
+            core.get(b"retro_set_input_state").unwrap();
+        let init: Symbol<extern "C" fn()> = core.get(b"retro_init").unwrap();
+        let deinit: Symbol<extern "C" fn()> = core.get(b"retro_deinit").unwrap();
+        let get_system_info: Symbol<unsafe extern "C" fn(*mut RetroSystemInfo)> =
+            core.get(b"retro_get_system_info").unwrap();
+        let get_system_av_info: Symbol<unsafe extern "C" fn(*mut RetroSystemAvInfo)> =
+            core.get(b"retro_get_system_av_info").unwrap();
+        let load_game: Symbol<unsafe extern "C" fn(*const RetroGameInfo) -> bool> =
+            core.get(b"retro_load_game").unwrap();
+        let run: Symbol<extern "C" fn()> = core.get(b"retro_run").unwrap();
+        let reset: Symbol<extern "C" fn()> = core.get(b"retro_reset").unwrap();
+        let serialize_size: Symbol<extern "C" fn() -> usize> = core.get(b"retro_serialize_size").unwrap();
+        let serialize: Symbol<unsafe extern "C" fn(*mut c_void, usize) -> bool> =
+            core.get(b"retro_serialize").unwrap();
+        let unserialize: Symbol<unsafe extern "C" fn(*const c_void, usize) -> bool> =
+            core.get(b"retro_unserialize").unwrap();
+        let get_memory_data: Symbol<extern "C" fn(c_uint) -> *mut c_void> =
+            core.get(b"retro_get_memory_data").unwrap();
+        let get_memory_size: Symbol<extern "C" fn(c_uint) -> usize> = core.get(b"retro_get_memory_size").unwrap();
+        let unload_game: Symbol<extern "C" fn()> = core.get(b"retro_unload_game").unwrap();
+
+        set_environment(environment);
+        set_video_refresh(video_refresh);
+        set_audio_sample(audio_sample);
+        set_audio_sample_batch(audio_sample_batch);
+        set_input_poll(input_poll);
+        set_input_state(input_state);
+        init();
+        assert!(HOST.lock().unwrap().variables);
+
+        let mut system_info = std::mem::zeroed::<RetroSystemInfo>();
+        get_system_info(&mut system_info);
+        assert!(!system_info.need_fullpath);
+        assert_eq!(CStr::from_ptr(system_info.valid_extensions).to_str().unwrap(), "ch8|c8|sc8|xo8");
+
+        let game = RetroGameInfo {
+            path: std::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(load_game(&game));
+        assert_eq!(HOST.lock().unwrap().pixel_format, Some(RETRO_PIXEL_FORMAT_XRGB8888));
+
+        let mut av_info = std::mem::zeroed::<RetroSystemAvInfo>();
+        get_system_av_info(&mut av_info);
+        assert_eq!((av_info.geometry.base_width, av_info.geometry.base_height), (64, 32));
+        assert_eq!(av_info.timing.fps, 60.0);
+
+        // The RAM is the whole machine memory, ROM at 0x200
+        assert_eq!(get_memory_size(2), 0x1000);
+        let ram = get_memory_data(2) as *const u8;
+        assert_eq!(*ram.add(0x200), 0x61);
+
+        // Waiting on a key with the sound timer running
+        run();
+        {
+            let host = HOST.lock().unwrap();
+            assert_eq!(host.frame_size, (64, 32));
+            assert!(host.frame.iter().all(|pixel| *pixel == 0));
+            assert_eq!(host.audio_frames, 735);
+            assert!(host.loud_samples > 0);
+        }
+
+        // Up is key 5, its glyph starts with a full row
+        assert_eq!(JOYPAD_KEYS[RETRO_DEVICE_ID_JOYPAD_UP], 0x5);
+        set_button(RETRO_DEVICE_ID_JOYPAD_UP, true);
+        run();
+        set_button(RETRO_DEVICE_ID_JOYPAD_UP, false);
+        run();
+        {
+            let host = HOST.lock().unwrap();
+            assert_eq!(&host.frame[..5], &[0x00FF_FFFF, 0x00FF_FFFF, 0x00FF_FFFF, 0x00FF_FFFF, 0]);
+        }
+
+        let size = serialize_size();
+        let mut state = vec![0u8; size];
+        assert!(serialize(state.as_mut_ptr() as *mut c_void, size));
+        let drawn = HOST.lock().unwrap().frame.clone();
+
+        // Back to the blank screen, then back again to the drawn one
+        reset();
+        run();
+        assert!(HOST.lock().unwrap().frame.iter().all(|pixel| *pixel == 0));
+        assert!(unserialize(state.as_ptr() as *const c_void, size));
+        run();
+        assert_eq!(HOST.lock().unwrap().frame, drawn);
+
+        // Resets and save states keep the RAM where it was
+        assert_eq!(get_memory_data(2) as *const u8, ram);
+
+        unload_game();
+        deinit();
+    }
+}
@@ -0,0 +1,163 @@
+/* Python bindings, for scripting the emulator without the window.
+ *
+ *   import rusty_chip_8
+ *
+ *   chip8 = rusty_chip_8.Chip8(open("pong.ch8", "rb").read(), variant="vip", seed=1)
+ *   chip8.run_frames(60)
+ *   chip8.vram()        # numpy uint8 array, height x width, 1 when lit
+ *   chip8.registers()   # {"v": [...], "i": ..., "pc": ..., "sp": ...}
+ *
+ * Keys pressed and released are seen on the next instruction.
+ */
+use std::time::Duration;
+
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::keyboard::State;
+use rusty_chip_8::machine::{MachineConfig, Variant};
+use rusty_chip_8::savestate::SaveState;
+
+fn value_error<E: ToString>(err: E) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+#[pyclass(name = "Chip8", module = "rusty_chip_8")]
+pub struct Chip8 {
+    bus: Bus,
+}
+
+#[pymethods]
+impl Chip8 {
+    // variant is any of the names --variant takes, seed makes CXNN repeat between runs
+    #[new]
+    #[pyo3(signature = (rom, variant = "chip8", seed = None))]
+    fn new(rom: &[u8], variant: &str, seed: Option<u32>) -> PyResult<Self> {
+        let variant: Variant = serde_json::from_value(serde_json::Value::from(variant))
+            .map_err(|_| value_error(format!("Unknown variant {}", variant)))?;
+        let mut config = MachineConfig::new(variant);
+        config.seed = seed;
+        Ok(Chip8 { bus: Bus::with_config(rom, config).map_err(value_error)? })
+    }
+
+    // Starts over with another ROM on the same machine
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.bus.load_rom(rom);
+    }
+
+    fn reset(&mut self) {
+        self.bus.reset();
+    }
+
+    #[pyo3(signature = (count = 1))]
+    fn step(&mut self, count: usize) {
+        for _ in 0..count {
+            self.bus.step();
+        }
+    }
+
+    // Each frame is 1/60 of a second worth of instructions and timer ticks
+    #[pyo3(signature = (count = 1))]
+    fn run_frames(&mut self, count: usize) {
+        for _ in 0..count {
+            self.bus.run_for(Duration::from_secs_f64(1.0 / 60.0));
+        }
+    }
+
+    fn vram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<u8>> {
+        let vram = self.bus.get_vram();
+        Array2::from_shape_fn((vram.height(), vram.width()), |(y, x)| vram.get(x, y) as u8)
+            .into_pyarray(py)
+    }
+
+    fn registers<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let registers = self.bus.registers();
+        let dict = PyDict::new(py);
+        // A list of ints, a Vec<u8> would turn into bytes
+        dict.set_item("v", PyList::new(py, registers.v)?)?;
+        dict.set_item("i", registers.i)?;
+        dict.set_item("pc", registers.pc)?;
+        dict.set_item("sp", registers.sp)?;
+        Ok(dict)
+    }
+
+    // Only the registers given change
+    #[pyo3(signature = (v = None, i = None, pc = None, sp = None))]
+    fn set_registers(&mut self, v: Option<Vec<u8>>, i: Option<u16>, pc: Option<u16>, sp: Option<u16>) {
+        let mut registers = self.bus.registers();
+        if let Some(v) = v {
+            let size = v.len().min(registers.v.len());
+            registers.v[..size].copy_from_slice(&v[..size]);
+        }
+        registers.i = i.unwrap_or(registers.i);
+        registers.pc = pc.unwrap_or(registers.pc);
+        registers.sp = sp.unwrap_or(registers.sp);
+        self.bus.set_registers(&registers);
+    }
+
+    // Addresses wrap around the memory, like the RPC server does
+    fn read_memory<'py>(&self, py: Python<'py>, address: u16, length: usize) -> Bound<'py, PyBytes> {
+        let bytes: Vec<u8> = (0..length)
+            .map(|offset| self.bus.memory[address.wrapping_add(offset as u16)])
+            .collect();
+        PyBytes::new(py, &bytes)
+    }
+
+    fn write_memory(&mut self, address: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            self.bus.memory[address.wrapping_add(offset as u16)] = *byte;
+        }
+    }
+
+    fn press_key(&mut self, key: i64) -> PyResult<()> {
+        self.queue_key(key, State::PRESSED)
+    }
+
+    fn release_key(&mut self, key: i64) -> PyResult<()> {
+        self.queue_key(key, State::RELEASED)
+    }
+
+    fn sound_active(&self) -> bool {
+        self.bus.state.sound > 0
+    }
+
+    // What stopped the machine, None while it runs
+    fn fault(&self) -> Option<String> {
+        self.bus.fault().map(|fault| fault.to_string())
+    }
+
+    // The save state JSON, what --rpc save_state writes
+    fn snapshot<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let json = self.bus.save_state().to_json().map_err(value_error)?;
+        Ok(PyBytes::new(py, json.as_bytes()))
+    }
+
+    fn restore(&mut self, state: &[u8]) -> PyResult<()> {
+        let json = std::str::from_utf8(state).map_err(value_error)?;
+        let state = SaveState::from_json(json).map_err(value_error)?;
+        self.bus.load_state(&state).map_err(value_error)?;
+        Ok(())
+    }
+}
+
+impl Chip8 {
+    // The keypad goes from 0x0 to 0xF, anything else is a mistake on the Python side
+    fn queue_key(&mut self, key: i64, state: State) -> PyResult<()> {
+        if !(0x0..=0xF).contains(&key) {
+            return Err(value_error(format!("Key {} is not on the keypad (0x0 to 0xF)", key)));
+        }
+        let time = self.bus.time();
+        self.bus.queue_key(key as u8, state, time);
+        Ok(())
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "rusty_chip_8")]
+pub fn chip8_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Chip8>()
+}
@@ -0,0 +1,105 @@
+/* Drives the module from Python code, as a notebook would,
+ * on an interpreter embedded in the test
+ */
+use std::ffi::CString;
+
+use numpy::{PyArray2, PyArrayMethods, PyUntypedArrayMethods};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+fn run_python(code: &str) {
+    Python::attach(|py| {
+        let module = pyo3::wrap_pymodule!(rusty_chip_8_python::chip8_module)(py);
+        py.import("sys").unwrap().getattr("modules").unwrap()
+            .set_item("rusty_chip_8", module).unwrap();
+
+        let globals = PyDict::new(py);
+        let code = CString::new(code).unwrap();
+        if let Err(err) = py.run(&code, Some(&globals), None) {
+            err.display(py);
+            panic!("Python code failed");
+        }
+    });
+}
+
+#[test]
+fn test_module() {
+    run_python(r#"
+import rusty_chip_8
+
+# 200: F00A V0 = key, 202: F029 I = glyph of V0, 204: D005 draw it, 206: 1206 loop
+rom = bytes([0xF0, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06])
+chip8 = rusty_chip_8.Chip8(rom, seed=1)
+assert chip8.read_memory(0x200, 2) == b"\xF0\x0A"
+
+chip8.run_frames(2)
+assert chip8.registers()["pc"] == 0x202
+chip8.press_key(0x0)
+chip8.step(3)
+assert chip8.registers()["pc"] == 0x206
+assert chip8.registers()["i"] == 0
+
+state = chip8.snapshot()
+chip8.set_registers(v=[7, 8], i=0x300)
+assert chip8.registers()["v"][:3] == [7, 8, 0]
+chip8.write_memory(0x300, b"\x01\x02")
+assert chip8.read_memory(0x300, 2) == b"\x01\x02"
+
+chip8.restore(state)
+assert chip8.registers()["i"] == 0
+assert chip8.read_memory(0x300, 2) == b"\x00\x00"
+
+# Keys past the keypad are refused
+for key in (0x10, 0xFF, 0x100, -1):
+    for method in (chip8.press_key, chip8.release_key):
+        try:
+            method(key)
+            assert False
+        except ValueError:
+            pass
+
+# 00EE with nothing on the stack
+chip8.load_rom(b"\x00\xEE")
+chip8.step()
+assert chip8.fault() is not None
+chip8.reset()
+assert chip8.fault() is None
+
+try:
+    rusty_chip_8.Chip8(rom, variant="nes")
+    assert False
+except ValueError:
+    pass
+"#);
+}
+
+/* Skipped when the interpreter running the tests has no numpy,
+ * unless RUSTY_CHIP_8_REQUIRE_NUMPY is set
+ */
+#[test]
+fn test_vram_is_a_numpy_array() {
+    Python::attach(|py| {
+        if let Err(err) = py.import("numpy") {
+            if std::env::var_os("RUSTY_CHIP_8_REQUIRE_NUMPY").is_some() {
+                panic!("numpy is needed to run this test (pip install numpy): {}", err);
+            }
+            eprintln!("Skipping test_vram_is_a_numpy_array, numpy is not installed");
+            return;
+        }
+        // 200: F029 I = glyph of V0, 202: D005 draw it, 204: 1204 loop
+        let rom: &[u8] = &[0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04];
+        let module = pyo3::wrap_pymodule!(rusty_chip_8_python::chip8_module)(py);
+        let chip8 = module.bind(py).getattr("Chip8").unwrap().call1((PyBytes::new(py, rom),)).unwrap();
+        chip8.call_method1("step", (3,)).unwrap();
+
+        let vram = chip8.call_method0("vram").unwrap();
+        let vram = vram.cast_into::<PyArray2<u8>>().unwrap();
+        assert_eq!(vram.shape(), [32, 64]);
+        let vram = vram.readonly();
+        let vram = vram.as_array();
+        // Top of the 0 glyph, then a row with only the sides lit
+        assert_eq!(vram.row(0).iter().take(5).copied().collect::<Vec<u8>>(), [1, 1, 1, 1, 0]);
+        assert_eq!(vram.row(1).iter().take(5).copied().collect::<Vec<u8>>(), [1, 0, 0, 1, 0]);
+        assert_eq!(vram.iter().map(|&pixel| u32::from(pixel)).sum::<u32>(), 14);
+    });
+}
@@ -1,10 +1,24 @@
-extern crate bitmatrix;
-
 use crate::busstate::BusState;
-use crate::cpu::CPU;
+use crate::cheats::Cheat;
+use crate::coverage::Coverage;
+use crate::cpu::{CallFrame, CPU, Registers};
+use crate::debug::DebugEvent;
+use crate::fault::Fault;
+use crate::machine::MachineConfig;
 use crate::memory::MemoryMap;
-use crate::keyboard::Keyboard; 
-use bitmatrix::BitMatrix;
+use crate::profiler::Profiler;
+use crate::keyboard::{InputEvent, Keyboard, State};
+use crate::framebuffer::Framebuffer;
+use crate::timing::{self, Tick, Timing};
+#[cfg(feature = "files")]
+use crate::rom;
+use crate::savestate::{SaveState, SAVE_STATE_VERSION};
+use std::collections::BTreeSet;
+use std::io;
+use std::ops::Range;
+#[cfg(feature = "files")]
+use std::path::Path;
+use std::time::Duration;
 
 /* This is a entirelly public
  * state.soundruct so other components can have
@@ -18,63 +32,381 @@ use bitmatrix::BitMatrix;
  */
 pub struct Bus{
     cpu: CPU,
+    config: MachineConfig,
+    // Kept for reset
+    rom: Vec<u8>,
     pub memory: MemoryMap,
     pub keyboard: Keyboard,
 
     pub state: BusState,
+    pub timing: Timing,
+
+    // run_for stops before running an instruction at any of these
+    breakpoints: BTreeSet<u16>,
+    breakpoint_hit: Option<u16>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    // Written back on every 60hz frame while enabled
+    cheats: Vec<Cheat>,
+    cheats_enabled: bool,
 }
 
 impl Bus{
+    // Accepts anything rom::load does, with the default machine
+    #[cfg(feature = "files")]
     pub fn new(file: &str) -> Bus {
-        Bus {
-            cpu: CPU::new(),
-            memory: MemoryMap::new(file),
+        let rom = rom::load(Path::new(file), None).expect("Could not open file");
+        Bus::with_rom(&rom.data)
+    }
+
+    pub fn with_rom(rom: &[u8]) -> Bus {
+        Bus::with_config(rom, MachineConfig::default()).expect("The default machine is valid")
+    }
+
+    // Configs the machine can't be built with are an error
+    pub fn with_config(rom: &[u8], config: MachineConfig) -> io::Result<Bus> {
+        config.validate()?;
+        let mut timing = Timing::new(config.rate);
+        timing.set_mode(config.mode);
+        Ok(Bus {
+            cpu: CPU::with_config(&config),
+            memory: MemoryMap::with_config(rom, &config),
             keyboard: Keyboard::new(),
             state: BusState {
                 delay: 0,
                 sound: 0,
                 lock_until_pressed: false,
+                wait_vblank: false,
                 write_to: 0x0,
+                quirks: config.quirks,
+                fault: None,
             },
+            timing,
+            config,
+            rom: rom.to_vec(),
+            breakpoints: BTreeSet::new(),
+            breakpoint_hit: None,
+            profiler: None,
+            coverage: None,
+            cheats: Vec::new(),
+            cheats_enabled: true,
+        })
+    }
+
+    // What the machine was built from
+    pub fn config(&self) -> &MachineConfig {
+        &self.config
+    }
+
+    // Power cycle, breakpoints are kept
+    pub fn reset(&mut self) {
+        let rom = std::mem::take(&mut self.rom);
+        self.load_rom(&rom);
+    }
+
+    // Starts over with another ROM on the same machine
+    pub fn load_rom(&mut self, rom: &[u8]) {
+        let config = self.config.clone();
+        self.rebuild(rom, config).expect("The config was checked when the bus was built");
+    }
+
+    /* Breakpoints, cheats, the profiler and coverage are kept, and so is the memory
+     * buffer when the size didn't change, frontends may hold a pointer to it
+     */
+    fn rebuild(&mut self, rom: &[u8], config: MachineConfig) -> io::Result<()> {
+        let old = std::mem::replace(self, Bus::with_config(rom, config)?);
+        self.breakpoints = old.breakpoints;
+        self.memory.reuse_buffer(old.memory);
+        self.profiler = old.profiler;
+        self.coverage = old.coverage;
+        self.cheats = old.cheats;
+        self.cheats_enabled = old.cheats_enabled;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.restart(self.cpu.pc());
+        }
+        Ok(())
+    }
+
+    pub fn save_state(&self) -> SaveState {
+        let (rng_seed, rng_draws) = self.cpu.rng_state();
+        SaveState {
+            version: SAVE_STATE_VERSION,
+            config: self.config.clone(),
+            rom: self.rom.clone(),
+            registers: self.cpu.registers(),
+            stack: self.cpu.stack().to_vec(),
+            rng_seed,
+            rng_draws,
+            memory: self.memory.bytes().to_vec(),
+            vram: self.memory.get_full_vram().to_bitmap(),
+            state: self.state.clone(),
+            keyboard: self.keyboard.clone(),
+            timing: self.timing.clone(),
         }
     }
 
+    /* Rebuilds the machine from the state config, breakpoints are kept.
+     * A config the machine can't be built with, or a clock out of range, leaves the bus as it was
+     */
+    pub fn load_state(&mut self, state: &SaveState) -> io::Result<()> {
+        // The rate comes from the config, only the clock from the state
+        let mut timing = Timing::new(state.config.rate);
+        timing.set_mode(state.config.mode);
+        timing.set_speed(self.timing.speed());
+        timing.restore_clock(&state.timing)?;
+        self.rebuild(&state.rom, state.config.clone())?;
+
+        self.cpu.set_registers(&state.registers);
+        self.cpu.set_stack(&state.stack);
+        self.cpu.set_rng_state(state.rng_seed, state.rng_draws);
+        self.memory.load_bytes(&state.memory);
+        self.memory.get_full_vram_mut().load_bitmap(&state.vram);
+        self.state = state.state.clone();
+        self.keyboard = state.keyboard.clone();
+        self.timing = timing;
+        Ok(())
+    }
+
+    // Set once the machine stopped on a fault, nothing runs after it
+    pub fn fault(&self) -> Option<Fault> {
+        self.state.fault
+    }
+
+    // Events raised since the last call, oldest first
+    pub fn take_debug_events(&mut self) -> Vec<DebugEvent> {
+        self.memory.take_debug_events()
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    pub fn set_registers(&mut self, registers: &Registers) {
+        self.cpu.set_registers(registers);
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    // Address run_for stopped at, once
+    pub fn take_breakpoint_hit(&mut self) -> Option<u16> {
+        self.breakpoint_hit.take()
+    }
+
+    pub fn call_stack(&self) -> Vec<CallFrame> {
+        self.cpu.call_stack()
+    }
+
+    // Starts counting every instruction from now on
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new(self.cpu.pc()));
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Stops profiling, giving back what was counted
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    // Starts tracking which instructions run from now on
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    // The ROM as it was loaded, before the program changed anything
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Replaces the cheats, the enabled ones apply right away
+    pub fn set_cheats(&mut self, cheats: Vec<Cheat>) {
+        self.cheats = cheats;
+        self.apply_cheats();
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+        self.apply_cheats();
+    }
+
+    // Out of range indexes are ignored
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+        self.apply_cheats();
+    }
+
+    // Turns every cheat on or off at once, each one keeps its own switch
+    pub fn set_cheats_enabled(&mut self, enabled: bool) {
+        self.cheats_enabled = enabled;
+        self.apply_cheats();
+    }
+
+    pub fn cheats_enabled(&self) -> bool {
+        self.cheats_enabled
+    }
+
+    fn apply_cheats(&mut self) {
+        if !self.cheats_enabled || self.cheats.iter().all(|cheat| !cheat.enabled) {
+            return;
+        }
+        let mut registers = self.cpu.registers();
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            cheat.apply(&mut self.memory, &mut registers);
+        }
+        self.cpu.set_registers(&registers);
+    }
+
     pub fn was_screen_updated(&self) -> bool {
         self.memory.was_screen_updated()
     }
 
-    pub fn get_vram(&self) -> &BitMatrix {
+    pub fn get_vram(&self) -> &Framebuffer {
         self.memory.get_full_vram()
     }
 
+    pub fn take_dirty_rows(&mut self) -> Vec<Range<usize>> {
+        self.memory.take_dirty_rows()
+    }
+
+    /* Runs whatever the elapsed host time is worth of
+     * instructions and 60hz timer ticks, interleaved
+     * in the order they would happen on the machine
+     */
+    pub fn run_for(&mut self, elapsed: Duration) {
+        self.timing.advance(elapsed);
+        while let Some(tick) = self.timing.next_tick() {
+            match tick {
+                Tick::Cpu => {
+                    let pc = self.cpu.pc();
+                    if self.breakpoints.contains(&pc) {
+                        // Stops before running it, step goes past it
+                        self.breakpoint_hit = Some(pc);
+                        self.timing.discard_pending();
+                        return;
+                    }
+                    self.run_cpu_tick();
+                },
+                Tick::Timers => self.tick_frame_timer(),
+            }
+        }
+    }
+
+    /* Runs a single instruction, along with the timer ticks due
+     * before it, breakpoints are ignored
+     */
+    pub fn step(&mut self) {
+        loop {
+            self.timing.allow_next_tick();
+            match self.timing.next_tick() {
+                Some(Tick::Cpu) => return self.run_cpu_tick(),
+                Some(Tick::Timers) => self.tick_frame_timer(),
+                None => {},
+            }
+        }
+    }
+
+    fn run_cpu_tick(&mut self) {
+        // Input is seen at the instruction boundary it happened at
+        self.keyboard.apply_events_until(self.timing.now());
+        let cycles = self.tick_frame_cpu();
+        self.timing.consume(cycles);
+    }
+
+    // Emulated time since power on, what input events are stamped with
+    pub fn time(&self) -> Duration {
+        self.timing.now()
+    }
+
+    pub fn queue_key(&mut self, key: u8, state: State, time: Duration) {
+        self.keyboard.queue_event(InputEvent { key: key & 0xF, state, time });
+    }
+
     pub fn tick_frame_timer(&mut self) {
+        // Timers tick on the vertical blank, so a pending draw can continue
+        self.state.wait_vblank = false;
+
         if self.state.delay > 0 {
-            self.state.delay = self.state.delay - 1;
+            self.state.delay -= 1;
         }
         if self.state.sound > 0 {
-            self.state.sound = self.state.sound - 1;
+            self.state.sound -= 1;
+        }
+        self.keyboard.expire_taps();
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
         }
+        self.apply_cheats();
     }
 
-    pub fn tick_frame_cpu(&mut self) {
-        if self.keyboard.was_any_key_pressed() && self.state.lock_until_pressed {
-            self.state.lock_until_pressed = false;
+    // Time spent without running an instruction
+    fn idle(&mut self, cycles: u32) -> u32 {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record_idle(cycles);
+        }
+        cycles
+    }
 
-            let last_key_pressed = self.keyboard.get_last_pressed_key();
-            self.cpu.write_key_to(self.state.write_to as usize, last_key_pressed);
+    // Returns the VIP machine cycles spent
+    pub fn tick_frame_cpu(&mut self) -> u32 {
+        if self.state.fault.is_some() {
+            // Halted, time still goes on so the timers keep running
+            return self.idle(1);
         }
-                    
         if self.state.lock_until_pressed {
-            return;
+            match self.keyboard.take_waited_key() {
+                Some(key) => {
+                    self.state.lock_until_pressed = false;
+                    self.cpu.write_key_to(self.state.write_to as usize, key);
+                },
+                // Still spinning on the FX0A keypad scan
+                None => return self.idle(timing::vip_cycles(0xF00A, false, 0)),
+            }
         }
-        //four clocks per frame
-        //This looks ugly, needs to revisit this once I learn more about the language
-        self.cpu.execute_next_instruction(
+
+        if self.state.wait_vblank {
+            // Idle until the timers tick, burning a cycle at a time
+            return self.idle(1);
+        }
+        let pc = self.cpu.pc();
+        let opcode = u16::from(self.memory[pc]) << 8 | u16::from(self.memory[pc.wrapping_add(1)]);
+        let cycles = self.cpu.execute_next_instruction(
             &mut self.memory,
             &mut self.keyboard,
             &mut self.state
         );
-    } 
+        if let Some(fault) = self.memory.take_fault() {
+            self.state.fault = Some(fault);
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(pc, opcode, cycles, self.state.fault.is_some());
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(pc, opcode, self.cpu.pc(), self.state.fault.is_some());
+        }
+        cycles
+    }
 }
 
 
@@ -1,3 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+use crate::fault::Fault;
+use crate::quirks::Quirks;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BusState{
     //Decrement at 60hz
     pub delay:u8,
@@ -5,8 +11,14 @@ pub struct BusState{
     pub sound:u8,
     
     pub write_to: u8,
-    //stops processing until a key is pressed
+    //stops processing until the keyboard key wait completes (FX0A)
     pub lock_until_pressed: bool,
+    //stops processing until the next 60hz frame (display wait quirk)
+    pub wait_vblank: bool,
+
+    pub quirks: Quirks,
+    //set when the ROM did something the machine can't go on from
+    pub fault: Option<Fault>,
 }
 
 
@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+#[cfg(feature = "files")]
+use std::fs;
+#[cfg(feature = "files")]
+use std::io;
+#[cfg(feature = "files")]
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::Registers;
+use crate::memory::MemoryMap;
+
+#[cfg(feature = "files")]
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+// How a byte has to compare with what it was on the last search
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchFilter {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    // Holds exactly this now
+    Value(u8),
+}
+
+impl SearchFilter {
+    fn keeps(self, before: u8, now: u8) -> bool {
+        match self {
+            SearchFilter::Equal => now == before,
+            SearchFilter::Changed => now != before,
+            SearchFilter::Increased => now > before,
+            SearchFilter::Decreased => now < before,
+            SearchFilter::Value(value) => now == value,
+        }
+    }
+}
+
+/* Finds where a game keeps something (lives, time, score) the way
+ * other emulators do: take a snapshot, play a bit, keep the
+ * addresses that changed the way the value did, repeat until
+ * only a few are left.
+ *
+ *   let mut search = RamSearch::new(&bus.memory);
+ *   // lose a life
+ *   search.filter(&bus.memory, SearchFilter::Decreased);
+ */
+#[derive(Clone, Debug)]
+pub struct RamSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl RamSearch {
+    // Every address starts as a candidate
+    pub fn new(memory: &MemoryMap) -> Self {
+        let snapshot = memory.bytes().to_vec();
+        let candidates = (0..snapshot.len()).map(|address| address as u16).collect();
+        RamSearch { snapshot, candidates }
+    }
+
+    // Drops the candidates that don't match, the snapshot moves to now
+    pub fn filter(&mut self, memory: &MemoryMap, filter: SearchFilter) -> &[u16] {
+        let bytes = memory.bytes();
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|address| {
+            let address = usize::from(*address);
+            match (snapshot.get(address), bytes.get(address)) {
+                (Some(before), Some(now)) => filter.keeps(*before, *now),
+                _ => false,
+            }
+        });
+        self.snapshot = bytes.to_vec();
+        &self.candidates
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // What the address held on the last snapshot
+    pub fn value(&self, address: u16) -> Option<u8> {
+        self.snapshot.get(usize::from(address)).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+/* What a cheat pins, written on the cheat file as "0x2F0"
+ * (an address), "V3" or "I"
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum CheatTarget {
+    Memory(u16),
+    V(u8),
+    I,
+}
+
+impl TryFrom<String> for CheatTarget {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let upper = text.trim().to_uppercase();
+        if upper == "I" {
+            return Ok(CheatTarget::I);
+        }
+        if let Some(register) = upper.strip_prefix('V') {
+            return match u8::from_str_radix(register, 16) {
+                Ok(register) if register < 0x10 => Ok(CheatTarget::V(register)),
+                _ => Err(format!("Invalid register {}", text)),
+            };
+        }
+        let address = match upper.strip_prefix("0X") {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => upper.parse(),
+        };
+        address.map(CheatTarget::Memory).map_err(|_| format!("Invalid cheat target {}, expected an address, V0-VF or I", text))
+    }
+}
+
+impl From<CheatTarget> for String {
+    fn from(target: CheatTarget) -> String {
+        target.to_string()
+    }
+}
+
+impl fmt::Display for CheatTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatTarget::Memory(address) => write!(f, "{:#05X}", address),
+            CheatTarget::V(register) => write!(f, "V{:X}", register),
+            CheatTarget::I => write!(f, "I"),
+        }
+    }
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+// A value written back on every frame, memory and V only take the low byte
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cheat {
+    #[serde(default)]
+    pub name: String,
+    pub target: CheatTarget,
+    pub value: u16,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn new(name: &str, target: CheatTarget, value: u16) -> Self {
+        Cheat { name: name.to_string(), target, value, enabled: true }
+    }
+
+    /* Straight to memory and the registers, going around the
+     * bounds policy and write protection like a frontend poke
+     */
+    pub fn apply(&self, memory: &mut MemoryMap, registers: &mut Registers) {
+        match self.target {
+            CheatTarget::Memory(address) => memory[address] = self.value as u8,
+            CheatTarget::V(register) => registers.v[usize::from(register & 0xF)] = self.value as u8,
+            CheatTarget::I => registers.i = self.value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RomCheats {
+    #[serde(default)]
+    pub cheats: Vec<Cheat>,
+}
+
+/* Cheats for every ROM, keyed by its SHA-1 (romdb::sha1_hex)
+ * so a renamed file keeps them:
+ *
+ *   [[roms.<sha1>.cheats]]
+ *   name = "Infinite lives"
+ *   target = "0x2F0"
+ *   value = 3
+ *
+ *   [[roms.<sha1>.cheats]]
+ *   target = "V5"
+ *   value = 0
+ *   enabled = false
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CheatFile {
+    #[serde(default)]
+    pub roms: BTreeMap<String, RomCheats>,
+}
+
+impl CheatFile {
+    // A missing file is not an error, there are just no cheats
+    #[cfg(feature = "files")]
+    pub fn load(path: &Path) -> io::Result<CheatFile> {
+        match fs::read_to_string(path) {
+            Ok(text) => CheatFile::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(CheatFile::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    #[cfg(feature = "files")]
+    pub fn parse(text: &str) -> io::Result<CheatFile> {
+        toml::from_str(text).map_err(invalid_data)
+    }
+
+    #[cfg(feature = "files")]
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, toml::to_string(self).map_err(invalid_data)?)
+    }
+
+    pub fn cheats(&self, hash: &str) -> &[Cheat] {
+        self.roms.get(hash).map_or(&[], |rom| &rom.cheats)
+    }
+
+    // No cheats drops the ROM from the file
+    pub fn set_cheats(&mut self, hash: &str, cheats: Vec<Cheat>) {
+        if cheats.is_empty() {
+            self.roms.remove(hash);
+        } else {
+            self.roms.insert(hash.to_string(), RomCheats { cheats });
+        }
+    }
+}
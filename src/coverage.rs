@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::disasm::{disassemble, is_instruction, is_skip};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BranchCounts {
+    // The condition held and the next instruction was skipped
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/* Maps addresses back to the Octo source, one entry per line:
+ *
+ *   # Comments start with #
+ *   source pong.8o
+ *   label main 0x200
+ *   line 0x200 12
+ *
+ * "line" is an address and the source line it was assembled from,
+ * it can be written from the debug info the Octo compiler keeps.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolFile {
+    pub source: Option<String>,
+    lines: BTreeMap<u16, u32>,
+    labels: BTreeMap<u16, String>,
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+fn parse_address(text: &str) -> io::Result<u16> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| invalid_data(format!("Invalid address {}", text)))
+}
+
+impl SymbolFile {
+    pub fn parse(text: &str) -> io::Result<SymbolFile> {
+        let mut symbols = SymbolFile::default();
+        for (number, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let error = || invalid_data(format!("Line {}: expected source, label or line, got {}", number + 1, line));
+            match fields.as_slice() {
+                [] => {},
+                [comment, ..] if comment.starts_with('#') => {},
+                ["source", file] => symbols.source = Some(file.to_string()),
+                ["label", name, address] => {
+                    symbols.labels.insert(parse_address(address)?, name.to_string());
+                },
+                ["line", address, source_line] => {
+                    let source_line = source_line.parse().map_err(|_| error())?;
+                    symbols.lines.insert(parse_address(address)?, source_line);
+                },
+                _ => return Err(error()),
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> io::Result<SymbolFile> {
+        SymbolFile::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn line(&self, address: u16) -> Option<u32> {
+        self.lines.get(&address).copied()
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+}
+
+// A line of the listing, either an instruction or a byte on its own
+struct Entry {
+    address: u16,
+    opcode: Option<u16>,
+    text: String,
+}
+
+/* Which addresses ran and how often, and which way every skip
+ * instruction (3XNN, 4XNN, 5XY0, 9XY0, EX9E, EXA1) went, fed by
+ * the bus after every instruction
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    hits: BTreeMap<u16, u64>,
+    branches: BTreeMap<u16, BranchCounts>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    // next_pc is where the PC ended up after running it
+    pub fn record(&mut self, pc: u16, opcode: u16, next_pc: u16, faulted: bool) {
+        *self.hits.entry(pc).or_default() += 1;
+        if faulted || !is_skip(opcode) {
+            return;
+        }
+        let branch = self.branches.entry(pc).or_default();
+        if next_pc == pc.wrapping_add(4) {
+            branch.taken += 1;
+        } else {
+            branch.not_taken += 1;
+        }
+    }
+
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn branch(&self, address: u16) -> Option<BranchCounts> {
+        self.branches.get(&address).copied()
+    }
+
+    // Every address that ran at least once, in order
+    pub fn executed(&self) -> Vec<u16> {
+        self.hits.keys().copied().collect()
+    }
+
+    /* Splits the ROM into instructions, a byte only stands on its
+     * own when the code ran from the one after it (misaligned code)
+     */
+    fn entries(&self, rom: &[u8], start: u16) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < rom.len() {
+            let address = start.wrapping_add(offset as u16);
+            let misaligned = self.hits(address) == 0 && self.hits(address.wrapping_add(1)) > 0;
+            if offset + 1 < rom.len() && !misaligned {
+                let opcode = u16::from(rom[offset]) << 8 | u16::from(rom[offset + 1]);
+                entries.push(Entry { address, opcode: Some(opcode), text: disassemble(opcode) });
+                offset += 2;
+            } else {
+                entries.push(Entry { address, opcode: None, text: format!("DB {:#04X}", rom[offset]) });
+                offset += 1;
+            }
+        }
+        entries
+    }
+
+    // Never ran, but looks like it could, the rest is taken as data
+    fn is_code(&self, entry: &Entry) -> bool {
+        self.hits(entry.address) > 0 || entry.opcode.is_some_and(is_instruction)
+    }
+
+    fn branch_text(&self, address: u16) -> String {
+        match self.branch(address) {
+            Some(branch) => format!("taken {}, not taken {}", branch.taken, branch.not_taken),
+            None => String::new(),
+        }
+    }
+
+    /* The ROM disassembled with how many times each instruction ran,
+     * "#####" for code that never ran and "-" for what looks like data
+     */
+    pub fn listing(&self, rom: &[u8], start: u16, symbols: Option<&SymbolFile>) -> String {
+        self.listing_lines(rom, start, symbols).into_iter().map(|(line, _)| line + "\n").collect()
+    }
+
+    // Every line of the listing, along with the address on it
+    fn listing_lines(&self, rom: &[u8], start: u16, symbols: Option<&SymbolFile>) -> Vec<(String, Option<u16>)> {
+        let entries = self.entries(rom, start);
+        let code = entries.iter().filter(|entry| self.is_code(entry)).count();
+        let run = entries.iter().filter(|entry| self.hits(entry.address) > 0).count();
+
+        let mut lines = vec![
+            (format!("; {} of {} instructions ran", run, code), None),
+            (format!(";{:>10}  {:<7}  {:<6}  {:<20}  Branches", "Hits", "Address", "Opcode", "Instruction"), None),
+        ];
+        for entry in &entries {
+            if let Some(label) = symbols.and_then(|symbols| symbols.label(entry.address)) {
+                lines.push((format!("{}:", label), None));
+            }
+            let hits = match self.hits(entry.address) {
+                0 if self.is_code(entry) => "#####".to_string(),
+                0 => "-".to_string(),
+                hits => hits.to_string(),
+            };
+            let opcode = match entry.opcode {
+                Some(opcode) => format!("{:04X}", opcode),
+                None => format!("{:02X}", rom[usize::from(entry.address.wrapping_sub(start))]),
+            };
+            let mut line = format!("{:>11}  {:#05X}    {:<6}  {:<20}  {}",
+                hits, entry.address, opcode, entry.text, self.branch_text(entry.address));
+            if let Some(source_line) = symbols.and_then(|symbols| symbols.line(entry.address)) {
+                let source = symbols.and_then(|symbols| symbols.source.as_deref()).unwrap_or("source");
+                write!(line, "  ; {}:{}", source, source_line).unwrap();
+            }
+            lines.push((line.trim_end().to_string(), Some(entry.address)));
+        }
+        lines
+    }
+
+    /* lcov tracefile, for genhtml and the coverage tools that read it.
+     * With symbols the lines are those of the Octo source, without
+     * them they are the lines of the listing, written to listing_name.
+     */
+    pub fn lcov(&self, rom: &[u8], start: u16, symbols: Option<&SymbolFile>, listing_name: &str) -> String {
+        // Source line, hits and the addresses of the skips on it
+        let mut lines: BTreeMap<u32, (u64, Vec<u16>)> = BTreeMap::new();
+        let mut add = |line: u32, address: u16, hits: u64| {
+            let entry = lines.entry(line).or_insert((0, Vec::new()));
+            entry.0 = entry.0.max(hits);
+            if self.branch(address).is_some() || is_skip_at(rom, start, address) {
+                entry.1.push(address);
+            }
+        };
+
+        let source = match symbols {
+            Some(symbols) => {
+                for entry in self.entries(rom, start) {
+                    if let Some(line) = symbols.line(entry.address) {
+                        add(line, entry.address, self.hits(entry.address));
+                    }
+                }
+                symbols.source.clone().unwrap_or_else(|| "source".to_string())
+            },
+            None => {
+                let entries = self.entries(rom, start);
+                for (number, (_, address)) in self.listing_lines(rom, start, None).iter().enumerate() {
+                    let address = match address {
+                        Some(address) => *address,
+                        None => continue,
+                    };
+                    let is_code = entries.iter().any(|entry| entry.address == address && self.is_code(entry));
+                    if is_code {
+                        add(number as u32 + 1, address, self.hits(address));
+                    }
+                }
+                listing_name.to_string()
+            },
+        };
+
+        let mut lcov = String::new();
+        writeln!(lcov, "TN:").unwrap();
+        writeln!(lcov, "SF:{}", source).unwrap();
+        let (mut branches, mut branches_hit) = (0, 0);
+        for (line, (hits, skips)) in &lines {
+            for (block, address) in skips.iter().enumerate() {
+                let counts = [self.branch(*address).map(|branch| branch.taken),
+                              self.branch(*address).map(|branch| branch.not_taken)];
+                for (branch, count) in counts.iter().enumerate() {
+                    // "-" when the skip itself never ran
+                    let count = match count {
+                        Some(count) if *hits > 0 => count.to_string(),
+                        _ => "-".to_string(),
+                    };
+                    branches += 1;
+                    if count != "-" && count != "0" {
+                        branches_hit += 1;
+                    }
+                    writeln!(lcov, "BRDA:{},{},{},{}", line, block, branch, count).unwrap();
+                }
+            }
+        }
+        for (line, (hits, _)) in &lines {
+            writeln!(lcov, "DA:{},{}", line, hits).unwrap();
+        }
+        writeln!(lcov, "BRF:{}", branches).unwrap();
+        writeln!(lcov, "BRH:{}", branches_hit).unwrap();
+        writeln!(lcov, "LF:{}", lines.len()).unwrap();
+        writeln!(lcov, "LH:{}", lines.values().filter(|(hits, _)| *hits > 0).count()).unwrap();
+        writeln!(lcov, "end_of_record").unwrap();
+        lcov
+    }
+}
+
+fn is_skip_at(rom: &[u8], start: u16, address: u16) -> bool {
+    let offset = usize::from(address.wrapping_sub(start));
+    match (rom.get(offset), rom.get(offset + 1)) {
+        (Some(high), Some(low)) => is_skip(u16::from(*high) << 8 | u16::from(*low)),
+        _ => false,
+    }
+}
@@ -1,13 +1,14 @@
-extern crate bit_vec;
-
-use std::process;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::busstate::BusState;
+use crate::fault::Fault;
 use crate::memory::MemoryMap;
 use crate::keyboard::Keyboard;
-
-use bit_vec::BitVec;
+use crate::machine::MachineConfig;
+use crate::quirks::Quirks;
+use crate::timing;
 
 pub struct CPU {
     sp:u16,
@@ -20,9 +21,35 @@ pub struct CPU {
     v: [u8; 0x10],
     //Single I register
     i: u16,
-    //Stack in CHIP-8 is
-    //limited to 16 elements
-    stack: [u16; 0x10],
+    //Stack in CHIP-8 is limited,
+    //16 elements on most interpreters (12 on the VIP)
+    stack: Vec<u16>,
+    //Source for CXNN, seeded from the config when it has a seed
+    rng: ChaCha12Rng,
+    //Seed and numbers drawn since, all a save state needs to continue the sequence
+    seed: u64,
+    draws: u64,
+    //Addresses set from outside are kept inside the memory
+    memory_size: usize,
+}
+
+// A subroutine call still waiting for its return
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CallFrame {
+    // Address of the 2NNN that made the call
+    pub call_site: u16,
+    // Where 00EE goes back to
+    pub return_address: u16,
+}
+
+// The registers a debugger can see and change
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Registers {
+    pub v: [u8; 0x10],
+    pub i: u16,
+    pub pc: u16,
+    // Stack levels in use
+    pub sp: u16,
 }
 
 enum PcIncrement {
@@ -31,14 +58,32 @@ enum PcIncrement {
     NONE,
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CPU {
     pub fn new() -> CPU {
+        CPU::with_config(&MachineConfig::default())
+    }
+
+    pub fn with_config(config: &MachineConfig) -> CPU {
+        let seed = match config.seed {
+            Some(seed) => u64::from(seed),
+            None => rand::random(),
+        };
         CPU{
             sp: 0,
-            pc: 0x200,
+            pc: config.layout.entry_pc,
             v: [0; 0x10],
             i: 0,
-            stack: [0; 0x10],
+            stack: vec![0; config.stack_depth],
+            rng: ChaCha12Rng::seed_from_u64(seed),
+            seed,
+            draws: 0,
+            memory_size: config.memory_size.max(1),
         }
     }
 
@@ -46,28 +91,106 @@ impl CPU {
         (opcode >> (pos * 4) & 0xF) as usize
     }
 
-    fn push_pc_to_stack(&mut self) {
+    // The call site is stored, the return goes to the instruction after it
+    fn push_pc_to_stack(&mut self) -> Result<(), Fault> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(Fault::StackOverflow { pc: self.pc, depth: self.stack.len() });
+        }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
+        Ok(())
     }
 
-    fn pop_pc_from_stack(&mut self) {
+    fn pop_pc_from_stack(&mut self) -> Result<(), Fault> {
+        if self.sp == 0 {
+            return Err(Fault::StackUnderflow { pc: self.pc });
+        }
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
+        Ok(())
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
     }
 
-    pub fn execute_next_instruction(&mut self, memory: &mut MemoryMap, keyboard: &mut Keyboard, state: &mut BusState) {
+    pub fn registers(&self) -> Registers {
+        Registers { v: self.v, i: self.i, pc: self.pc, sp: self.sp }
+    }
+
+    // SP can't go past the stack depth and the PC can't point outside the memory
+    pub fn set_registers(&mut self, registers: &Registers) {
+        self.v = registers.v;
+        self.i = registers.i;
+        self.pc = (registers.pc as usize % self.memory_size) as u16;
+        self.sp = registers.sp.min(self.stack.len() as u16);
+    }
+
+    // Return addresses in use and free levels, for save states
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    // The stack depth stays the one from the config
+    pub fn set_stack(&mut self, stack: &[u16]) {
+        let size = stack.len().min(self.stack.len());
+        self.stack[..size].copy_from_slice(&stack[..size]);
+    }
+
+    /* The generator state isn't stored, save states keep the seed and
+     * how many numbers were drawn, reading it doesn't change anything
+     */
+    pub fn rng_state(&self) -> (u64, u64) {
+        (self.seed, self.draws)
+    }
+
+    /* Every CXNN draw takes one 32 bit word of the ChaCha stream, so
+     * skipping to word `draws` is the same as drawing them all again,
+     * without taking forever on a huge count
+     */
+    pub fn set_rng_state(&mut self, seed: u64, draws: u64) {
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+        self.rng.set_word_pos(u128::from(draws));
+        self.seed = seed;
+        self.draws = draws;
+    }
+
+    // Innermost call last
+    pub fn call_stack(&self) -> Vec<CallFrame> {
+        self.stack[..self.sp as usize].iter()
+            .map(|call_site| CallFrame { call_site: *call_site, return_address: call_site.wrapping_add(2) })
+            .collect()
+    }
+
+    /* Executes a single instruction, returning how many machine
+     * cycles it would have taken on the COSMAC VIP, the Bus
+     * uses it when running with the VIP timing
+     */
+    pub fn execute_next_instruction(&mut self, memory: &mut MemoryMap, keyboard: &mut Keyboard, state: &mut BusState) -> u32 {
+        let cycles = self.execute(memory, keyboard, state);
+        // The PC follows the bounds policy like any other address
+        self.pc = memory.wrap_address(self.pc);
+        cycles
+    }
+
+    fn execute(&mut self, memory: &mut MemoryMap, keyboard: &mut Keyboard, state: &mut BusState) -> u32 {
         // Opcodes are stored in 2 bytes
-        let shifted_pc:u16 = (memory[self.pc] as u16) << 8;
-        let opcode = shifted_pc | (memory[self.pc + 1] as u16);
+        memory.set_pc(self.pc);
+        let shifted_pc:u16 = (memory.read(self.pc as usize) as u16) << 8;
+        let opcode = shifted_pc | (memory.read(self.pc as usize + 1) as u16);
+        // Some instructions cost depends on VX before it runs
+        let vx = self.v[CPU::get_val_from_opcode(opcode, 2)];
         if opcode == 0x00E0 {
             memory.clear_vram();
 
         }
         else if opcode == 0xEE {
-            self.pop_pc_from_stack();
-            self.pc += 2;
-            return;
+            // On a fault the PC stays on the instruction that caused it
+            match self.pop_pc_from_stack() {
+                Ok(()) => self.pc = self.pc.wrapping_add(2),
+                Err(fault) => state.fault = Some(fault),
+            }
+            return timing::vip_cycles(opcode, false, vx);
         }
 
         // Most of instructions, beside
@@ -95,8 +218,10 @@ impl CPU {
                 increment_type = PcIncrement::NONE;
             }
             0x2 => {
-                self.push_pc_to_stack();
-                self.pc = opcode & 0x0FFF;
+                match self.push_pc_to_stack() {
+                    Ok(()) => self.pc = opcode & 0x0FFF,
+                    Err(fault) => state.fault = Some(fault),
+                }
                 increment_type = PcIncrement::NONE;
             },
             0x3 => {
@@ -114,10 +239,10 @@ impl CPU {
             0x5 => {
                 let last_octal = opcode & 0xF;
                 if last_octal != 0x0 {
-                    CPU::abort();
+                    state.fault = Some(self.invalid_instruction(opcode));
+                    increment_type = PcIncrement::NONE;
                 }
-
-                if self.v[regs.1] == self.v[regs.2] {
+                else if self.v[regs.1] == self.v[regs.2] {
                     increment_type = PcIncrement::SKIP;
                 }
             },
@@ -130,15 +255,18 @@ impl CPU {
                 self.v[regs.2] = self.v[regs.2].wrapping_add(value);
             },
             0x8 => {
-                CPU::execute_instr_op_8(&mut self.v, opcode);
+                if let Err(fault) = self.execute_instr_op_8(opcode, &state.quirks) {
+                    state.fault = Some(fault);
+                    increment_type = PcIncrement::NONE;
+                }
             },
             0x9 => {
                 let last_octal = opcode & 0xF;
                 if last_octal != 0x0 {
-                    CPU::abort();
+                    state.fault = Some(self.invalid_instruction(opcode));
+                    increment_type = PcIncrement::NONE;
                 }
-
-                if self.v[regs.1] != self.v[regs.2] {
+                else if self.v[regs.1] != self.v[regs.2] {
                     increment_type = PcIncrement::SKIP;
                 }
             },
@@ -146,140 +274,154 @@ impl CPU {
                 self.i = opcode & 0xFFF;
             },
             0xB => {
-                self.pc = (self.v[0x0] as u16) + (opcode & 0xFFF);
-                return;
+                // CHIP-48 read BXNN as a jump to XNN + VX
+                let offset = if state.quirks.jump_vx { self.v[regs.2] } else { self.v[0x0] };
+                self.pc = (offset as u16) + (opcode & 0xFFF);
+                return timing::vip_cycles(opcode, false, vx);
             },
             0xC => {
-                let mut rng = rand::thread_rng();
-                let val:u8 = rng.gen();
+                let val:u8 = self.rng.gen();
+                self.draws = self.draws.wrapping_add(1);
                 self.v[regs.2] = val & (opcode & 0xFF) as u8;
             },
             0xD => {
-                self.render_sprites_x_y(regs.2, regs.1, regs.0, memory);
+                self.render_sprites_x_y(regs.2, regs.1, regs.0, memory, state.quirks.wrap_sprites);
+                // The original interpreter only draws on the vertical blank
+                if state.quirks.display_wait {
+                    state.wait_vblank = true;
+                }
             },
             0xE => {
-                self.execute_instr_op_e(&mut increment_type, opcode, keyboard);
+                if let Err(fault) = self.execute_instr_op_e(&mut increment_type, opcode, keyboard) {
+                    state.fault = Some(fault);
+                    increment_type = PcIncrement::NONE;
+                }
             },
             0xF => {
-                self.execute_instr_op_f(opcode, memory, state);
+                if let Err(fault) = self.execute_instr_op_f(opcode, memory, keyboard, state) {
+                    state.fault = Some(fault);
+                    increment_type = PcIncrement::NONE;
+                }
             },
             _ => {
             }
         }
 
         // Handle pc increment
-        match increment_type {
-            PcIncrement::SINGLE => self.pc += 2,
-            PcIncrement::SKIP => self.pc += 4,
-            PcIncrement::NONE => {},
-        } 
+        let skipped = match increment_type {
+            PcIncrement::SINGLE => { self.pc = self.pc.wrapping_add(2); false },
+            PcIncrement::SKIP => { self.pc = self.pc.wrapping_add(4); true },
+            PcIncrement::NONE => false,
+        };
+
+        timing::vip_cycles(opcode, skipped, vx)
     }
 
     /* Maybe move that into GPU in the future? */
-    fn render_sprites_x_y(&mut self, x:usize, y:usize, n:usize, memory: &mut MemoryMap) {
+    fn render_sprites_x_y(&mut self, x:usize, y:usize, n:usize, memory: &mut MemoryMap, wrap: bool) {
+        let width = memory.get_full_vram().width();
+        let height = memory.get_full_vram().height();
         // Initial position warp, but, if it starts at 63 we dont warp
         // further pixel writes
-        let x_pos = (self.v[x] % 64) as usize;
-        let y_pos = (self.v[y] % 32) as usize;
-        let height = n;
+        let x_pos = self.v[x] as usize % width;
+        let y_pos = self.v[y] as usize % height;
 
         self.v[0xF] = 0;
 
-        for y in 0..height {
-            let byte = memory[self.i + y as u16];
-            let pixel_vec = BitVec::from_bytes(&[byte]);
-            let target_y = y + y_pos;
-            for x in 0..8 {
-                let mut bit_goal = false;
-                let target_x = x + x_pos;
-                // We only warp at the start (we break the loop and
-                // avoid warp here.
-                if target_x >= 64 || target_y >= 32 {
+        for y in 0..n {
+            let mut target_y = y + y_pos;
+            // We only warp at the start (we stop at the bottom
+            // and the framebuffer clips at the right edge),
+            // unless the ROM wants the sprites to wrap
+            if target_y >= height {
+                if !wrap {
                     break
                 }
+                target_y %= height;
+            }
 
-                let pixel = pixel_vec[x] as bool;
-                let is_set = memory.get_vram(target_x, target_y);
-
-                /* Weird pixel set behavior
-                 *
-                 * If the current pixel in the sprite row is on and the pixel at 
-                 * coordinates X,Y on the screen is also on, turn off the pixel 
-                 * and set VF to 1
-                 *
-                 * Or if the current pixel in the sprite row is on and the screen 
-                 * pixel is not, draw the pixel at the X and Y coordinates
-                 */
-                if pixel {
-                    if is_set {
-                        self.v[0xF] = 1;
-                    } else {
-                        bit_goal = true;
-                    }
-                } else if is_set {
-                    bit_goal = true;
-                }
-
-                /* We only redraw the screen if there
-                 * is a pending update.
-                 * While the main code will redraw everything
-                 * and we could improve that, this will be
-                 * left as it is now.
-                 */
-                if bit_goal != is_set {
-                    memory.pending_screen_update(true);
-                }
-
-                /* Set the bit on vram */
-                memory.set_vram(target_x, target_y, bit_goal);
+            let byte = memory.read(self.i as usize + y);
+            /* Weird pixel set behavior
+             *
+             * If the current pixel in the sprite row is on and the pixel at 
+             * coordinates X,Y on the screen is also on, turn off the pixel 
+             * and set VF to 1
+             *
+             * Or if the current pixel in the sprite row is on and the screen 
+             * pixel is not, draw the pixel at the X and Y coordinates
+             *
+             * Which is just a XOR of the whole row, the framebuffer
+             * also flags the row as dirty if anything changed
+             */
+            let vram = memory.get_full_vram_mut();
+            let collision = if wrap {
+                vram.draw_sprite_row_wrapping(x_pos, target_y, byte)
+            } else {
+                vram.draw_sprite_row(x_pos, target_y, byte)
+            };
+            if collision {
+                self.v[0xF] = 1;
             }
         }
-
     }
 
-    fn abort() {
-        println!("Invalid instruction");
-        process::abort();
+    // The machine stops on it, the PC stays on the instruction
+    fn invalid_instruction(&self, opcode: u16) -> Fault {
+        Fault::InvalidInstruction { pc: self.pc, opcode }
     }
 
-    fn execute_instr_op_8(v: &mut [u8; 0x10], opcode:u16) {
+    fn execute_instr_op_8(&mut self, opcode:u16, quirks: &Quirks) -> Result<(), Fault> {
+        let v = &mut self.v;
         let op8 = CPU::get_val_from_opcode(opcode, 0);
         let regs = (CPU::get_val_from_opcode(opcode, 2),
         CPU::get_val_from_opcode(opcode, 1));
         match op8 {
             0x0 => v[regs.0] = v[regs.1],
-            0x1 => v[regs.0] = v[regs.0] | v[regs.1],
-            0x2 => v[regs.0] = v[regs.0] & v[regs.1],
-            0x3 => v[regs.0] = v[regs.0] ^ v[regs.1],
+            0x1..=0x3 => {
+                match op8 {
+                    0x1 => v[regs.0] |= v[regs.1],
+                    0x2 => v[regs.0] &= v[regs.1],
+                    _ => v[regs.0] ^= v[regs.1],
+                }
+                if quirks.logic_reset_vf {
+                    v[0xF] = 0;
+                }
+            },
             0x4 => {
-                let tmp_sum = (v[regs.0] as u16 + v[regs.1] as u16) as u16;
+                let tmp_sum = v[regs.0] as u16 + v[regs.1] as u16;
                 v[0xF] = (tmp_sum > 0xFF) as u8;
                 v[regs.0] = tmp_sum as u8;
             },
+            // VF is 1 when there is no borrow, equal values included
             0x5 => {
-                v[0xF] = (v[regs.0] > v[regs.1]) as u8;
+                let no_borrow = v[regs.0] >= v[regs.1];
                 v[regs.0] = v[regs.0].wrapping_sub(v[regs.1]);
+                v[0xF] = no_borrow as u8;
             },
+            // The VIP shifted VY into VX, later interpreters shift VX in place
             0x6 => {
-                v[0xF] = (v[regs.0] & 0x1) as u8;
-                v[regs.0] = v[regs.0] >> 1;
+                let value = if quirks.shift_vy { v[regs.1] } else { v[regs.0] };
+                v[regs.0] = value >> 1;
+                v[0xF] = value & 0x1;
             },
+            // VX = VY - VX
             0x7 => {
-                v[0xF] = (v[regs.1] > v[regs.0]) as u8;
-                v[regs.1] = v[regs.1].wrapping_sub(v[regs.0]);
+                let no_borrow = v[regs.1] >= v[regs.0];
+                v[regs.0] = v[regs.1].wrapping_sub(v[regs.0]);
+                v[0xF] = no_borrow as u8;
             },
             /* No 0x8..0xC */
             0xE => {
-                v[0xF] = ((v[regs.0] >> 7) & 0x1) as u8;
-                v[regs.0] = v[regs.0] << 1;
+                let value = if quirks.shift_vy { v[regs.1] } else { v[regs.0] };
+                v[regs.0] = value << 1;
+                v[0xF] = (value >> 7) & 0x1;
             },
-            _ => {
-                process::abort();
-            }
+            _ => return Err(self.invalid_instruction(opcode)),
         }
+        Ok(())
     }
 
-    fn execute_instr_op_e(&mut self, increment_type: &mut PcIncrement, opcode:u16, keyboard: &mut Keyboard) {
+    fn execute_instr_op_e(&mut self, increment_type: &mut PcIncrement, opcode:u16, keyboard: &mut Keyboard) -> Result<(), Fault> {
         let sub_op_code = opcode & 0xFF;
         let reg = CPU::get_val_from_opcode(opcode, 2);
         match sub_op_code {
@@ -294,32 +436,32 @@ impl CPU {
                     *increment_type = PcIncrement::SKIP;
                 }
             },
-            _ => {
-                CPU::abort();
-            }
+            _ => return Err(self.invalid_instruction(opcode)),
         }
+        Ok(())
     }
 
-    fn execute_instr_op_f(&mut self, opcode:u16, memory: &mut MemoryMap, state: &mut BusState) {
+    fn execute_instr_op_f(&mut self, opcode:u16, memory: &mut MemoryMap, keyboard: &mut Keyboard, state: &mut BusState) -> Result<(), Fault> {
         let sub_op_code = opcode & 0xFF;
         let reg = CPU::get_val_from_opcode(opcode, 2);
         match sub_op_code {
             0x07 => self.v[reg] = state.delay,
             0x0A => {
+                keyboard.begin_key_wait(state.quirks.key_wait_release);
                 state.lock_until_pressed = true;
                 state.write_to = reg as u8;
             },
             0x15 => state.delay = self.v[reg],
             0x18 => state.sound = self.v[reg],
             0x1E => {
-                let tmp_sum = (u16::from(self.v[reg]) + self.i) as u16;
+                let tmp_sum = self.i.wrapping_add(u16::from(self.v[reg]));
                 self.v[0xF] = (tmp_sum > 0xFFF) as u8;
                 self.i = tmp_sum & 0xFFF;
             },
             0x29 => {
                 //The opcode contains the memory location for the index of the char
-                //Each char has 5 bytes, so we get the position and multiply by 5
-                self.i = u16::from(self.v[reg]) * 5;
+                //Each char has 5 bytes, starting at the font base
+                self.i = memory.font_address(self.v[reg]);
             },
             0x33 => {
                 let mut val = self.v[reg];
@@ -329,9 +471,9 @@ impl CPU {
                  * as 1, 5, 6 ON [2, 1, 0]
                  */
                 for idx in (0..3).rev() {
-                    let current_pos = (self.i + idx) as u16;
-                    memory[current_pos] = val%10;
-                    val = val/10;
+                    let current_pos = self.i as usize + idx;
+                    memory.write(current_pos, val%10);
+                    val /= 10;
                 }
             },
             0x55 => {
@@ -341,25 +483,25 @@ impl CPU {
                  */
                 let limit:u16 = reg as u16 + 1;
                 for idx in 0x0..limit {
-                    let current_pos = (self.i + idx) as u16;
-                    memory[current_pos] = self.v[idx as usize];
+                    let current_pos = self.i as usize + idx as usize;
+                    memory.write(current_pos, self.v[idx as usize]);
                 }
+                self.i = self.i.wrapping_add(state.quirks.index_increment.amount(reg as u16));
             },
             0x65 => {
                 let limit:u16 = reg as u16 + 1;
                 for idx in 0x0..limit {
-                    let current_pos = (self.i + idx) as u16;
-                    self.v[idx as usize] = memory[current_pos];
+                    let current_pos = self.i as usize + idx as usize;
+                    self.v[idx as usize] = memory.read(current_pos);
                 }
-
+                self.i = self.i.wrapping_add(state.quirks.index_increment.amount(reg as u16));
             },
-            _ => {
-                CPU::abort();
-            }
+            _ => return Err(self.invalid_instruction(opcode)),
         }
+        Ok(())
     }
 
     pub fn write_key_to(&mut self, reg: usize, key: u8) {
-        self.v[reg] = key;
+        self.v[reg & 0xF] = key;
     }
 }
@@ -0,0 +1,8 @@
+/* Things worth telling a debugger about that don't stop the
+ * machine, the Bus queues them until someone takes them.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugEvent {
+    // The ROM tried to write to the write protected interpreter area
+    ProtectedWrite { pc: u16, address: u16, value: u8 },
+}
@@ -0,0 +1,81 @@
+/* Turns opcodes back into the usual assembly mnemonics
+ * (the ones from Cowgod's technical reference), for listings
+ * and debug output
+ */
+pub fn disassemble(opcode: u16) -> String {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let nn = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            _ => format!("SYS {:#05X}", nnn),
+        },
+        0x1 => format!("JP {:#05X}", nnn),
+        0x2 => format!("CALL {:#05X}", nnn),
+        0x3 => format!("SE V{:X}, {:#04X}", x, nn),
+        0x4 => format!("SNE V{:X}, {:#04X}", x, nn),
+        0x5 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6 => format!("LD V{:X}, {:#04X}", x, nn),
+        0x7 => format!("ADD V{:X}, {:#04X}", x, nn),
+        0x8 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => data(opcode),
+        },
+        0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA => format!("LD I, {:#05X}", nnn),
+        0xB => format!("JP V0, {:#05X}", nnn),
+        0xC => format!("RND V{:X}, {:#04X}", x, nn),
+        0xD => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE => match nn {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => data(opcode),
+        },
+        0xF => match nn {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => data(opcode),
+        },
+        _ => data(opcode),
+    }
+}
+
+// Anything that isn't an instruction, most likely sprites or variables
+fn data(opcode: u16) -> String {
+    format!("DW {:#06X}", opcode)
+}
+
+// False for anything disassemble can only show as data
+pub fn is_instruction(opcode: u16) -> bool {
+    !disassemble(opcode).starts_with("DW ")
+}
+
+// The instructions that skip the next one when their condition holds
+pub fn is_skip(opcode: u16) -> bool {
+    match opcode >> 12 {
+        0x3 | 0x4 => true,
+        0x5 | 0x9 => opcode & 0xF == 0,
+        0xE => matches!(opcode & 0xFF, 0x9E | 0xA1),
+        _ => false,
+    }
+}
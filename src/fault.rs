@@ -0,0 +1,34 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/* Things a ROM can do that the real machine would have crashed
+ * (or done something weird) on, the machine stops instead of
+ * taking the emulator down and the frontend reports it.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Fault {
+    // 2NNN with every stack level in use
+    StackOverflow { pc: u16, depth: usize },
+    // 00EE with nothing on the stack
+    StackUnderflow { pc: u16 },
+    // Memory access past the end, with the fault bounds policy
+    OutOfBounds { pc: u16, address: usize },
+    // An opcode no CHIP-8 instruction uses, like 5XY1 or EX00
+    InvalidInstruction { pc: u16, opcode: u16 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow { pc, depth } =>
+                write!(f, "Stack overflow at {:#05X}, more than {} nested calls", pc, depth),
+            Fault::StackUnderflow { pc } =>
+                write!(f, "Stack underflow at {:#05X}, return without a call", pc),
+            Fault::OutOfBounds { pc, address } =>
+                write!(f, "Memory access to {:#05X} out of bounds at {:#05X}", address, pc),
+            Fault::InvalidInstruction { pc, opcode } =>
+                write!(f, "Invalid instruction {:04X} at {:#05X}", opcode, pc),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::ops::Range;
+
+/* Bit packed monochrome framebuffer.
+ *
+ * Each row is a single u128 where the most significant bit
+ * is the leftmost pixel (x = 0), this is wide enough for the
+ * 128x64 modes, while 64x32 just uses the upper half of it.
+ *
+ * Drawing a sprite row then becomes a shift to align the byte
+ * with X, an AND to detect the collision and a XOR to draw it,
+ * instead of walking 8 pixels one by one.
+ */
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    rows: Vec<u128>,
+    //Mask with only the visible columns set, used to clip
+    //sprites that go past the right edge
+    width_mask: u128,
+    changed: bool,
+    //One flag per row, so the frontend only
+    //has to upload what actually changed since the last present
+    dirty_rows: Vec<bool>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && width <= 128, "Framebuffer rows are limited to 128 pixels");
+
+        Self {
+            width,
+            height,
+            rows: vec![0; height],
+            width_mask: !0u128 << (128 - width),
+            //Everything starts dirty so the first frame
+            //uploads the whole (blank) screen
+            changed: true,
+            dirty_rows: vec![true; height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn bit(x: usize) -> u128 {
+        1u128 << (127 - x)
+    }
+
+    fn mark_dirty(&mut self, y: usize) {
+        self.dirty_rows[y] = true;
+        self.changed = true;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.rows[y] & Framebuffer::bit(x) != 0
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, set: bool) {
+        let row = if set {
+            self.rows[y] | Framebuffer::bit(x)
+        } else {
+            self.rows[y] & !Framebuffer::bit(x)
+        };
+
+        if row != self.rows[y] {
+            self.rows[y] = row;
+            self.mark_dirty(y);
+        }
+    }
+
+    // Raw row bits, leftmost pixel on the most significant bit
+    pub fn row(&self, y: usize) -> u128 {
+        self.rows[y]
+    }
+
+    /* XOR a sprite row (8 pixels, MSB first) at (x, y).
+     * Pixels past the right edge are clipped, the caller
+     * is responsible for the initial wrap of x and y.
+     * Returns true if any set pixel was turned off (collision)
+     */
+    pub fn draw_sprite_row(&mut self, x: usize, y: usize, byte: u8) -> bool {
+        let sprite = byte as u128;
+        let mask = if x <= 120 {
+            sprite << (120 - x)
+        } else {
+            sprite >> (x - 120)
+        } & self.width_mask;
+
+        self.xor_row(y, mask)
+    }
+
+    // Same, but the pixels past the right edge come back on the left
+    pub fn draw_sprite_row_wrapping(&mut self, x: usize, y: usize, byte: u8) -> bool {
+        let mut mask = 0u128;
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                mask |= Framebuffer::bit((x + bit) % self.width);
+            }
+        }
+        self.xor_row(y, mask)
+    }
+
+    fn xor_row(&mut self, y: usize, mask: u128) -> bool {
+        if mask == 0 {
+            return false;
+        }
+
+        let collision = self.rows[y] & mask != 0;
+        self.rows[y] ^= mask;
+        self.mark_dirty(y);
+        collision
+    }
+
+    pub fn clear(&mut self) {
+        for y in 0..self.height {
+            if self.rows[y] != 0 {
+                self.rows[y] = 0;
+                self.mark_dirty(y);
+            }
+        }
+    }
+
+    // Bytes each row takes on the bitmap
+    pub fn bitmap_stride(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+
+    /* Packed 1 bit per pixel, row after row, leftmost
+     * pixel on the most significant bit of each byte
+     */
+    pub fn to_bitmap(&self) -> Vec<u8> {
+        let stride = self.bitmap_stride();
+        self.rows.iter()
+            .flat_map(|row| row.to_be_bytes().to_vec().into_iter().take(stride))
+            .collect()
+    }
+
+    // Missing rows are left blank, everything gets redrawn
+    pub fn load_bitmap(&mut self, bitmap: &[u8]) {
+        let stride = self.bitmap_stride();
+        for y in 0..self.height {
+            let mut bytes = [0u8; 16];
+            if let Some(row) = bitmap.get(y * stride..(y + 1) * stride) {
+                bytes[..stride].copy_from_slice(row);
+            }
+            self.rows[y] = u128::from_be_bytes(bytes) & self.width_mask;
+            self.mark_dirty(y);
+        }
+    }
+
+    pub fn was_updated(&self) -> bool {
+        self.changed
+    }
+
+    /* Returns the rows changed since the last call, with
+     * contiguous rows merged into a single range, so the
+     * frontend can upload a whole band at once.
+     * Consuming them also resets the updated flag.
+     */
+    pub fn take_dirty_rows(&mut self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (y, dirty) in self.dirty_rows.iter_mut().enumerate() {
+            if !*dirty {
+                continue;
+            }
+            *dirty = false;
+            match ranges.last_mut() {
+                Some(last) if last.end == y => last.end = y + 1,
+                _ => ranges.push(y..y + 1),
+            }
+        }
+        self.changed = false;
+        ranges
+    }
+
+    /* Expands the given rows into RGBA8888, 4 bytes per pixel,
+     * width * rows.len() pixels in total, ready to be uploaded
+     * as a texture by any frontend
+     */
+    pub fn rows_to_rgba(&self, rows: Range<usize>, on: [u8; 4], off: [u8; 4]) -> Vec<u8> {
+        let mut pixels = vec![0; self.width * rows.len() * 4];
+        self.write_rgba(rows, on, off, &mut pixels);
+        pixels
+    }
+
+    pub fn to_rgba(&self, on: [u8; 4], off: [u8; 4]) -> Vec<u8> {
+        self.rows_to_rgba(0..self.height, on, off)
+    }
+
+    // Same as rows_to_rgba, but writing into a buffer owned by the caller
+    pub fn write_rgba(&self, rows: Range<usize>, on: [u8; 4], off: [u8; 4], out: &mut [u8]) {
+        let mut pixels = out.chunks_exact_mut(4);
+        for y in rows {
+            let mut row = self.rows[y];
+            for _ in 0..self.width {
+                let color = if row & (1 << 127) != 0 { on } else { off };
+                row <<= 1;
+                if let Some(pixel) = pixels.next() {
+                    pixel.copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
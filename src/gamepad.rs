@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::keyboard::State;
+
+// Same axes SFML reports, kept separate so the core doesn't need SFML
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+    R,
+    U,
+    V,
+    PovX,
+    PovY,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+    ButtonPressed(u32),
+    ButtonReleased(u32),
+    // Position goes from -100 to 100, like SFML
+    AxisMoved(Axis, f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ButtonBinding {
+    pub button: u32,
+    pub key: u8,
+}
+
+// Each side of an axis can drive its own CHIP-8 key
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AxisBinding {
+    pub axis: Axis,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub negative: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub positive: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GamepadPreset {
+    // Up/Left/Down/Right on 5/7/8/9 with 6 as action, what most games use
+    #[default]
+    Dpad,
+    // Up/Down on 1/4, for Pong and other paddle games
+    Paddle,
+}
+
+impl GamepadPreset {
+    fn buttons(&self) -> Vec<ButtonBinding> {
+        match self {
+            GamepadPreset::Dpad => vec![
+                ButtonBinding { button: 0, key: 0x6 },
+                ButtonBinding { button: 1, key: 0x4 },
+            ],
+            GamepadPreset::Paddle => vec![],
+        }
+    }
+
+    fn axes(&self) -> Vec<AxisBinding> {
+        // SFML reports up as negative on both the stick and the hat
+        let (up, down, left, right) = match self {
+            GamepadPreset::Dpad => (Some(0x5), Some(0x8), Some(0x7), Some(0x9)),
+            GamepadPreset::Paddle => (Some(0x1), Some(0x4), None, None),
+        };
+        let mut axes = Vec::new();
+        for (x, y) in [(Axis::X, Axis::Y), (Axis::PovX, Axis::PovY)].iter() {
+            if left.is_some() || right.is_some() {
+                axes.push(AxisBinding { axis: *x, negative: left, positive: right });
+            }
+            axes.push(AxisBinding { axis: *y, negative: up, positive: down });
+        }
+        axes
+    }
+}
+
+fn default_deadzone() -> f32 {
+    25.0
+}
+
+/* Gamepad to CHIP-8 keys, as stored on the keymap file:
+ *
+ *   [gamepad]
+ *   preset = "paddle"
+ *   deadzone = 30
+ *   buttons = [{ button = 0, key = 1 }]
+ *   axes = [{ axis = "povy", negative = 4, positive = 1 }]
+ *
+ * Buttons and axes listed replace the ones from the preset.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GamepadProfile {
+    #[serde(default)]
+    pub preset: GamepadPreset,
+    // Axis positions (0 to 100) below it are ignored
+    #[serde(default = "default_deadzone")]
+    pub deadzone: f32,
+    #[serde(default)]
+    pub buttons: Vec<ButtonBinding>,
+    #[serde(default)]
+    pub axes: Vec<AxisBinding>,
+}
+
+impl Default for GamepadProfile {
+    fn default() -> Self {
+        GamepadProfile::preset(GamepadPreset::default())
+    }
+}
+
+impl GamepadProfile {
+    pub fn preset(preset: GamepadPreset) -> Self {
+        GamepadProfile {
+            preset,
+            deadzone: default_deadzone(),
+            buttons: Vec::new(),
+            axes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Direction {
+    Negative,
+    Positive,
+}
+
+/* Turns gamepad events into CHIP-8 key presses/releases.
+ * Axes are treated as two buttons each, so it keeps
+ * which side each axis was on to release it once it moves
+ * back inside the deadzone (or to the other side).
+ */
+pub struct GamepadMapper {
+    deadzone: f32,
+    buttons: BTreeMap<u32, u8>,
+    axes: BTreeMap<Axis, AxisBinding>,
+    axis_state: BTreeMap<Axis, Direction>,
+}
+
+impl GamepadMapper {
+    pub fn new(profile: &GamepadProfile) -> Self {
+        let mut buttons = BTreeMap::new();
+        for binding in profile.preset.buttons().iter().chain(profile.buttons.iter()) {
+            buttons.insert(binding.button, binding.key & 0xF);
+        }
+        let mut axes = BTreeMap::new();
+        for binding in profile.preset.axes().iter().chain(profile.axes.iter()) {
+            axes.insert(binding.axis, *binding);
+        }
+
+        GamepadMapper {
+            deadzone: profile.deadzone.abs().min(100.0),
+            buttons,
+            axes,
+            axis_state: BTreeMap::new(),
+        }
+    }
+
+    fn axis_key(&self, axis: Axis, direction: Direction) -> Option<u8> {
+        let binding = self.axes.get(&axis)?;
+        let key = match direction {
+            Direction::Negative => binding.negative,
+            Direction::Positive => binding.positive,
+        };
+        key.map(|key| key & 0xF)
+    }
+
+    // Returns the CHIP-8 key changes the event causes, if any
+    pub fn process(&mut self, event: GamepadEvent) -> Vec<(u8, State)> {
+        let mut changes = Vec::new();
+        match event {
+            GamepadEvent::ButtonPressed(button) => {
+                if let Some(key) = self.buttons.get(&button) {
+                    changes.push((*key, State::PRESSED));
+                }
+            },
+            GamepadEvent::ButtonReleased(button) => {
+                if let Some(key) = self.buttons.get(&button) {
+                    changes.push((*key, State::RELEASED));
+                }
+            },
+            GamepadEvent::AxisMoved(axis, position) => {
+                let direction = if position > self.deadzone {
+                    Some(Direction::Positive)
+                } else if position < -self.deadzone {
+                    Some(Direction::Negative)
+                } else {
+                    None
+                };
+                let previous = self.axis_state.get(&axis).copied();
+                if direction == previous {
+                    return changes;
+                }
+
+                if let Some(key) = previous.and_then(|previous| self.axis_key(axis, previous)) {
+                    changes.push((key, State::RELEASED));
+                }
+                match direction {
+                    Some(direction) => {
+                        if let Some(key) = self.axis_key(axis, direction) {
+                            changes.push((key, State::PRESSED));
+                        }
+                        self.axis_state.insert(axis, direction);
+                    },
+                    None => {
+                        self.axis_state.remove(&axis);
+                    },
+                }
+            },
+        }
+        changes
+    }
+}
@@ -0,0 +1,441 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::bus::Bus;
+
+/* GDB remote serial protocol stub, so gdb (or lldb) can debug
+ * the running ROM over a local TCP socket:
+ *
+ *   (gdb) target remote localhost:1234
+ *
+ * It is polled by the frontend every frame, the machine only
+ * runs while the debugger says so (or nobody is attached).
+ *
+ * Registers are V0-VF (8 bits), I and PC (16 bits) and SP
+ * (8 bits), in that order, described by target.xml. There is
+ * no CHIP-8 architecture on gdb, so the description has none
+ * and numbers every register itself.
+ */
+
+const V_REGISTERS: usize = 0x10;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+
+// Stop reasons, as unix signal numbers
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+pub fn target_xml() -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\"?>\n",
+        "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n",
+        "<target version=\"1.0\">\n",
+        // gdb refuses descriptions naming an architecture it doesn't have, so the layout is spelled out
+        "  <!-- CHIP-8, no gdb architecture: v0-vf are registers 0-15, i is 16, pc 17 and sp 18,\n",
+        "       memory addresses are the CHIP-8 ones, with the bytes of the opcodes big endian -->\n",
+        "  <feature name=\"org.rusty-chip-8.cpu\">\n",
+    ));
+    for reg in 0..V_REGISTERS {
+        xml.push_str(&format!("    <reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\n", reg, reg));
+    }
+    xml.push_str(concat!(
+        "    <reg name=\"i\" bitsize=\"16\" type=\"data_ptr\" regnum=\"16\"/>\n",
+        "    <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\" regnum=\"17\"/>\n",
+        "    <reg name=\"sp\" bitsize=\"8\" type=\"uint8\" regnum=\"18\"/>\n",
+        "  </feature>\n",
+        "</target>\n",
+    ));
+    xml
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() & 1 != 0 {
+        return None;
+    }
+    (0..text.len()).step_by(2)
+        .map(|idx| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// "addr,length" as sent on m, M and qXfer
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (start, length) = text.split_once(',')?;
+    Some((parse_hex(start)?, parse_hex(length)?))
+}
+
+// Register value as gdb expects it, little endian
+fn read_register(bus: &Bus, reg: usize) -> Option<Vec<u8>> {
+    let registers = bus.registers();
+    match reg {
+        0..=0xF => Some(vec![registers.v[reg]]),
+        REG_I => Some(registers.i.to_le_bytes().to_vec()),
+        REG_PC => Some(registers.pc.to_le_bytes().to_vec()),
+        REG_SP => Some(vec![registers.sp as u8]),
+        _ => None,
+    }
+}
+
+fn write_register(bus: &mut Bus, reg: usize, value: &[u8]) -> Option<()> {
+    let mut registers = bus.registers();
+    let word = || Some(u16::from_le_bytes([*value.first()?, *value.get(1)?]));
+    match reg {
+        0..=0xF => registers.v[reg] = *value.first()?,
+        REG_I => registers.i = word()?,
+        REG_PC => registers.pc = word()?,
+        REG_SP => registers.sp = u16::from(*value.first()?),
+        _ => return None,
+    }
+    bus.set_registers(&registers);
+    Some(())
+}
+
+// Size in bytes of each register on g and G
+fn register_size(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    connection: Option<TcpStream>,
+    // Bytes received that don't make a full packet yet
+    input: Vec<u8>,
+    // The debugger let the machine run (continue)
+    running: bool,
+    no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            connection: None,
+            input: Vec::new(),
+            running: false,
+            no_ack: false,
+        })
+    }
+
+    pub fn local_port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    // Blocks until a debugger connects, so breakpoints can be set before the ROM starts
+    pub fn wait_for_debugger(&mut self) -> io::Result<()> {
+        self.listener.set_nonblocking(false)?;
+        let (stream, _) = self.listener.accept()?;
+        self.listener.set_nonblocking(true)?;
+        self.attach(stream)
+    }
+
+    fn attach(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        self.connection = Some(stream);
+        self.input.clear();
+        // The debugger expects the machine stopped when it attaches
+        self.running = false;
+        self.no_ack = false;
+        Ok(())
+    }
+
+    fn detach(&mut self) {
+        self.connection = None;
+        self.running = false;
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    // Whether the frontend should run the machine this frame
+    pub fn is_running(&self) -> bool {
+        self.running || !self.is_attached()
+    }
+
+    /* Accepts a debugger, handles what it sent and reports
+     * the machine stopping (breakpoint or fault) while running
+     */
+    pub fn poll(&mut self, bus: &mut Bus) -> io::Result<()> {
+        if self.connection.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => self.attach(stream)?,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+
+        if self.running {
+            if bus.take_breakpoint_hit().is_some() {
+                self.stop(SIGTRAP)?;
+            } else if bus.fault().is_some() {
+                self.stop(SIGSEGV)?;
+            }
+        }
+
+        let mut buffer = [0; 4096];
+        loop {
+            let read = match self.connection.as_mut() {
+                Some(connection) => connection.read(&mut buffer),
+                None => return Ok(()),
+            };
+            match read {
+                Ok(0) => {
+                    self.detach();
+                    return Ok(());
+                },
+                Ok(size) => self.input.extend_from_slice(&buffer[..size]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    self.detach();
+                    return Err(err);
+                },
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            let reply = self.handle(&packet, bus);
+            if let Some(reply) = reply {
+                self.send(&reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Takes the next full packet out of the input, acknowledging it
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.input.first() {
+                None => return Ok(None),
+                // Ctrl-C from the debugger
+                Some(0x03) => {
+                    self.input.remove(0);
+                    if self.running {
+                        self.stop(SIGINT)?;
+                    }
+                },
+                Some(b'$') => break,
+                // Acks and anything between packets
+                Some(_) => {
+                    self.input.remove(0);
+                },
+            }
+        }
+
+        let end = match self.input.iter().position(|byte| *byte == b'#') {
+            Some(end) if self.input.len() >= end + 3 => end,
+            _ => return Ok(None),
+        };
+        let data = String::from_utf8_lossy(&self.input[1..end]).into_owned();
+        let checksum = std::str::from_utf8(&self.input[end + 1..end + 3]).ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+        self.input.drain(..end + 3);
+
+        let valid = checksum == Some(data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte)));
+        if !self.no_ack {
+            self.write_raw(if valid { b"+" } else { b"-" })?;
+        }
+        // A bad packet gets sent again by the debugger
+        Ok(if valid { Some(data) } else { self.next_packet()? })
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.connection.as_mut() {
+            Some(connection) => {
+                // The socket is non blocking, but replies are small
+                connection.set_nonblocking(false)?;
+                let result = connection.write_all(bytes);
+                connection.set_nonblocking(true)?;
+                result
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.write_raw(format!("${}#{:02x}", data, checksum).as_bytes())
+    }
+
+    fn stop(&mut self, signal: u8) -> io::Result<()> {
+        self.running = false;
+        self.send(&format!("S{:02x}", signal))
+    }
+
+    fn stop_reply(bus: &Bus) -> String {
+        let signal = if bus.fault().is_some() { SIGSEGV } else { SIGTRAP };
+        format!("S{:02x}", signal)
+    }
+
+    // Reply to the packet, None when it comes later (continue)
+    fn handle(&mut self, packet: &str, bus: &mut Bus) -> Option<String> {
+        let reply = match packet.as_bytes().first()? {
+            b'?' => GdbStub::stop_reply(bus),
+            b'g' => {
+                let registers: Vec<u8> = (0..=REG_SP)
+                    .flat_map(|reg| read_register(bus, reg).unwrap_or_default())
+                    .collect();
+                to_hex(&registers)
+            },
+            b'G' => {
+                let mut values = match from_hex(&packet[1..]) {
+                    Some(values) => values,
+                    None => return Some("E01".to_owned()),
+                };
+                for reg in 0..=REG_SP {
+                    let size = register_size(reg);
+                    if values.len() < size {
+                        break;
+                    }
+                    let rest = values.split_off(size);
+                    write_register(bus, reg, &values);
+                    values = rest;
+                }
+                "OK".to_owned()
+            },
+            b'p' => {
+                match parse_hex(&packet[1..]).and_then(|reg| read_register(bus, reg)) {
+                    Some(value) => to_hex(&value),
+                    None => "E01".to_owned(),
+                }
+            },
+            b'P' => {
+                let written = packet[1..].split_once('=').and_then(|(reg, value)| {
+                    write_register(bus, parse_hex(reg)?, &from_hex(value)?)
+                });
+                match written {
+                    Some(()) => "OK".to_owned(),
+                    None => "E01".to_owned(),
+                }
+            },
+            b'm' => {
+                match parse_range(&packet[1..]) {
+                    Some((start, length)) => {
+                        // Reads past the end go around, never more than the whole memory
+                        let size = bus.memory.size();
+                        let bytes: Vec<u8> = (0..length.min(size))
+                            .map(|offset| bus.memory[(start.wrapping_add(offset) % size) as u16])
+                            .collect();
+                        to_hex(&bytes)
+                    },
+                    None => "E01".to_owned(),
+                }
+            },
+            b'M' => {
+                let written = packet[1..].split_once(':').and_then(|(range, data)| {
+                    let (start, length) = parse_range(range)?;
+                    let data = from_hex(data)?;
+                    if data.len() != length {
+                        return None;
+                    }
+                    // The debugger goes around the write protection
+                    let size = bus.memory.size();
+                    for (offset, byte) in data.iter().take(size).enumerate() {
+                        bus.memory[(start.wrapping_add(offset) % size) as u16] = *byte;
+                    }
+                    Some(())
+                });
+                match written {
+                    Some(()) => "OK".to_owned(),
+                    None => "E01".to_owned(),
+                }
+            },
+            b'Z' | b'z' => {
+                // Only software breakpoints (type 0) and hardware ones (1) which are the same here
+                let mut fields = packet[1..].split(',');
+                let kind = fields.next();
+                let address = fields.next().and_then(parse_hex);
+                match (kind, address) {
+                    (Some("0"), Some(address)) | (Some("1"), Some(address)) => {
+                        if packet.starts_with('Z') {
+                            bus.add_breakpoint(address as u16);
+                        } else {
+                            bus.remove_breakpoint(address as u16);
+                        }
+                        "OK".to_owned()
+                    },
+                    _ => String::new(),
+                }
+            },
+            b's' => {
+                self.resume_at(&packet[1..], bus);
+                bus.step();
+                GdbStub::stop_reply(bus)
+            },
+            b'c' => {
+                self.resume_at(&packet[1..], bus);
+                // Get past the breakpoint it is stopped at, if any
+                bus.step();
+                bus.take_breakpoint_hit();
+                self.running = true;
+                return None;
+            },
+            b'D' => {
+                let _ = self.send("OK");
+                self.detach();
+                return None;
+            },
+            b'k' => {
+                self.detach();
+                return None;
+            },
+            b'H' => "OK".to_owned(),
+            b'q' | b'Q' => self.handle_query(packet),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    // s and c can carry the address to resume from
+    fn resume_at(&self, address: &str, bus: &mut Bus) {
+        if let Some(address) = parse_hex(address) {
+            let mut registers = bus.registers();
+            registers.pc = address as u16;
+            bus.set_registers(&registers);
+        }
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_owned()
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_owned()
+        } else if packet == "qAttached" {
+            "1".to_owned()
+        } else if packet == "qC" {
+            "QC1".to_owned()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_owned()
+        } else if packet == "qsThreadInfo" {
+            "l".to_owned()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = target_xml();
+            match parse_range(range) {
+                Some((offset, length)) => {
+                    let chunk: String = xml.chars().skip(offset).take(length).collect();
+                    // l marks the last chunk
+                    let marker = if offset + chunk.len() >= xml.len() { 'l' } else { 'm' };
+                    format!("{}{}", marker, chunk)
+                },
+                None => "E01".to_owned(),
+            }
+        } else {
+            String::new()
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::Bus;
+use crate::keyboard::State;
+use crate::machine::MachineConfig;
+
+// Frames are always 1/60 of a second, what the timers tick at
+const FRAME: f64 = 1.0 / 60.0;
+
+// The display, one byte per pixel (1 when lit), row after row
+pub type Observation = Vec<u8>;
+
+/* What the agent can do, every action is the set of keys
+ * held until the next step, action 0 is always no key at all.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActionSet {
+    actions: Vec<Vec<u8>>,
+}
+
+impl ActionSet {
+    // Nothing, then each key on its own, 17 actions
+    pub fn all_keys() -> Self {
+        ActionSet::from_keys(&(0x0..=0xF).collect::<Vec<u8>>())
+    }
+
+    // Nothing, then each of the keys on its own
+    pub fn from_keys(keys: &[u8]) -> Self {
+        let mut actions = vec![Vec::new()];
+        actions.extend(keys.iter().map(|key| vec![key & 0xF]));
+        ActionSet { actions }
+    }
+
+    // Any combination of keys, nothing is added in front
+    pub fn new(actions: Vec<Vec<u8>>) -> Self {
+        ActionSet { actions }
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn keys(&self, action: usize) -> &[u8] {
+        &self.actions[action]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreEncoding {
+    // Bytes of a big endian number
+    Binary,
+    // One decimal digit per byte, most significant first, what FX33 writes
+    Bcd,
+}
+
+/* Where a game keeps its score, the reward of a step
+ * is how much the score went up (or down) during it
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreReader {
+    pub addresses: Vec<u16>,
+    pub encoding: ScoreEncoding,
+}
+
+impl ScoreReader {
+    pub fn new(addresses: Vec<u16>, encoding: ScoreEncoding) -> Self {
+        ScoreReader { addresses, encoding }
+    }
+
+    pub fn read(&self, bus: &Bus) -> f64 {
+        self.addresses.iter().fold(0.0, |score, address| {
+            let byte = f64::from(bus.memory[*address]);
+            match self.encoding {
+                ScoreEncoding::Binary => score * 256.0 + byte,
+                ScoreEncoding::Bcd => score * 10.0 + byte,
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EnvConfig {
+    pub machine: MachineConfig,
+    // Frames run for every step, with the same keys held
+    pub frame_skip: u32,
+    pub actions: ActionSet,
+    // No score means every reward is 0
+    pub score: Option<ScoreReader>,
+    // The game is over once this address holds this value
+    pub game_over: Option<(u16, u8)>,
+    // Episodes are cut after this many frames
+    pub max_frames: Option<u64>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        EnvConfig {
+            machine: MachineConfig::default(),
+            frame_skip: 4,
+            actions: ActionSet::all_keys(),
+            score: None,
+            game_over: None,
+            max_frames: None,
+        }
+    }
+}
+
+impl EnvConfig {
+    pub fn with_machine(mut self, machine: MachineConfig) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    pub fn with_frame_skip(mut self, frame_skip: u32) -> Self {
+        self.frame_skip = frame_skip.max(1);
+        self
+    }
+
+    pub fn with_actions(mut self, actions: ActionSet) -> Self {
+        self.actions = actions;
+        self
+    }
+
+    pub fn with_score(mut self, score: ScoreReader) -> Self {
+        self.score = Some(score);
+        self
+    }
+
+    pub fn with_game_over(mut self, address: u16, value: u8) -> Self {
+        self.game_over = Some((address, value));
+        self
+    }
+
+    pub fn with_max_frames(mut self, max_frames: u64) -> Self {
+        self.max_frames = Some(max_frames);
+        self
+    }
+}
+
+/* A ROM as a reinforcement learning environment, the usual
+ * reset / step loop:
+ *
+ *   let mut env = Env::new(&rom, EnvConfig::default())?;
+ *   let mut observation = env.reset();
+ *   loop {
+ *       let (next, reward, done) = env.step(agent.act(&observation));
+ *       ...
+ *   }
+ *
+ * The episode is done when the game is over, the frame limit is
+ * reached or the machine stopped on a fault.
+ */
+pub struct Env {
+    bus: Bus,
+    config: EnvConfig,
+    held: Vec<u8>,
+    score: f64,
+    frames: u64,
+}
+
+impl Env {
+    // Fails when the machine config can't be built
+    pub fn new(rom: &[u8], config: EnvConfig) -> io::Result<Self> {
+        let mut env = Env {
+            bus: Bus::with_config(rom, config.machine.clone())?,
+            config,
+            held: Vec::new(),
+            score: 0.0,
+            frames: 0,
+        };
+        env.reset();
+        Ok(env)
+    }
+
+    // The machine, to look at or poke between steps
+    pub fn bus(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    pub fn actions(&self) -> &ActionSet {
+        &self.config.actions
+    }
+
+    // Frames run since the last reset
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Powers the machine back on, with the same seed if the config has one
+    pub fn reset(&mut self) -> Observation {
+        self.bus.reset();
+        self.held.clear();
+        self.frames = 0;
+        self.score = self.read_score();
+        self.observation()
+    }
+
+    pub fn step(&mut self, action: usize) -> (Observation, f64, bool) {
+        self.hold(action);
+        for _ in 0..self.config.frame_skip {
+            if self.is_done() {
+                break;
+            }
+            self.bus.run_for(Duration::from_secs_f64(FRAME));
+            self.frames += 1;
+        }
+
+        let score = self.read_score();
+        let reward = score - self.score;
+        self.score = score;
+        (self.observation(), reward, self.is_done())
+    }
+
+    pub fn observation(&self) -> Observation {
+        let vram = self.bus.get_vram();
+        let mut pixels = Vec::with_capacity(vram.width() * vram.height());
+        for y in 0..vram.height() {
+            for x in 0..vram.width() {
+                pixels.push(vram.get(x, y) as u8);
+            }
+        }
+        pixels
+    }
+
+    pub fn is_done(&self) -> bool {
+        if self.bus.fault().is_some() {
+            return true;
+        }
+        if let Some((address, value)) = self.config.game_over {
+            if self.bus.memory[address] == value {
+                return true;
+            }
+        }
+        self.config.max_frames.is_some_and(|max_frames| self.frames >= max_frames)
+    }
+
+    fn read_score(&self) -> f64 {
+        self.config.score.as_ref().map_or(0.0, |score| score.read(&self.bus))
+    }
+
+    // Releases what the action doesn't hold and presses what it does
+    fn hold(&mut self, action: usize) {
+        let keys = self.config.actions.keys(action).to_vec();
+        let time = self.bus.time();
+        for key in &self.held {
+            if !keys.contains(key) {
+                self.bus.queue_key(*key, State::RELEASED, time);
+            }
+        }
+        for key in &keys {
+            if !self.held.contains(key) {
+                self.bus.queue_key(*key, State::PRESSED, time);
+            }
+        }
+        self.held = keys;
+    }
+}
+
+/* Many copies of the same environment stepped together, spread
+ * over threads. With a seed on the machine config every copy gets
+ * its own one (seed, seed + 1, ...) so they don't all play the same.
+ */
+pub struct VecEnv {
+    envs: Vec<Env>,
+    #[cfg_attr(not(feature = "threads"), allow(dead_code))]
+    threads: usize,
+}
+
+impl VecEnv {
+    pub fn new(rom: &[u8], config: EnvConfig, count: usize) -> io::Result<Self> {
+        let envs = (0..count)
+            .map(|index| {
+                let mut config = config.clone();
+                config.machine.seed = config.machine.seed.map(|seed| seed.wrapping_add(index as u32));
+                Env::new(rom, config)
+            })
+            .collect::<io::Result<_>>()?;
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        Ok(VecEnv { envs, threads })
+    }
+
+    // 1 runs everything on the calling thread
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envs.is_empty()
+    }
+
+    pub fn env(&mut self, index: usize) -> &mut Env {
+        &mut self.envs[index]
+    }
+
+    pub fn reset(&mut self) -> Vec<Observation> {
+        self.envs.iter_mut().map(Env::reset).collect()
+    }
+
+    // Done environments are left alone, reset them through env()
+    #[cfg(feature = "threads")]
+    pub fn step(&mut self, actions: &[usize]) -> Vec<(Observation, f64, bool)> {
+        assert_eq!(actions.len(), self.envs.len(), "One action per environment");
+        let chunk = self.envs.len().div_ceil(self.threads).max(1);
+        let mut results = Vec::with_capacity(self.envs.len());
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = self.envs.chunks_mut(chunk)
+                .zip(actions.chunks(chunk))
+                .map(|(envs, actions)| scope.spawn(move || {
+                    envs.iter_mut().zip(actions).map(|(env, action)| env.step(*action)).collect::<Vec<_>>()
+                }))
+                .collect();
+            for worker in workers {
+                results.extend(worker.join().expect("Environment thread panicked"));
+            }
+        });
+        results
+    }
+
+    // Without threads (wasm) everything runs on the calling thread
+    #[cfg(not(feature = "threads"))]
+    pub fn step(&mut self, actions: &[usize]) -> Vec<(Observation, f64, bool)> {
+        assert_eq!(actions.len(), self.envs.len(), "One action per environment");
+        self.envs.iter_mut().zip(actions).map(|(env, action)| env.step(*action)).collect()
+    }
+}
@@ -1,45 +1,247 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::gamepad::Axis;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Keyboard {
     keys: [State; 0x10],
     last_pressed_key: u8,
     key_pressed: bool,
+    wait: KeyWait,
+    //Events waiting for the emulated time to reach them
+    events: VecDeque<InputEvent>,
+    //Keys pressed that EX9E/EXA1 didn't look at yet
+    unseen: u16,
+    //Unseen taps that were already there on the last timer tick
+    #[serde(default)]
+    stale: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputEvent {
+    pub key: u8,
+    pub state: State,
+    //Emulated time (since power on) the CPU should see it
+    pub time: Duration,
+}
+
+/* FX0A state machine.
+ *
+ * Keys already held when the wait started are ignored until they
+ * are released, otherwise a key held from a previous FX0A would
+ * be read again right away.
+ * The original COSMAC VIP only returns once the key is released,
+ * later interpreters return as soon as it is pressed.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum KeyWait {
+    Idle,
+    WaitingPress { ignored: u16, on_release: bool },
+    WaitingRelease { key: u8 },
+    Done { key: u8 },
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum State{
     PRESSED,
     RELEASED,
 }
 
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Keyboard{
     pub fn new() -> Keyboard {
         Keyboard {
             keys: [State::RELEASED; 0x10],
             key_pressed: false,
             last_pressed_key: 0,
+            wait: KeyWait::Idle,
+            events: VecDeque::new(),
+            unseen: 0,
+            stale: 0,
         }
     }
     pub fn reset_key_press(&mut self) {
         self.key_pressed = false;
     }
 
+    // Only the low nibble counts, there are 16 keys
     pub fn process_key (&mut self, key: usize, state: State) {
+        let key = key & 0xF;
         if state == State::PRESSED {
             self.key_pressed = true;
+            self.last_pressed_key = key as u8;
+            self.unseen |= 1 << key;
+            self.stale &= !(1 << key);
         }
-        
-        self.last_pressed_key = key as u8;
+
+        let was_pressed = self.keys[key] == State::PRESSED;
         self.keys[key] = state;
+        self.update_wait(key as u8, was_pressed, state);
+    }
+
+    fn update_wait(&mut self, key: u8, was_pressed: bool, state: State) {
+        let bit = 1u16 << (key & 0xF);
+        self.wait = match (self.wait, state) {
+            // Repeated press events (key repeat) are not new presses
+            (KeyWait::WaitingPress { ignored, on_release }, State::PRESSED) if ignored & bit == 0 && !was_pressed => {
+                if on_release {
+                    KeyWait::WaitingRelease { key }
+                } else {
+                    KeyWait::Done { key }
+                }
+            },
+            (KeyWait::WaitingPress { ignored, on_release }, State::RELEASED) => {
+                KeyWait::WaitingPress { ignored: ignored & !bit, on_release }
+            },
+            (KeyWait::WaitingRelease { key: waited }, State::RELEASED) if waited == key => {
+                KeyWait::Done { key }
+            },
+            (wait, _) => wait,
+        };
+    }
+
+    // Starts a FX0A wait, on_release selects the COSMAC VIP behaviour
+    pub fn begin_key_wait(&mut self, on_release: bool) {
+        let mut ignored = 0u16;
+        for (key, state) in self.keys.iter().enumerate() {
+            if *state == State::PRESSED {
+                ignored |= 1 << key;
+            }
+        }
+        self.wait = KeyWait::WaitingPress { ignored, on_release };
+    }
+
+    pub fn key_wait(&self) -> KeyWait {
+        self.wait
+    }
+
+    // Returns the key once the wait completed, going back to idle
+    pub fn take_waited_key(&mut self) -> Option<u8> {
+        match self.wait {
+            KeyWait::Done { key } => {
+                self.wait = KeyWait::Idle;
+                Some(key & 0xF)
+            },
+            _ => None,
+        }
     }
 
     pub fn was_any_key_pressed(&mut self) -> bool {
         self.key_pressed
     }
 
+    /* A key that was pressed and released before the ROM polled it
+     * still reads as pressed once, otherwise short taps between two
+     * polls would be lost
+     */
     pub fn is_key_pressed(&mut self, key: usize) -> bool {
-        self.keys[key] == State::PRESSED
+        let key = key & 0xF;
+        let unseen = self.unseen & (1 << key) != 0;
+        self.unseen &= !(1 << key);
+        self.stale &= !(1 << key);
+        self.keys[key] == State::PRESSED || unseen
+    }
+
+    /* Called on every 60hz timer tick, a tap nobody polled for a
+     * whole frame is dropped, so a press during a menu doesn't
+     * show up later in the game
+     */
+    pub fn expire_taps(&mut self) {
+        self.unseen &= !self.stale;
+        self.stale = self.unseen;
+    }
+
+    // Events are kept in time order, ties keep the order they were queued
+    pub fn queue_event(&mut self, event: InputEvent) {
+        let idx = self.events.iter()
+            .position(|queued| queued.time > event.time)
+            .unwrap_or(self.events.len());
+        self.events.insert(idx, event);
+    }
+
+    pub fn pending_events(&self) -> usize {
+        self.events.len()
+    }
+
+    // Applies every queued event that happened up to the given time
+    pub fn apply_events_until(&mut self, time: Duration) {
+        while let Some(event) = self.events.front() {
+            if event.time > time {
+                break;
+            }
+            let event = *event;
+            self.events.pop_front();
+            self.process_key(event.key as usize, event.state);
+        }
     }
 
     pub fn get_last_pressed_key(&mut self) -> u8 {
         self.last_pressed_key
     }
 }
+
+// Anything on the host that can hold a CHIP-8 key down
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KeySource {
+    // Host keyboard key, by name
+    Key(String),
+    GamepadButton(u32),
+    // Each axis only holds one side at a time
+    GamepadAxis(Axis),
+}
+
+/* Frontends can map many sources to the same CHIP-8 key (two
+ * host keys, the keyboard and the gamepad, the stick and the hat),
+ * so each key remembers who is holding it and is only released
+ * once the last one lets go.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct HeldKeys {
+    sources: [BTreeSet<KeySource>; 0x10],
+}
+
+impl HeldKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* Returns what the CHIP-8 key should see, None when it doesn't
+     * change (already held by someone else, or a key repeat)
+     */
+    pub fn update(&mut self, source: KeySource, key: u8, state: State) -> Option<State> {
+        let sources = &mut self.sources[(key & 0xF) as usize];
+        match state {
+            State::PRESSED => {
+                let first = sources.is_empty();
+                sources.insert(source);
+                if first { Some(State::PRESSED) } else { None }
+            },
+            State::RELEASED => {
+                if sources.remove(&source) && sources.is_empty() {
+                    Some(State::RELEASED)
+                } else {
+                    None
+                }
+            },
+        }
+    }
+
+    pub fn is_held(&self, key: u8) -> bool {
+        !self.sources[(key & 0xF) as usize].is_empty()
+    }
+
+    // Forgets every source, the caller releases the keys
+    pub fn clear(&mut self) {
+        for sources in self.sources.iter_mut() {
+            sources.clear();
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::gamepad::GamepadProfile;
+
+/* CHIP-8 keys in the order they appear on the COSMAC VIP keypad:
+ *  1 2 3 C
+ *  4 5 6 D
+ *  7 8 9 E
+ *  A 0 B F
+ */
+pub const KEYPAD: [u8; 0x10] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Dvorak,
+}
+
+impl Layout {
+    /* Host keys sitting on the same physical 4x4 block (left side
+     * of the keyboard) for each layout, in KEYPAD order.
+     * Names are the host key names the frontend reports.
+     */
+    fn host_keys(&self) -> [&'static str; 0x10] {
+        match self {
+            Layout::Qwerty => [
+                "Num1", "Num2", "Num3", "Num4",
+                "Q", "W", "E", "R",
+                "A", "S", "D", "F",
+                "Z", "X", "C", "V",
+            ],
+            Layout::Azerty => [
+                "Num1", "Num2", "Num3", "Num4",
+                "A", "Z", "E", "R",
+                "Q", "S", "D", "F",
+                "W", "X", "C", "V",
+            ],
+            Layout::Dvorak => [
+                "Num1", "Num2", "Num3", "Num4",
+                "Quote", "Comma", "Period", "P",
+                "A", "O", "E", "U",
+                "SemiColon", "Q", "J", "K",
+            ],
+        }
+    }
+}
+
+/* Host key name to CHIP-8 key.
+ * A CHIP-8 key can have as many host keys as wanted,
+ * but each host key only drives a single CHIP-8 key.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyMap {
+    bindings: BTreeMap<String, u8>,
+}
+
+impl KeyMap {
+    pub fn preset(layout: Layout) -> KeyMap {
+        let mut keymap = KeyMap::default();
+        for (host, key) in layout.host_keys().iter().zip(KEYPAD.iter()) {
+            keymap.bind(host, *key);
+        }
+        keymap
+    }
+
+    pub fn lookup(&self, host: &str) -> Option<u8> {
+        self.bindings.get(host).copied()
+    }
+
+    // Binding an already bound host key moves it to the new CHIP-8 key
+    pub fn bind(&mut self, host: &str, key: u8) {
+        self.bindings.insert(host.to_owned(), key & 0xF);
+    }
+
+    // Removes every host key bound to the CHIP-8 key
+    pub fn clear_key(&mut self, key: u8) {
+        self.bindings.retain(|_, bound| *bound != key);
+    }
+
+    pub fn host_keys(&self, key: u8) -> Vec<&str> {
+        self.bindings.iter()
+            .filter(|(_, bound)| **bound == key)
+            .map(|(host, _)| host.as_str())
+            .collect()
+    }
+
+    // Applies the bindings as stored in the config file, each listed
+    // CHIP-8 key gets its previous host keys replaced
+    fn apply(&mut self, bindings: &Bindings) -> io::Result<()> {
+        for (key, hosts) in bindings {
+            let key = parse_key(key)?;
+            self.clear_key(key);
+            for host in hosts {
+                self.bind(host, key);
+            }
+        }
+        Ok(())
+    }
+
+    fn to_bindings(&self) -> Bindings {
+        KEYPAD.iter()
+            .map(|key| {
+                let hosts = self.host_keys(*key).iter().map(|host| host.to_string()).collect();
+                (format!("{:X}", key), hosts)
+            })
+            .collect()
+    }
+}
+
+// CHIP-8 key as a hex digit ("0" to "F") to the host keys bound to it
+type Bindings = BTreeMap<String, Vec<String>>;
+
+fn parse_key(key: &str) -> io::Result<u8> {
+    match u8::from_str_radix(key, 16) {
+        Ok(key) if key <= 0xF => Ok(key),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid CHIP-8 key {}", key))),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RomKeymap {
+    // Falls back to the global layout if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+    #[serde(default)]
+    pub bindings: Bindings,
+    // Replaces the global gamepad profile for this ROM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gamepad: Option<GamepadProfile>,
+}
+
+/* Keymap config file, something like:
+ *
+ *   layout = "azerty"
+ *
+ *   [bindings]
+ *   5 = ["Z", "Up"]
+ *
+ *   [roms."pong.ch8".bindings]
+ *   1 = ["W", "Up"]
+ *   4 = ["S", "Down"]
+ *
+ *   [roms."pong.ch8".gamepad]
+ *   preset = "paddle"
+ *
+ * Bindings on the top level apply to every ROM, the ones
+ * under roms only when that ROM (by file name) is loaded.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeymapConfig {
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub bindings: Bindings,
+    #[serde(default)]
+    pub gamepad: GamepadProfile,
+    #[serde(default)]
+    pub roms: BTreeMap<String, RomKeymap>,
+}
+
+impl KeymapConfig {
+    // A missing file is not an error, it just means the defaults
+    pub fn load(path: &Path) -> io::Result<KeymapConfig> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(KeymapConfig::default()),
+            Err(err) => return Err(err),
+        };
+        KeymapConfig::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> io::Result<KeymapConfig> {
+        let config: KeymapConfig = toml::from_str(text)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        // Validate now instead of failing once a ROM is loaded
+        config.keymap_for("")?;
+        for rom in config.roms.keys() {
+            config.keymap_for(rom)?;
+        }
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        fs::write(path, text)
+    }
+
+    pub fn keymap_for(&self, rom: &str) -> io::Result<KeyMap> {
+        let overrides = self.roms.get(rom);
+        let layout = overrides.and_then(|rom| rom.layout).unwrap_or(self.layout);
+
+        let mut keymap = KeyMap::preset(layout);
+        keymap.apply(&self.bindings)?;
+        if let Some(overrides) = overrides {
+            keymap.apply(&overrides.bindings)?;
+        }
+        Ok(keymap)
+    }
+
+    pub fn gamepad_for(&self, rom: &str) -> GamepadProfile {
+        self.rom_gamepad(rom).unwrap_or_else(|| self.gamepad.clone())
+    }
+
+    // Only the profile set for this ROM, without falling back to the global one
+    pub fn rom_gamepad(&self, rom: &str) -> Option<GamepadProfile> {
+        self.roms.get(rom).and_then(|rom| rom.gamepad.clone())
+    }
+
+    // Stores the whole keymap as the override for the ROM
+    pub fn set_rom_keymap(&mut self, rom: &str, keymap: &KeyMap) {
+        let overrides = self.roms.entry(rom.to_owned()).or_default();
+        overrides.layout = None;
+        overrides.bindings = keymap.to_bindings();
+    }
+}
@@ -0,0 +1,32 @@
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod bus;
+pub mod busstate;
+pub mod cheats;
+pub mod coverage;
+pub mod cpu;
+pub mod debug;
+pub mod disasm;
+pub mod fault;
+pub mod font;
+pub mod framebuffer;
+pub mod gamepad;
+#[cfg(feature = "net")]
+pub mod gdb;
+pub mod gym;
+pub mod keyboard;
+#[cfg(feature = "files")]
+pub mod keymap;
+pub mod machine;
+pub mod memory;
+pub mod octo;
+pub mod profiler;
+pub mod quirks;
+#[cfg(feature = "files")]
+pub mod rom;
+#[cfg(feature = "files")]
+pub mod romdb;
+#[cfg(feature = "net")]
+pub mod rpc;
+pub mod savestate;
+pub mod timing;
@@ -0,0 +1,345 @@
+#[cfg(feature = "files")]
+use std::fs;
+use std::io;
+#[cfg(feature = "files")]
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::font::FontSet;
+use crate::memory::{BoundsPolicy, MemoryLayout};
+use crate::quirks::{IndexIncrement, Quirks};
+#[cfg(feature = "files")]
+use crate::romdb::RomInfo;
+use crate::timing::{ClockRate, TimingMode};
+
+// Addresses are 16 bits, so there is no point going over it
+pub const MAX_MEMORY_SIZE: usize = 0x10000;
+// SUPER-CHIP high resolution, anything bigger is a mistake (or a crafted save state)
+pub const MAX_DISPLAY_WIDTH: usize = 128;
+pub const MAX_DISPLAY_HEIGHT: usize = 64;
+pub const MAX_STACK_DEPTH: usize = 256;
+
+/* The interpreters this emulator knows how to behave like,
+ * they only pick the defaults, every value can still be changed.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    // What this emulator always did, close to most modern interpreters
+    #[default]
+    Chip8,
+    // The original COSMAC VIP interpreter
+    Vip,
+    Dream6800,
+    // Programs load at 0x600
+    Eti660,
+    Chip48,
+    SuperChip,
+}
+
+/* Everything needed to build a machine, as stored on TOML:
+ *
+ *   variant = "vip"
+ *   stack_depth = 12
+ *   seed = 1234
+ *
+ *   [rate]
+ *   instructions_per_frame = 15
+ *
+ *   [layout]
+ *   load_address = 512
+ *   entry_pc = 512
+ *   font_base = 0
+ *   font = "vip"
+ *
+ * Anything left out takes the default of the variant.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MachineConfig {
+    pub variant: Variant,
+    pub memory_size: usize,
+    // How many nested subroutine calls fit
+    pub stack_depth: usize,
+    pub display_width: usize,
+    pub display_height: usize,
+    pub quirks: Quirks,
+    pub rate: ClockRate,
+    pub mode: TimingMode,
+    // Random numbers (CXNN) repeat between runs when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>,
+    pub layout: MemoryLayout,
+    // Accesses past the end of the memory
+    pub bounds_policy: BoundsPolicy,
+    // Drops ROM writes to 0x000-0x1FF, raising a debug event
+    pub write_protect: bool,
+}
+
+/* Settings picked by hand (on the command line), they go on top
+ * of the machine file and the ROM database, unset ones keep
+ * whatever those said
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigOverrides {
+    pub rate: Option<ClockRate>,
+    pub mode: Option<TimingMode>,
+    pub seed: Option<u32>,
+    pub bounds_policy: Option<BoundsPolicy>,
+    // Quirks can only be turned on
+    pub display_wait: bool,
+    pub key_wait_release: bool,
+    pub write_protect: bool,
+    // Moves both the ROM and the entry PC
+    pub load_address: Option<u16>,
+    pub entry_pc: Option<u16>,
+    pub font_base: Option<u16>,
+    pub font: Option<FontSet>,
+}
+
+impl ConfigOverrides {
+    pub fn apply(&self, config: &mut MachineConfig) {
+        let layout = &mut config.layout;
+        if let Some(load_address) = self.load_address {
+            layout.load_address = load_address;
+            layout.entry_pc = load_address;
+        }
+        if let Some(entry_pc) = self.entry_pc {
+            layout.entry_pc = entry_pc;
+        }
+        if let Some(font_base) = self.font_base {
+            layout.font_base = font_base;
+        }
+        if let Some(font) = self.font {
+            layout.font = font;
+        }
+
+        config.quirks.display_wait |= self.display_wait;
+        config.quirks.key_wait_release |= self.key_wait_release;
+        if let Some(rate) = self.rate {
+            config.rate = rate;
+        }
+        if let Some(mode) = self.mode {
+            config.mode = mode;
+        }
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+        if let Some(bounds_policy) = self.bounds_policy {
+            config.bounds_policy = bounds_policy;
+        }
+        config.write_protect |= self.write_protect;
+    }
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        MachineConfig::new(Variant::default())
+    }
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+impl MachineConfig {
+    // Defaults for the variant
+    pub fn new(variant: Variant) -> Self {
+        let mut config = MachineConfig {
+            variant,
+            memory_size: 0x1000,
+            stack_depth: 16,
+            display_width: 64,
+            display_height: 32,
+            quirks: Quirks::default(),
+            rate: ClockRate::default(),
+            mode: TimingMode::default(),
+            seed: None,
+            layout: MemoryLayout::default(),
+            bounds_policy: BoundsPolicy::default(),
+            write_protect: false,
+        };
+
+        match variant {
+            Variant::Chip8 => {},
+            Variant::Vip => {
+                // The VIP interpreter only had room for 12 return addresses
+                config.stack_depth = 12;
+                config.quirks = Quirks {
+                    display_wait: true,
+                    key_wait_release: true,
+                    shift_vy: true,
+                    logic_reset_vf: true,
+                    index_increment: IndexIncrement::ByXPlusOne,
+                    ..Quirks::default()
+                };
+                config.rate = ClockRate::InstructionsPerFrame(15);
+                config.mode = TimingMode::VipCycles;
+                config.layout.font = FontSet::Vip;
+            },
+            Variant::Dream6800 => config.layout.font = FontSet::Dream6800,
+            Variant::Eti660 => config.layout = MemoryLayout::eti660(),
+            Variant::Chip48 => {
+                config.quirks.jump_vx = true;
+                config.quirks.index_increment = IndexIncrement::ByX;
+                config.rate = ClockRate::InstructionsPerFrame(30);
+            },
+            Variant::SuperChip => {
+                config.quirks.jump_vx = true;
+                config.rate = ClockRate::InstructionsPerFrame(30);
+            },
+        }
+        config
+    }
+
+    /* Machine file first (or the defaults of the variant, picked
+     * by hand or by the ROM platform), then the ROM database,
+     * then the overrides
+     */
+    #[cfg(feature = "files")]
+    pub fn resolve(file: Option<MachineConfig>, variant: Option<Variant>, rom_info: Option<&RomInfo>,
+                   overrides: &ConfigOverrides) -> MachineConfig {
+        let mut config = file.unwrap_or_else(|| {
+            let platform = rom_info.and_then(|rom_info| rom_info.variant());
+            MachineConfig::new(variant.or(platform).unwrap_or_default())
+        });
+        if let Some(rom_info) = rom_info {
+            rom_info.apply_config(&mut config);
+        }
+        overrides.apply(&mut config);
+        config
+    }
+
+    pub fn with_memory_size(mut self, memory_size: usize) -> Self {
+        self.memory_size = memory_size;
+        self
+    }
+
+    pub fn with_stack_depth(mut self, stack_depth: usize) -> Self {
+        self.stack_depth = stack_depth;
+        self
+    }
+
+    pub fn with_display(mut self, width: usize, height: usize) -> Self {
+        self.display_width = width;
+        self.display_height = height;
+        self
+    }
+
+    pub fn with_quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    pub fn with_rate(mut self, rate: ClockRate) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: TimingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_layout(mut self, layout: MemoryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_font(mut self, font: FontSet) -> Self {
+        self.layout.font = font;
+        self
+    }
+
+    // Moves both where the ROM is loaded and where it starts running
+    pub fn with_load_address(mut self, address: u16) -> Self {
+        self.layout.load_address = address;
+        self.layout.entry_pc = address;
+        self
+    }
+
+    pub fn with_bounds_policy(mut self, bounds_policy: BoundsPolicy) -> Self {
+        self.bounds_policy = bounds_policy;
+        self
+    }
+
+    pub fn with_write_protect(mut self, write_protect: bool) -> Self {
+        self.write_protect = write_protect;
+        self
+    }
+
+    // Catches values the machine can't be built with
+    pub fn validate(&self) -> io::Result<()> {
+        if self.memory_size < 0x200 || self.memory_size > MAX_MEMORY_SIZE {
+            return Err(invalid_data(format!("Memory size {:#X} is not between 0x200 and {:#X}",
+                self.memory_size, MAX_MEMORY_SIZE)));
+        }
+        if self.stack_depth == 0 || self.stack_depth > MAX_STACK_DEPTH {
+            return Err(invalid_data(format!("Stack depth {} is not between 1 and {}", self.stack_depth, MAX_STACK_DEPTH)));
+        }
+        if self.display_width == 0 || self.display_width > MAX_DISPLAY_WIDTH
+            || self.display_height == 0 || self.display_height > MAX_DISPLAY_HEIGHT {
+            return Err(invalid_data(format!("Display size {}x{} is not supported, the most is {}x{}",
+                self.display_width, self.display_height, MAX_DISPLAY_WIDTH, MAX_DISPLAY_HEIGHT)));
+        }
+        let layout = &self.layout;
+        for (name, address) in [("Load address", layout.load_address), ("Entry PC", layout.entry_pc),
+                                ("Font base", layout.font_base)].iter() {
+            if *address as usize >= self.memory_size {
+                return Err(invalid_data(format!("{} {:#X} is outside the memory", name, address)));
+            }
+        }
+        Ok(())
+    }
+
+    /* Values missing from the text are taken from the variant
+     * defaults, so the variant gets parsed first on its own
+     */
+    #[cfg(feature = "files")]
+    pub fn from_toml(text: &str) -> io::Result<MachineConfig> {
+        let mut table: toml::Table = toml::from_str(text).map_err(invalid_data)?;
+        let variant = match table.get("variant") {
+            Some(variant) => variant.clone().try_into().map_err(invalid_data)?,
+            None => Variant::default(),
+        };
+        let defaults = toml::Table::try_from(MachineConfig::new(variant)).map_err(invalid_data)?;
+        for (key, value) in defaults {
+            match (table.get_mut(&key), value) {
+                // Layout and quirks get merged key by key, the rate is replaced whole
+                (Some(toml::Value::Table(over)), toml::Value::Table(value)) if key != "rate" => {
+                    for (key, value) in value {
+                        over.entry(key).or_insert(value);
+                    }
+                },
+                (Some(_), _) => {},
+                (None, value) => {
+                    table.insert(key, value);
+                },
+            }
+        }
+
+        let config: MachineConfig = table.try_into().map_err(invalid_data)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[cfg(feature = "files")]
+    pub fn to_toml(&self) -> io::Result<String> {
+        toml::to_string(self).map_err(invalid_data)
+    }
+
+    #[cfg(feature = "files")]
+    pub fn load(path: &Path) -> io::Result<MachineConfig> {
+        MachineConfig::from_toml(&fs::read_to_string(path)?)
+    }
+
+    #[cfg(feature = "files")]
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_toml()?)
+    }
+}
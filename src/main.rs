@@ -1,85 +1,712 @@
 extern crate sfml;
 
-mod bus;
-mod busstate;
-mod cpu;
-mod memory;
-mod keyboard;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
 
-use keyboard::State;
-use sfml::window::{ContextSettings, Event, Style, Key};
-use sfml::graphics::{Color, Image, RenderTarget, RenderWindow, Texture, Sprite};
-use crate::bus::Bus;
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::cheats::CheatFile;
+use rusty_chip_8::coverage::{Coverage, SymbolFile};
+use rusty_chip_8::debug::DebugEvent;
+use rusty_chip_8::fault::Fault;
+use rusty_chip_8::font::FontSet;
+use rusty_chip_8::gamepad::{self, GamepadEvent, GamepadMapper};
+use rusty_chip_8::gdb::GdbStub;
+use rusty_chip_8::keyboard::{HeldKeys, KeySource, State};
+use rusty_chip_8::keymap::{KeyMap, KeymapConfig, KEYPAD};
+use rusty_chip_8::machine::{ConfigOverrides, MachineConfig, Variant};
+use rusty_chip_8::memory::BoundsPolicy;
+use rusty_chip_8::rom::{self, Cartridge, Rom};
+use rusty_chip_8::romdb::{self, RomDatabase, RomInfo};
+use rusty_chip_8::profiler::Profiler;
+use rusty_chip_8::rpc::RpcServer;
+use rusty_chip_8::timing::{ClockRate, TimingMode};
+use sfml::window::{joystick, ContextSettings, Event, Style, Key};
+use sfml::graphics::{Color, RectangleShape, RenderTarget, RenderWindow, Shape, Texture, Sprite, Transformable};
+use sfml::system::Vector2f;
+
+// Speed multiplier while Tab is held
+const FAST_FORWARD: f64 = 4.0;
+const TITLE: &str = "RustyChip8 Emulator";
+const DEFAULT_RPC_ADDRESS: &str = "tcp:127.0.0.1:7070";
+// Rows on each table of the profiler report
+const PROFILE_TOP: usize = 20;
+
+struct Options {
+    rom: String,
+    // Which file to use inside a zip archive
+    entry: Option<String>,
+    keymap: PathBuf,
+    romdb: PathBuf,
+    // Cheats for every ROM, keyed by the ROM hash
+    cheats: PathBuf,
+    // Machine file to start from, instead of the variant defaults
+    machine: Option<PathBuf>,
+    // Picked from the ROM database platform when not given
+    variant: Option<Variant>,
+    // Where to write the machine config once resolved
+    save_machine: Option<PathBuf>,
+    // Command line wins over the ROM database
+    overrides: ConfigOverrides,
+    // Port for the GDB stub, it waits for the debugger before starting
+    gdb: Option<u16>,
+    // JSON-RPC server address (tcp:host:port or unix:path)
+    rpc: Option<String>,
+    // Where the profiler report goes on exit, the folded stacks go next to it
+    profile: Option<PathBuf>,
+    // Where the coverage listing goes on exit, the lcov report goes next to it
+    coverage: Option<PathBuf>,
+    // Maps addresses back to the Octo source, for the coverage
+    symbols: Option<PathBuf>,
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let subcommand = args.first()
+        .filter(|arg| *arg == "info" || *arg == "list" || *arg == "serve")
+        .cloned();
+    let options = parse_args(args.into_iter().skip(subcommand.is_some() as usize));
+    let path = Path::new(&options.rom);
+
+    if subcommand.as_deref() == Some("list") {
+        match rom::list_zip_entries(path) {
+            Ok(entries) => entries.iter().for_each(|entry| println!("{}", entry)),
+            Err(err) => println!("Could not list {}: {}", options.rom, err),
+        }
+        return;
+    }
+
+    let rom = match rom::load(path, options.entry.as_deref()) {
+        Ok(rom) => rom,
+        Err(err) => {
+            // Cartridges that don't assemble can't run, but their options can still be shown
+            let cartridge = fs::read(path).ok().and_then(|gif| rom::read_cartridge(&gif).ok());
+            match cartridge {
+                Some(cartridge) if subcommand.as_deref() == Some("info") => print_cartridge(&options.rom, &cartridge, &err),
+                _ => println!("Could not load {}: {}", options.rom, err),
+            }
+            process::exit(1);
+        }
+    };
+    let mut database = RomDatabase::bundled();
+    if let Err(err) = database.load_overrides(&options.romdb) {
+        println!("Ignoring ROM database overrides {}: {}", options.romdb.display(), err);
+    }
+    // Cartridges not in the database still run with their own options
+    let cartridge = rom.options.as_ref().map(|options| options.rom_info(romdb::sha1_hex(&rom.data)));
+    let rom_info = database.lookup_rom(&rom.data).or(cartridge);
+
+    if subcommand.as_deref() == Some("info") {
+        print_info(&options.rom, &rom, rom_info.as_ref(), &database);
+        return;
+    }
+    let rom = rom.data;
+
+    // Machine file (or variant defaults), then the ROM database, then the command line
+    let file = options.machine.as_ref().map(|path| {
+        MachineConfig::load(path).unwrap_or_else(|err| {
+            println!("Could not load machine {}: {}", path.display(), err);
+            process::exit(1);
+        })
+    });
+    let config = MachineConfig::resolve(file, options.variant, rom_info.as_ref(), &options.overrides);
+    let mut bus = Bus::with_config(&rom, config.clone()).unwrap_or_else(|err| {
+        println!("Invalid machine: {}", err);
+        process::exit(1);
+    });
+    if let Some(path) = &options.save_machine {
+        if let Err(err) = config.save(path) {
+            println!("Could not save machine {}: {}", path.display(), err);
+        }
+    }
+
+    let mut rpc = options.rpc.as_ref().map(|address| {
+        RpcServer::bind(address).unwrap_or_else(|err| {
+            println!("Could not listen for JSON-RPC on {}: {}", address, err);
+            process::exit(1);
+        })
+    });
+    if subcommand.as_deref() == Some("serve") {
+        serve(bus, rpc.get_or_insert_with(|| {
+            RpcServer::bind(DEFAULT_RPC_ADDRESS).unwrap_or_else(|err| {
+                println!("Could not listen for JSON-RPC on {}: {}", DEFAULT_RPC_ADDRESS, err);
+                process::exit(1);
+            })
+        }));
+    }
+
+    let (width, height) = (config.display_width as u32, config.display_height as u32);
     let mut window = RenderWindow::new(
-        //64 x 32 chip 8 resolution
-        (64, 32),
-        "RustyChip8 Emulator",
+        (width, height),
+        TITLE,
         Style::CLOSE,
         &ContextSettings::default(),
     );
-    let mut image = Image::new(64, 32);
+    //Created once, only the dirty rows are uploaded every frame
+    let mut texture = Texture::new(width, height).expect("Could not create screen texture");
 
-    let mut bus = Bus::new("/Users/alexcpeixoto/developer/pong.ch8");
-    let color = Color::rgb(0, 127, 0);
-    let black = Color::rgb(9, 0, 0);
+    if options.profile.is_some() {
+        bus.enable_profiler();
+    }
+    if options.coverage.is_some() {
+        bus.enable_coverage();
+    }
+    let cheat_file = CheatFile::load(&options.cheats).unwrap_or_else(|err| {
+        println!("Ignoring cheats {}: {}", options.cheats.display(), err);
+        CheatFile::default()
+    });
+    bus.set_cheats(cheat_file.cheats(&romdb::sha1_hex(&rom)).to_vec());
+    let mut gdb = options.gdb.map(|port| {
+        let mut stub = GdbStub::bind(("127.0.0.1", port)).unwrap_or_else(|err| {
+            println!("Could not listen for gdb on port {}: {}", port, err);
+            process::exit(1);
+        });
+        println!("Waiting for gdb on localhost:{}", port);
+        if let Err(err) = stub.wait_for_debugger() {
+            println!("Could not accept gdb: {}", err);
+        }
+        stub
+    });
+    // Per ROM overrides are keyed by the ROM file name
+    let rom_name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut keymap_config = KeymapConfig::load(&options.keymap).unwrap_or_else(|err| {
+        println!("Ignoring keymap {}: {}", options.keymap.display(), err);
+        KeymapConfig::default()
+    });
+    let mut keymap = keymap_config.keymap_for(&rom_name).expect("Keymap was validated on load");
+    // A gamepad profile set for the ROM wins over the database key hints
+    let gamepad_profile = keymap_config.rom_gamepad(&rom_name)
+        .or_else(|| rom_info.as_ref().and_then(|rom_info| rom_info.gamepad_profile()))
+        .unwrap_or_else(|| keymap_config.gamepad_for(&rom_name));
+    let mut gamepad = GamepadMapper::new(&gamepad_profile);
+    let mut held = HeldKeys::new();
+    // Emulation is paused while keys are being remapped (F1)
+    let mut remap: Option<Remap> = None;
+
+    let (color, black) = rom_info.as_ref()
+        .and_then(|rom_info| rom_info.palette())
+        .unwrap_or(([0, 127, 0, 255], [9, 0, 0, 255]));
 
     window.set_framerate_limit(60);
-    window.set_size((1280, 640));
+    // 20 times the CHIP-8 resolution
+    window.set_size((width * 20, height * 20));
+
+    // PageUp/PageDown change it, for slow motion or a steady fast speed
+    let mut speed = 1.0;
+    let mut fast_forward = false;
+    let mut last_frame = Instant::now();
 
     while window.is_open() {
         // Reset key state for this frame
         bus.keyboard.reset_key_press();
 
-        while let Some(event) = window.poll_event() {
+        // Emulated time follows the real elapsed time, not the host framerate
+        let now = Instant::now();
+        let elapsed = now - last_frame;
+        last_frame = now;
+
+        /* SFML doesn't say when each event happened inside the frame,
+         * so they get spread over the emulated time this frame runs,
+         * a press and release in the same frame still land on
+         * different instructions
+         */
+        let events: Vec<Event> = std::iter::from_fn(|| window.poll_event()).collect();
+        let frame_start = bus.time();
+        let frame_span = elapsed.mul_f64(bus.timing.speed());
+        let count = events.len();
+
+        for (idx, event) in events.into_iter().enumerate() {
+            let at = frame_start + frame_span.mul_f64((idx + 1) as f64 / (count + 1) as f64);
+
             // Request closing for the window
             if event == Event::Closed {
                 window.close();
             }
+            if let Some(current) = remap.as_mut() {
+                if let Event::KeyPressed {code, ..} = event {
+                    match current.process_key(code) {
+                        RemapResult::Pending => window.set_title(&current.title()),
+                        RemapResult::Cancelled => {
+                            remap = None;
+                            window.set_title(TITLE);
+                        },
+                        RemapResult::Done(new_keymap) => {
+                            keymap_config.set_rom_keymap(&rom_name, &new_keymap);
+                            if let Err(err) = keymap_config.save(&options.keymap) {
+                                println!("Could not save keymap {}: {}", options.keymap.display(), err);
+                            }
+                            keymap = new_keymap;
+                            remap = None;
+                            window.set_title(TITLE);
+                        },
+                    }
+                }
+                continue;
+            }
             match event {
-                Event::KeyPressed {code, ..} => process_keys(code, &mut bus.keyboard, State::PRESSED),
-                Event::KeyReleased{code, ..} => process_keys(code, &mut bus.keyboard, State::RELEASED),
+                Event::KeyPressed {code: Key::F1, ..} => {
+                    let current = Remap::new(&keymap);
+                    window.set_title(&current.title());
+                    remap = Some(current);
+                    // Nothing should stay held while remapping
+                    held.clear();
+                    for key in 0..0x10 {
+                        bus.keyboard.process_key(key, State::RELEASED);
+                    }
+                },
+                Event::KeyPressed {code: Key::F2, ..} if !bus.cheats().is_empty() => {
+                    bus.set_cheats_enabled(!bus.cheats_enabled());
+                    let cheats = if bus.cheats_enabled() { "cheats on" } else { "cheats off" };
+                    window.set_title(&format!("{} - {}", TITLE, cheats));
+                },
+                Event::KeyPressed {code: Key::Tab, ..} => fast_forward = true,
+                Event::KeyReleased {code: Key::Tab, ..} => fast_forward = false,
+                Event::KeyPressed {code: Key::PageUp, ..} => speed = f64::min(speed * 2.0, 8.0),
+                Event::KeyPressed {code: Key::PageDown, ..} => speed = f64::max(speed / 2.0, 0.125),
+                Event::KeyPressed {code, ..} => process_keys(code, &keymap, &mut held, &mut bus, State::PRESSED, at),
+                Event::KeyReleased{code, ..} => process_keys(code, &keymap, &mut held, &mut bus, State::RELEASED, at),
+                Event::JoystickButtonPressed {button, ..} =>
+                    process_gamepad(GamepadEvent::ButtonPressed(button), &mut gamepad, &mut held, &mut bus, at),
+                Event::JoystickButtonReleased {button, ..} =>
+                    process_gamepad(GamepadEvent::ButtonReleased(button), &mut gamepad, &mut held, &mut bus, at),
+                Event::JoystickMoved {axis, position, ..} =>
+                    process_gamepad(GamepadEvent::AxisMoved(map_axis(axis), position), &mut gamepad, &mut held, &mut bus, at),
                 _ => {},
             };
         }
-        for _tick in 0..5 {
-            bus.tick_frame_cpu();
-        }
-        let screen_updated = bus.was_screen_updated();
-        if screen_updated {
-            let vram = bus.get_vram();
-            for i in 0..64 {
-                for j in 0..32 {
-                    if vram[(i, j)] {
-                        image.set_pixel(i as u32, j as u32, color);
-                    } else {
-                        image.set_pixel(i as u32, j as u32, black);
-                    }
+        bus.timing.set_speed(if fast_forward { FAST_FORWARD } else { speed });
+
+        if let Some(stub) = gdb.as_mut() {
+            if let Err(err) = stub.poll(&mut bus) {
+                println!("gdb connection lost: {}", err);
+            }
+        }
+        if let Some(server) = rpc.as_mut() {
+            if let Err(err) = server.poll(&mut bus) {
+                println!("JSON-RPC server error: {}", err);
+            }
+        }
+        let debugger_running = gdb.as_ref().map(|stub| stub.is_running()).unwrap_or(true);
+
+        if remap.is_none() && bus.fault().is_none() && debugger_running {
+            bus.run_for(elapsed);
+            for event in bus.take_debug_events() {
+                match event {
+                    DebugEvent::ProtectedWrite { pc, address, value } =>
+                        println!("Write of {:#04X} to protected {:#05X} at {:#05X}", value, address, pc),
+                }
+            }
+            if let Some(fault) = bus.fault() {
+                report_fault(&fault, &bus);
+                window.set_title(&format!("{} - {}", TITLE, fault));
+            }
+        }
+
+        if bus.was_screen_updated() {
+            for rows in bus.take_dirty_rows() {
+                let rows_height = rows.len() as u32;
+                let y = rows.start as u32;
+                let pixels = bus.get_vram().rows_to_rgba(rows, color, black);
+                // Safe as the buffer always holds width x rows_height RGBA pixels
+                unsafe {
+                    texture.update_from_pixels(&pixels, width, rows_height, 0, y);
                 }
             }
         }
-        let texture = Texture::from_image(&image).unwrap();
         let sprite = Sprite::with_texture(&texture);
         window.set_active(true);
         window.draw(&sprite); 
+        if let Some(current) = remap.as_ref() {
+            current.draw(&mut window);
+        }
         window.display();
+    }
 
-        bus.tick_frame_timer();
+    if let (Some(path), Some(profiler)) = (&options.profile, bus.take_profiler()) {
+        write_profile(path, &profiler);
+    }
+    if let (Some(path), Some(coverage)) = (&options.coverage, bus.take_coverage()) {
+        let load_address = bus.memory.layout().load_address;
+        write_coverage(path, options.symbols.as_deref(), &coverage, bus.rom(), load_address);
     }
 }
 
-fn process_keys(key_ev: Key , keyboard: &mut crate::keyboard::Keyboard, state: State) {
-    let key_pair = [(Key::Num1, 0x1), (Key::Num2, 0x2), (Key::Num3, 0x3), 
-                    (Key::Num4, 0xC), (Key::Q,    0x4), (Key::W,    0x5), 
-                    (Key::E,    0x6), (Key::R,    0xD), (Key::A,    0x7), 
-                    (Key::S,    0x8), (Key::D,    0x9), (Key::F,    0xE), 
-                    (Key::Z,    0xA), (Key::X,    0x0), (Key::C,    0xB), 
-                    (Key::V,    0xF)];
+// The listing on the path, the lcov report with a .info extension
+fn write_coverage(path: &Path, symbols: Option<&Path>, coverage: &Coverage, rom: &[u8], load_address: u16) {
+    let symbols = symbols.and_then(|symbols| match SymbolFile::load(symbols) {
+        Ok(symbols) => Some(symbols),
+        Err(err) => {
+            println!("Ignoring symbols {}: {}", symbols.display(), err);
+            None
+        },
+    });
+    let lcov_path = path.with_extension("info");
+    let listing_name = path.to_string_lossy();
+    let lcov = coverage.lcov(rom, load_address, symbols.as_ref(), &listing_name);
+
+    if let Err(err) = fs::write(path, coverage.listing(rom, load_address, symbols.as_ref())) {
+        println!("Could not write the coverage listing {}: {}", path.display(), err);
+    }
+    match fs::write(&lcov_path, lcov) {
+        Ok(()) => println!("Coverage written to {} and {}", path.display(), lcov_path.display()),
+        Err(err) => println!("Could not write the lcov report {}: {}", lcov_path.display(), err),
+    }
+}
+
+// The report on the path, the folded stacks with a .folded extension
+fn write_profile(path: &Path, profiler: &Profiler) {
+    let folded = path.with_extension("folded");
+    if let Err(err) = fs::write(path, profiler.report(PROFILE_TOP)) {
+        println!("Could not write the profile {}: {}", path.display(), err);
+    }
+    match fs::write(&folded, profiler.folded()) {
+        Ok(()) => println!("Profile written to {} and {}", path.display(), folded.display()),
+        Err(err) => println!("Could not write the folded stacks {}: {}", folded.display(), err),
+    }
+}
 
-    for (key, target) in key_pair.iter() {
-        if key_ev == *key {
-            keyboard.process_key(*target, state)
+fn print_info(path: &str, rom: &Rom, rom_info: Option<&RomInfo>, database: &RomDatabase) {
+    println!("File:        {}", path);
+    if rom.options.is_some() {
+        println!("Format:      Octo cartridge, assembled from its source");
+    }
+    println!("SHA-1:       {}", romdb::sha1_hex(&rom.data));
+    println!("Size:        {} bytes", rom.data.len());
+
+    let rom_info = match rom_info {
+        Some(rom_info) => rom_info,
+        None => {
+            println!("Not found in the ROM database");
+            if database.program_count() == 0 {
+                println!("The bundled program list is empty, run data/chip-8-database/update.sh and rebuild");
+            }
+            return;
+        }
+    };
+    if let Some(title) = &rom_info.title {
+        println!("Title:       {}", title);
+    }
+    if !rom_info.authors.is_empty() {
+        println!("Authors:     {}", rom_info.authors.join(", "));
+    }
+    if let Some(release) = &rom_info.release {
+        println!("Release:     {}", release);
+    }
+    if let Some(platform) = &rom_info.platform {
+        println!("Platform:    {} ({})", platform.name, platform.id);
+    }
+    if let Some(tickrate) = rom_info.tickrate {
+        println!("Tickrate:    {} instructions per frame", tickrate);
+    }
+    if let Some(start_address) = rom_info.start_address {
+        println!("Start:       {:#05X}", start_address);
+    }
+    let quirks: Vec<String> = rom_info.quirks.list().iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    if !quirks.is_empty() {
+        println!("Quirks:      {}", quirks.join(" "));
+    }
+    if let Some(colors) = &rom_info.colors {
+        println!("Colors:      {}", colors.pixels.join(" "));
+    }
+    if !rom_info.keys.is_empty() {
+        let keys: Vec<String> = rom_info.keys.iter()
+            .map(|(name, key)| format!("{}={:X}", name, key))
+            .collect();
+        println!("Keys:        {}", keys.join(" "));
+    }
+    if let Some(description) = &rom_info.description {
+        println!();
+        println!("{}", description);
+    }
+}
+
+// Headless, the machine only runs when a client asks it to
+fn serve(mut bus: Bus, server: &mut RpcServer) -> ! {
+    println!("Serving JSON-RPC, waiting for clients");
+    loop {
+        if let Err(err) = server.poll(&mut bus) {
+            println!("JSON-RPC server error: {}", err);
+            process::exit(1);
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn report_fault(fault: &Fault, bus: &Bus) {
+    println!("Machine stopped: {}", fault);
+    let call_stack = bus.call_stack();
+    if !call_stack.is_empty() {
+        println!("Call stack (innermost last):");
+    }
+    for frame in call_stack {
+        println!("  called from {:#05X}, returns to {:#05X}", frame.call_site, frame.return_address);
+    }
+}
+
+fn print_cartridge(path: &str, cartridge: &Cartridge, err: &io::Error) {
+    let options = &cartridge.options;
+    println!("File:        {}", path);
+    println!("Octo cartridge with {} bytes of source that can't run here", cartridge.program.len());
+    println!("{}", err);
+    if let Some(tickrate) = options.tickrate {
+        println!("Tickrate:    {} instructions per frame", tickrate);
+    }
+    let colors: Vec<&str> = [&options.background_color, &options.fill_color, &options.buzz_color, &options.quiet_color]
+        .iter()
+        .filter_map(|color| color.as_deref())
+        .collect();
+    if !colors.is_empty() {
+        println!("Colors:      {}", colors.join(" "));
+    }
+    let quirks = [
+        ("shift", options.shift_quirks),
+        ("loadStore", options.load_store_quirks),
+        ("clip", options.clip_quirks),
+        ("jump", options.jump_quirks),
+        ("logic", options.logic_quirks),
+        ("vBlank", options.v_blank_quirks),
+    ];
+    let quirks: Vec<String> = quirks.iter()
+        .filter_map(|(name, value)| value.map(|value| format!("{}={}", name, value)))
+        .collect();
+    if !quirks.is_empty() {
+        println!("Quirks:      {}", quirks.join(" "));
+    }
+}
+
+fn usage() -> ! {
+    println!("Usage: rusty_chip_8 [info | list | serve] <rom> [--entry <zip entry>] [--romdb <file>] [--hz <instructions per second> | --ipf <instructions per frame> | --vip-timing] [--display-wait] [--key-wait-release] [--keymap <file>] [--eti660] [--load-address <hex>] [--entry-pc <hex>] [--font-base <hex>] [--font octo|vip|dream6800|eti660|fish] [--variant chip8|vip|dream6800|eti660|chip48|superchip] [--machine <file>] [--save-machine <file>] [--seed <number>] [--bounds wrap|fault|clamp] [--write-protect] [--gdb <port>] [--rpc tcp:<host>:<port> | unix:<path>] [--profile <report file>] [--coverage <listing file>] [--symbols <file>] [--cheats <file>]");
+    process::exit(1);
+}
+
+fn parse_number(value: Option<String>) -> u32 {
+    match value.map(|v| v.parse::<u32>()) {
+        Some(Ok(number)) if number > 0 => number,
+        _ => usage(),
+    }
+}
+
+// Any seed is fine, 0 included
+fn parse_seed(value: Option<String>) -> u32 {
+    match value.map(|v| v.parse::<u32>()) {
+        Some(Ok(seed)) => seed,
+        _ => usage(),
+    }
+}
+
+fn parse_port(value: Option<String>) -> u16 {
+    match value.map(|v| v.parse::<u16>()) {
+        Some(Ok(port)) if port > 0 => port,
+        _ => usage(),
+    }
+}
+
+// Addresses are taken as hex, with or without 0x
+fn parse_address(value: Option<String>) -> u16 {
+    let value = value.unwrap_or_else(|| usage());
+    let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+    // The machine config checks it is inside the memory
+    match u16::from_str_radix(digits, 16) {
+        Ok(address) => address,
+        Err(_) => usage(),
+    }
+}
+
+fn parse_font(value: Option<String>) -> FontSet {
+    match value.as_deref() {
+        Some("octo") => FontSet::Octo,
+        Some("vip") => FontSet::Vip,
+        Some("dream6800") => FontSet::Dream6800,
+        Some("eti660") => FontSet::Eti660,
+        Some("fish") => FontSet::Fish,
+        _ => usage(),
+    }
+}
+
+fn parse_bounds(value: Option<String>) -> BoundsPolicy {
+    match value.as_deref() {
+        Some("wrap") => BoundsPolicy::Wrap,
+        Some("fault") => BoundsPolicy::Fault,
+        Some("clamp") => BoundsPolicy::Clamp,
+        _ => usage(),
+    }
+}
+
+fn parse_variant(value: Option<String>) -> Variant {
+    match value.as_deref() {
+        Some("chip8") => Variant::Chip8,
+        Some("vip") => Variant::Vip,
+        Some("dream6800") => Variant::Dream6800,
+        Some("eti660") => Variant::Eti660,
+        Some("chip48") => Variant::Chip48,
+        Some("superchip") => Variant::SuperChip,
+        _ => usage(),
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Options {
+    let mut rom = None;
+    let mut overrides = ConfigOverrides::default();
+    let mut gdb = None;
+    let mut rpc = None;
+    let mut profile = None;
+    let mut coverage = None;
+    let mut symbols = None;
+    let mut machine = None;
+    let mut variant = None;
+    let mut save_machine = None;
+    let mut keymap = PathBuf::from("keymap.toml");
+    let mut romdb = PathBuf::from("romdb.json");
+    let mut cheats = PathBuf::from("cheats.toml");
+    let mut entry = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--hz" => overrides.rate = Some(ClockRate::Hz(parse_number(args.next()))),
+            "--ipf" => overrides.rate = Some(ClockRate::InstructionsPerFrame(parse_number(args.next()))),
+            "--vip-timing" => overrides.mode = Some(TimingMode::VipCycles),
+            "--seed" => overrides.seed = Some(parse_seed(args.next())),
+            "--bounds" => overrides.bounds_policy = Some(parse_bounds(args.next())),
+            "--write-protect" => overrides.write_protect = true,
+            "--gdb" => gdb = Some(parse_port(args.next())),
+            "--rpc" => rpc = Some(args.next().unwrap_or_else(|| usage())),
+            "--profile" => profile = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            "--coverage" => coverage = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            "--symbols" => symbols = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            "--variant" => variant = Some(parse_variant(args.next())),
+            "--machine" => machine = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            "--save-machine" => save_machine = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            "--display-wait" => overrides.display_wait = true,
+            "--key-wait-release" => overrides.key_wait_release = true,
+            "--keymap" => keymap = args.next().map(PathBuf::from).unwrap_or_else(|| usage()),
+            "--romdb" => romdb = args.next().map(PathBuf::from).unwrap_or_else(|| usage()),
+            "--cheats" => cheats = args.next().map(PathBuf::from).unwrap_or_else(|| usage()),
+            "--entry" => entry = Some(args.next().unwrap_or_else(|| usage())),
+            "--eti660" => variant = Some(Variant::Eti660),
+            "--load-address" => overrides.load_address = Some(parse_address(args.next())),
+            "--entry-pc" => overrides.entry_pc = Some(parse_address(args.next())),
+            "--font-base" => overrides.font_base = Some(parse_address(args.next())),
+            "--font" => overrides.font = Some(parse_font(args.next())),
+            _ if arg.starts_with("--") || rom.is_some() => usage(),
+            _ => rom = Some(arg),
+        }
+    }
+
+    match rom {
+        Some(rom) => Options {
+            rom, entry, keymap, romdb, cheats, machine, variant, save_machine, overrides, gdb, rpc, profile,
+            coverage, symbols,
+        },
+        None => usage(),
+    }
+}
+
+fn process_keys(key_ev: Key, keymap: &KeyMap, held: &mut HeldKeys, bus: &mut Bus, state: State, at: Duration) {
+    // Keymap uses the SFML key names (Num1, Q, SemiColon...)
+    let name = format!("{:?}", key_ev);
+    if let Some(target) = keymap.lookup(&name) {
+        if let Some(state) = held.update(KeySource::Key(name), target, state) {
+            bus.queue_key(target, state, at);
+        }
+    }
+}
+
+fn process_gamepad(event: GamepadEvent, gamepad: &mut GamepadMapper, held: &mut HeldKeys, bus: &mut Bus, at: Duration) {
+    let source = match event {
+        GamepadEvent::ButtonPressed(button) | GamepadEvent::ButtonReleased(button) => KeySource::GamepadButton(button),
+        GamepadEvent::AxisMoved(axis, _) => KeySource::GamepadAxis(axis),
+    };
+    for (key, state) in gamepad.process(event) {
+        if let Some(state) = held.update(source.clone(), key, state) {
+            bus.queue_key(key, state, at);
+        }
+    }
+}
+
+fn map_axis(axis: joystick::Axis) -> gamepad::Axis {
+    match axis {
+        joystick::Axis::X => gamepad::Axis::X,
+        joystick::Axis::Y => gamepad::Axis::Y,
+        joystick::Axis::Z => gamepad::Axis::Z,
+        joystick::Axis::R => gamepad::Axis::R,
+        joystick::Axis::U => gamepad::Axis::U,
+        joystick::Axis::V => gamepad::Axis::V,
+        joystick::Axis::PovX => gamepad::Axis::PovX,
+        joystick::Axis::PovY => gamepad::Axis::PovY,
+    }
+}
+
+enum RemapResult {
+    Pending,
+    Cancelled,
+    Done(KeyMap),
+}
+
+/* Remapping screen, walks the keypad one CHIP-8 key at a time.
+ * Every host key pressed gets bound to the current CHIP-8 key
+ * (replacing what it had), Enter moves to the next one, keeping
+ * the old binding if nothing was pressed and Escape cancels it all.
+ */
+struct Remap {
+    keymap: KeyMap,
+    position: usize,
+    touched: bool,
+}
+
+impl Remap {
+    fn new(keymap: &KeyMap) -> Remap {
+        Remap {
+            keymap: keymap.clone(),
+            position: 0,
+            touched: false,
+        }
+    }
+
+    fn title(&self) -> String {
+        let key = KEYPAD[self.position];
+        format!("Remap CHIP-8 key {:X} [{}] - Enter for next, Esc to cancel",
+                key, self.keymap.host_keys(key).join(", "))
+    }
+
+    fn process_key(&mut self, code: Key) -> RemapResult {
+        let key = KEYPAD[self.position];
+        match code {
+            Key::Escape => return RemapResult::Cancelled,
+            Key::Return => {
+                self.position += 1;
+                self.touched = false;
+                if self.position == KEYPAD.len() {
+                    return RemapResult::Done(self.keymap.clone());
+                }
+            },
+            _ => {
+                if !self.touched {
+                    self.keymap.clear_key(key);
+                    self.touched = true;
+                }
+                self.keymap.bind(&format!("{:?}", code), key);
+            }
+        }
+        RemapResult::Pending
+    }
+
+    // 4x4 keypad over the screen, with the key being remapped highlighted
+    fn draw(&self, window: &mut RenderWindow) {
+        for idx in 0..KEYPAD.len() {
+            let mut cell = RectangleShape::with_size(Vector2f::new(14.0, 6.0));
+            cell.set_position(Vector2f::new((idx % 4) as f32 * 16.0 + 1.0, (idx / 4) as f32 * 8.0 + 1.0));
+            if idx == self.position {
+                cell.set_fill_color(Color::rgb(0, 255, 0));
+            } else {
+                cell.set_fill_color(Color::rgb(0, 60, 0));
+            }
+            window.draw(&cell);
         }
     }
 }
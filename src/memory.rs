@@ -1,11 +1,68 @@
 extern crate byteorder;
-extern crate bitmatrix;
 
-use std::fs::File;
-use std::io::Read;
-use bitmatrix::BitMatrix;
+#[cfg(feature = "files")]
+use std::path::Path;
+use serde::{Deserialize, Serialize};
 
-use std::ops::{Index, IndexMut};
+use crate::debug::DebugEvent;
+use crate::fault::Fault;
+use crate::font::{FontSet, GLYPH_SIZE};
+use crate::framebuffer::Framebuffer;
+use crate::machine::{MachineConfig, MAX_MEMORY_SIZE};
+#[cfg(feature = "files")]
+use crate::rom;
+
+use std::ops::{Index, IndexMut, Range};
+
+/* Where things go in memory, most machines load at 0x200
+ * and start running from there, but the ETI 660 uses 0x600.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MemoryLayout {
+    pub load_address: u16,
+    // Where the PC starts, usually the load address
+    pub entry_pc: u16,
+    pub font_base: u16,
+    pub font: FontSet,
+}
+
+impl Default for MemoryLayout {
+    fn default() -> Self {
+        MemoryLayout::at(0x200)
+    }
+}
+
+impl MemoryLayout {
+    // Loads and starts running at the address, the font stays at 0
+    pub fn at(address: u16) -> Self {
+        MemoryLayout {
+            load_address: address,
+            entry_pc: address,
+            font_base: 0x000,
+            font: FontSet::default(),
+        }
+    }
+
+    pub fn eti660() -> Self {
+        MemoryLayout { font: FontSet::Eti660, ..MemoryLayout::at(0x600) }
+    }
+}
+
+// Where the interpreter lived, what write protection covers
+pub const INTERPRETER_AREA: Range<usize> = 0x000..0x200;
+
+// What happens to ROM accesses past the end of the memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundsPolicy {
+    // Addresses wrap around, like the address lines would
+    #[default]
+    Wrap,
+    // The machine stops with a fault, reads give 0 and writes are dropped
+    Fault,
+    // Addresses stick to the last byte
+    Clamp,
+}
 
 pub struct MemoryMap {
     /*
@@ -32,107 +89,201 @@ pub struct MemoryMap {
        | Reserved for  |
        |  interpreter  |
        +---------------+= 0x000 (0) Start of Chip-8 RAM
+       The size comes from the machine config, 4K by default
        */
-    memory: [u8; 0xFFF],
-    rom_name: String,
-    vram: BitMatrix,
-    vram_changed: bool,
+    memory: Vec<u8>,
+    vram: Framebuffer,
+    layout: MemoryLayout,
+    policy: BoundsPolicy,
+    write_protect: bool,
+    // Instruction doing the accesses, for faults and events
+    pc: u16,
+    fault: Option<Fault>,
+    events: Vec<DebugEvent>,
 }
 
+/* Direct access, for the frontends and tools, always wraps and
+ * skips the protection, the CPU goes through read and write
+ */
 impl Index<u16> for MemoryMap {
     type Output = u8;
-    fn index<'a>(&'a self, i: u16) -> &'a u8 {
-        &self.memory[i as usize]
+    fn index(&self, i: u16) -> &u8 {
+        &self.memory[i as usize % self.memory.len()]
     }
 }
 
 impl IndexMut<u16> for MemoryMap{
-    fn index_mut<'a>(&'a mut self, i: u16) -> &'a mut u8 {
-        &mut self.memory[i as usize]
+    fn index_mut(&mut self, i: u16) -> &mut u8 {
+        let size = self.memory.len();
+        &mut self.memory[i as usize % size]
     }
 }
 
 impl MemoryMap {
+    // Accepts anything rom::load does (raw ROMs or zip archives)
+    #[cfg(feature = "files")]
     pub fn new(rom_name: &str) -> Self {
+        let rom = rom::load(Path::new(rom_name), None).expect("Could not open file");
+        Self::with_rom(&rom.data)
+    }
+
+    pub fn with_rom(rom: &[u8]) -> Self {
+        Self::with_config(rom, &MachineConfig::default())
+    }
+
+    pub fn with_config(rom: &[u8], config: &MachineConfig) -> Self {
         //this is actually returning a new instance
         let mut to_ret = Self {
-            memory: [0; 0xFFF],
-            //To preserve X, Y, "order" the matrix
-            //is "inverted", just to avoid confusion later
-            vram: BitMatrix::new(64, 32),
-            vram_changed: false,
-            rom_name: rom_name.to_owned(),
+            memory: vec![0; config.memory_size.min(MAX_MEMORY_SIZE)],
+            vram: Framebuffer::new(config.display_width, config.display_height),
+            layout: config.layout,
+            policy: config.bounds_policy,
+            write_protect: config.write_protect,
+            pc: config.layout.entry_pc,
+            fault: None,
+            events: Vec::new(),
         };
 
-        for i in 0..64 {
-            for j in 0..32 {
-                to_ret.vram.set((i, j), false);
-            }
-        }
-
         to_ret.init_font();
-        to_ret.load_rom();
+        to_ret.load_rom(rom);
         to_ret
     }
 
-    fn init_font(&mut self) {
-        let font_default = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-        for idx in 0..80 {
-            self.memory[idx] = font_default[idx];
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    // The whole memory, for save states and tools
+    pub fn bytes(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Goes around the bounds policy and write protection, for frontends that poke RAM
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    // Moves the contents into the buffer of the other map, when both are the same size
+    pub fn reuse_buffer(&mut self, mut other: MemoryMap) {
+        if other.memory.len() == self.memory.len() {
+            other.memory.copy_from_slice(&self.memory);
+            self.memory = other.memory;
+        }
+    }
+
+    // Anything past the memory size is dropped
+    pub fn load_bytes(&mut self, bytes: &[u8]) {
+        let size = bytes.len().min(self.memory.len());
+        self.memory[..size].copy_from_slice(&bytes[..size]);
+    }
+
+    pub fn layout(&self) -> &MemoryLayout {
+        &self.layout
+    }
+
+    // The CPU sets it before each instruction
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    // Addresses past the end go around under the Wrap policy, the others see them on access
+    pub fn wrap_address(&self, address: u16) -> u16 {
+        match self.policy {
+            BoundsPolicy::Wrap => (address as usize % self.memory.len()) as u16,
+            _ => address,
+        }
+    }
+
+    // Applies the bounds policy, None if the access has to be dropped
+    fn resolve(&mut self, address: usize) -> Option<usize> {
+        let size = self.memory.len();
+        if address < size {
+            return Some(address);
+        }
+        match self.policy {
+            BoundsPolicy::Wrap => Some(address % size),
+            BoundsPolicy::Clamp => Some(size - 1),
+            BoundsPolicy::Fault => {
+                // Only the first one matters, it is what stops the machine
+                self.fault.get_or_insert(Fault::OutOfBounds { pc: self.pc, address });
+                None
+            },
+        }
+    }
+
+    pub fn read(&mut self, address: usize) -> u8 {
+        match self.resolve(address) {
+            Some(address) => self.memory[address],
+            None => 0,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        let address = match self.resolve(address) {
+            Some(address) => address,
+            None => return,
+        };
+        if self.write_protect && INTERPRETER_AREA.contains(&address) {
+            self.events.push(DebugEvent::ProtectedWrite { pc: self.pc, address: address as u16, value });
+            return;
         }
+        self.memory[address] = value;
+    }
+
+    pub fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take()
     }
 
-    fn load_rom(&mut self) {
-        let slice = &mut self.memory[0x200..0xFFF];
+    pub fn take_debug_events(&mut self) -> Vec<DebugEvent> {
+        std::mem::take(&mut self.events)
+    }
 
-        let mut rom = File::open(&self.rom_name).expect("Could not open file");
+    fn init_font(&mut self) {
+        let glyphs = self.layout.font.glyphs();
+        // A font base too close to the end gets the last glyphs cut
+        let start = (self.layout.font_base as usize).min(self.memory.len());
+        let size = glyphs.len().min(self.memory.len() - start);
+        self.memory[start..start + size].copy_from_slice(&glyphs[..size]);
+    }
 
-        rom.read(&mut slice[..]).expect("Failed to load rom data");
+    // Address of the glyph for the low nibble of digit, what FX29 points to
+    pub fn font_address(&self, digit: u8) -> u16 {
+        self.layout.font_base.wrapping_add(u16::from(digit & 0xF) * GLYPH_SIZE)
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        let start = (self.layout.load_address as usize).min(self.memory.len());
+        let slice = &mut self.memory[start..];
+        // Anything that doesn't fit is dropped
+        let size = rom.len().min(slice.len());
+        slice[..size].copy_from_slice(&rom[..size]);
     }
 
     pub fn get_vram(&self, x: usize, y: usize) -> bool {
-        self.vram[(x, y)]
+        self.vram.get(x, y)
     }
 
-    pub fn get_full_vram(&self) -> &BitMatrix {
+    pub fn get_full_vram(&self) -> &Framebuffer {
         &self.vram
     }
 
-    pub fn set_vram(&mut self, x: usize, y: usize, set: bool) {
-        self.vram.set((x, y), set);
+    pub fn get_full_vram_mut(&mut self) -> &mut Framebuffer {
+        &mut self.vram
     }
 
-    pub fn pending_screen_update(&mut self, updated: bool) {
-        self.vram_changed = updated;
+    pub fn set_vram(&mut self, x: usize, y: usize, set: bool) {
+        self.vram.set(x, y, set);
     }
 
     pub fn was_screen_updated(&self) -> bool {
-        self.vram_changed.clone()
+        self.vram.was_updated()
+    }
+
+    pub fn take_dirty_rows(&mut self) -> Vec<Range<usize>> {
+        self.vram.take_dirty_rows()
     }
 
     pub fn clear_vram(&mut self) {
-        for i in 0..64 {
-            for j in 0..32 {
-                self.vram.set((i, j), false);
-            }
-        }
-        self.vram_changed = true;
+        self.vram.clear();
     }
 }
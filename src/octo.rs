@@ -0,0 +1,827 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+/* Assembles Octo source (https://github.com/JohnEarnest/Octo), what
+ * cartridge GIFs carry, into a CHIP-8 program loaded at 0x200.
+ *
+ * Only the plain CHIP-8 part of the language is known, SUPER-CHIP
+ * and XO-CHIP instructions are rejected as this emulator can't run
+ * them. Labels can be used before they are defined, constants and
+ * macros have to come first, like on Octo.
+ */
+const START: u16 = 0x200;
+const END: u16 = 0x1000;
+// Cartridges come from anywhere, a macro that expands itself
+// or a deeply nested expression has to fail instead of hanging
+const MAX_EXPANDED_TOKENS: usize = 200_000;
+const MAX_EXPRESSION_DEPTH: usize = 256;
+
+// Instructions and directives of the extensions that can't run here
+const UNSUPPORTED: [&str; 17] = [
+    "hires", "lores", "scroll-down", "scroll-up", "scroll-left", "scroll-right", "exit", "saveflags",
+    "loadflags", "plane", "audio", "pitch", "bighex", "long", ":stringmode", ":include", ":segment",
+];
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<String>,
+}
+
+// What gets written once a label used early is defined
+#[derive(Clone, Copy, Debug)]
+enum Patch {
+    // The low 12 bits of the instruction
+    Address,
+    // Both bytes, for :pointer
+    Word,
+    // :unpack, the nibble and the high bits of the address
+    UnpackHigh(u8),
+    UnpackLow,
+}
+
+struct Fixup {
+    address: u16,
+    label: String,
+    patch: Patch,
+    line: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    Register(u8),
+    Value(u8),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+    Less(u8, Operand),
+    Greater(u8, Operand),
+    LessEqual(u8, Operand),
+    GreaterEqual(u8, Operand),
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: u16,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    // Label for the second byte of the next instruction (:next)
+    next: Option<String>,
+    // Start and the while jumps of every open loop
+    loops: Vec<(u16, Vec<u16>)>,
+    // Jumps of the open if ... begin blocks
+    branches: Vec<u16>,
+    // Tokens produced by macros so far, see MAX_EXPANDED_TOKENS
+    expanded: usize,
+}
+
+// Octo splits on whitespace, # starts a comment, strings keep their spaces
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (number, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+            let mut text = c.to_string();
+            if c == '"' {
+                for c in chars.by_ref() {
+                    text.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+            } else {
+                while let Some(c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                    text.push(*c);
+                    chars.next();
+                }
+            }
+            tokens.push_back(Token { text, line: number + 1 });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn register_name(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => digit.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+fn unary(op: &str, value: f64) -> Option<f64> {
+    Some(match op {
+        "-" => -value,
+        "~" => !(value as i64) as f64,
+        "!" => (value == 0.0) as u8 as f64,
+        "abs" => value.abs(),
+        "sqrt" => value.sqrt(),
+        "sin" => value.sin(),
+        "cos" => value.cos(),
+        "tan" => value.tan(),
+        "exp" => value.exp(),
+        "log" => value.ln(),
+        "sign" => value.signum(),
+        "ceil" => value.ceil(),
+        "floor" => value.floor(),
+        _ => return None,
+    })
+}
+
+fn binary(op: &str, left: f64, right: f64) -> Option<f64> {
+    let (a, b) = (left as i64, right as i64);
+    Some(match op {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+        ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+        "<" => (left < right) as u8 as f64,
+        ">" => (left > right) as u8 as f64,
+        "<=" => (left <= right) as u8 as f64,
+        ">=" => (left >= right) as u8 as f64,
+        "==" => (left == right) as u8 as f64,
+        "!=" => (left != right) as u8 as f64,
+        _ => return None,
+    })
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        let mut aliases = HashMap::new();
+        aliases.insert("unpack-hi".to_string(), 0);
+        aliases.insert("unpack-lo".to_string(), 1);
+        Assembler {
+            tokens: tokenize(source),
+            line: 0,
+            rom: Vec::new(),
+            // 0x200 is kept for a jump to main
+            here: START + 2,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases,
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            next: None,
+            loops: Vec::new(),
+            branches: Vec::new(),
+            expanded: 0,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> io::Result<T> {
+        Err(invalid_data(format!("Line {}: {}", self.line, message)))
+    }
+
+    fn next_token(&mut self) -> io::Result<String> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            },
+            None => self.error("Unexpected end of the source".to_string()),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> io::Result<()> {
+        let token = self.next_token()?;
+        if token != expected {
+            return self.error(format!("Expected {}, got {}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn register(&mut self) -> io::Result<u8> {
+        let token = self.next_token()?;
+        match register_name(&token).or_else(|| self.aliases.get(&token).copied()) {
+            Some(register) => Ok(register),
+            None => self.error(format!("Expected a register, got {}", token)),
+        }
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        register_name(token).is_some() || self.aliases.contains_key(token)
+    }
+
+    // Numbers, constants and the labels already defined
+    fn known_value(&self, token: &str) -> Option<f64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|address| f64::from(*address)))
+    }
+
+    fn value(&mut self) -> io::Result<f64> {
+        let token = self.next_token()?;
+        match self.known_value(&token) {
+            Some(value) => Ok(value),
+            None => self.error(format!("Unknown value {}", token)),
+        }
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let value = self.value()?.floor() as i64;
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("{} doesn't fit on a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> io::Result<u8> {
+        let value = self.value()?.floor() as i64;
+        if !(0..=15).contains(&value) {
+            return self.error(format!("{} doesn't fit on a nibble", value));
+        }
+        Ok(value as u8)
+    }
+
+    // An address, labels not defined yet get patched at the end
+    fn address(&mut self, at: u16, patch: Patch) -> io::Result<u16> {
+        let token = self.next_token()?;
+        if let Some(value) = self.known_value(&token) {
+            let value = value.floor() as i64;
+            if !(0..=0xFFF).contains(&value) {
+                return self.error(format!("{:#X} is not an address", value));
+            }
+            return Ok(value as u16);
+        }
+        if parse_number(&token).is_some() || self.is_register(&token) {
+            return self.error(format!("Expected an address, got {}", token));
+        }
+        self.fixups.push(Fixup { address: at, label: token, patch, line: self.line });
+        Ok(0)
+    }
+
+    fn operand(&mut self) -> io::Result<Operand> {
+        match self.peek() {
+            Some(token) if self.is_register(token) => Ok(Operand::Register(self.register()?)),
+            _ => Ok(Operand::Value(self.byte()?)),
+        }
+    }
+
+    fn write(&mut self, address: u16, byte: u8) -> io::Result<()> {
+        if !(START..END).contains(&address) {
+            return self.error(format!("{:#X} is outside the program memory", address));
+        }
+        let offset = usize::from(address - START);
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.write(self.here, byte)?;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) -> io::Result<()> {
+        if let Some(label) = self.next.take() {
+            self.labels.insert(label, self.here + 1);
+        }
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte(opcode as u8)
+    }
+
+    // Instructions taking an address, fixed up later for labels defined further down
+    fn emit_with_address(&mut self, opcode: u16) -> io::Result<()> {
+        let at = self.here;
+        let address = self.address(at, Patch::Address)?;
+        self.emit(opcode | address)
+    }
+
+    fn patch_jump(&mut self, at: u16, target: u16) -> io::Result<()> {
+        self.write(at, 0x10 | (target >> 8) as u8)?;
+        self.write(at + 1, target as u8)
+    }
+
+    fn define_label(&mut self, name: String) -> io::Result<()> {
+        if self.labels.contains_key(&name) {
+            return self.error(format!("Label {} is defined twice", name));
+        }
+        // When main comes first there is no need to jump to it
+        if name == "main" && self.here == START + 2 && self.rom.is_empty() && self.labels.is_empty() {
+            self.here = START;
+        }
+        self.labels.insert(name, self.here);
+        Ok(())
+    }
+
+    // Tokens up to the closing brace, the opening one already read
+    fn block(&mut self) -> io::Result<Vec<String>> {
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next_token()?;
+            match token.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                },
+                _ => {},
+            }
+            body.push(token);
+        }
+    }
+
+    /* :calc expressions have no precedence, they are evaluated right to
+     * left like on Octo, parentheses group
+     */
+    fn calc(&self, tokens: &[String]) -> io::Result<f64> {
+        let (value, rest) = self.calc_expression(tokens, 0)?;
+        if !rest.is_empty() {
+            return self.error(format!("Unexpected {} in expression", rest[0]));
+        }
+        Ok(value)
+    }
+
+    // Depth counts every level of the recursion, parentheses, operators and unary functions
+    fn calc_expression<'a>(&self, tokens: &'a [String], depth: usize) -> io::Result<(f64, &'a [String])> {
+        let (left, rest) = self.calc_term(tokens, depth + 1)?;
+        match rest.split_first() {
+            Some((op, rest)) if op != ")" => {
+                let (right, rest) = self.calc_expression(rest, depth + 1)?;
+                match binary(op, left, right) {
+                    Some(value) => Ok((value, rest)),
+                    None => self.error(format!("Unknown operator {}", op)),
+                }
+            },
+            _ => Ok((left, rest)),
+        }
+    }
+
+    fn calc_term<'a>(&self, tokens: &'a [String], depth: usize) -> io::Result<(f64, &'a [String])> {
+        if depth > MAX_EXPRESSION_DEPTH {
+            return self.error(format!("Expression nested more than {} levels deep", MAX_EXPRESSION_DEPTH));
+        }
+        let (token, rest) = match tokens.split_first() {
+            Some(split) => split,
+            None => return self.error("Incomplete expression".to_string()),
+        };
+        if token == "(" {
+            let (value, rest) = self.calc_expression(rest, depth + 1)?;
+            return match rest.split_first() {
+                Some((close, rest)) if close == ")" => Ok((value, rest)),
+                _ => self.error("Missing )".to_string()),
+            };
+        }
+        let named = match token.as_str() {
+            "PI" => Some(std::f64::consts::PI),
+            "E" => Some(std::f64::consts::E),
+            "HERE" => Some(f64::from(self.here)),
+            _ => self.known_value(token),
+        };
+        if let Some(value) = named {
+            return Ok((value, rest));
+        }
+        let (value, rest) = self.calc_term(rest, depth + 1)?;
+        match unary(token, value) {
+            Some(value) => Ok((value, rest)),
+            None => self.error(format!("Unknown value {}", token)),
+        }
+    }
+
+    fn condition(&mut self) -> io::Result<Condition> {
+        let register = self.register()?;
+        let op = self.next_token()?;
+        Ok(match op.as_str() {
+            "key" => Condition::Key(register),
+            "-key" => Condition::NotKey(register),
+            "==" => Condition::Equal(register, self.operand()?),
+            "!=" => Condition::NotEqual(register, self.operand()?),
+            "<" => Condition::Less(register, self.operand()?),
+            ">" => Condition::Greater(register, self.operand()?),
+            "<=" => Condition::LessEqual(register, self.operand()?),
+            ">=" => Condition::GreaterEqual(register, self.operand()?),
+            _ => return self.error(format!("Unknown condition {}", op)),
+        })
+    }
+
+    // VF = 1 if a - b doesn't borrow, comparisons go through it like on Octo
+    fn emit_no_borrow(&mut self, register: u8, operand: Operand, reversed: bool) -> io::Result<()> {
+        let x = u16::from(register) << 4;
+        match (operand, reversed) {
+            (Operand::Register(y), false) => {
+                self.emit(0x8F00 | x)?;
+                self.emit(0x8F05 | u16::from(y) << 4)
+            },
+            (Operand::Register(y), true) => {
+                self.emit(0x8F00 | x)?;
+                self.emit(0x8F07 | u16::from(y) << 4)
+            },
+            (Operand::Value(value), false) => {
+                self.emit(0x6F00 | u16::from(value))?;
+                self.emit(0x8F07 | x)
+            },
+            (Operand::Value(value), true) => {
+                self.emit(0x6F00 | u16::from(value))?;
+                self.emit(0x8F05 | x)
+            },
+        }
+    }
+
+    // The instruction(s) skipping the next one when the condition is (or isn't) met
+    fn emit_skip(&mut self, condition: Condition, when: bool) -> io::Result<()> {
+        let skip_equal = |x: u8, operand: Operand, equal: bool| match operand {
+            Operand::Value(value) => (if equal { 0x3000 } else { 0x4000 }) | u16::from(x) << 8 | u16::from(value),
+            Operand::Register(y) => (if equal { 0x5000 } else { 0x9000 }) | u16::from(x) << 8 | u16::from(y) << 4,
+        };
+        let opcode = match condition {
+            Condition::Equal(x, operand) => skip_equal(x, operand, when),
+            Condition::NotEqual(x, operand) => skip_equal(x, operand, !when),
+            Condition::Key(x) => (if when { 0xE09E } else { 0xE0A1 }) | u16::from(x) << 8,
+            Condition::NotKey(x) => (if when { 0xE0A1 } else { 0xE09E }) | u16::from(x) << 8,
+            Condition::Less(x, operand) | Condition::GreaterEqual(x, operand)
+            | Condition::Greater(x, operand) | Condition::LessEqual(x, operand) => {
+                let reversed = matches!(condition, Condition::Greater(..) | Condition::LessEqual(..));
+                let flag = matches!(condition, Condition::GreaterEqual(..) | Condition::LessEqual(..));
+                self.emit_no_borrow(x, operand, reversed)?;
+                skip_equal(0xF, Operand::Value(flag as u8), when)
+            },
+        };
+        self.emit(opcode)
+    }
+
+    fn register_statement(&mut self, x: u8) -> io::Result<()> {
+        let op = self.next_token()?;
+        let x16 = u16::from(x) << 8;
+        let alu = |this: &mut Self, n: u16| -> io::Result<()> {
+            let y = this.register()?;
+            this.emit(0x8000 | x16 | u16::from(y) << 4 | n)
+        };
+        match op.as_str() {
+            ":=" => match self.peek() {
+                Some("random") => {
+                    self.next_token()?;
+                    let mask = self.byte()?;
+                    self.emit(0xC000 | x16 | u16::from(mask))
+                },
+                Some("key") => {
+                    self.next_token()?;
+                    self.emit(0xF00A | x16)
+                },
+                Some("delay") => {
+                    self.next_token()?;
+                    self.emit(0xF007 | x16)
+                },
+                _ => match self.operand()? {
+                    Operand::Register(y) => self.emit(0x8000 | x16 | u16::from(y) << 4),
+                    Operand::Value(value) => self.emit(0x6000 | x16 | u16::from(value)),
+                },
+            },
+            "+=" => match self.operand()? {
+                Operand::Register(y) => self.emit(0x8004 | x16 | u16::from(y) << 4),
+                Operand::Value(value) => self.emit(0x7000 | x16 | u16::from(value)),
+            },
+            "-=" => match self.operand()? {
+                Operand::Register(y) => self.emit(0x8005 | x16 | u16::from(y) << 4),
+                Operand::Value(value) => self.emit(0x7000 | x16 | u16::from(value.wrapping_neg())),
+            },
+            "|=" => alu(self, 0x1),
+            "&=" => alu(self, 0x2),
+            "^=" => alu(self, 0x3),
+            ">>=" => alu(self, 0x6),
+            "=-" => alu(self, 0x7),
+            "<<=" => alu(self, 0xE),
+            _ => self.error(format!("Unknown operator {}", op)),
+        }
+    }
+
+    fn directive(&mut self, token: &str) -> io::Result<()> {
+        match token {
+            ":" => {
+                let name = self.next_token()?;
+                self.define_label(name)
+            },
+            ":const" => {
+                let name = self.next_token()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+                Ok(())
+            },
+            ":calc" => {
+                let name = self.next_token()?;
+                self.expect("{")?;
+                let body = self.block()?;
+                let value = self.calc(&body)?;
+                self.constants.insert(name, value);
+                Ok(())
+            },
+            ":alias" => {
+                let name = self.next_token()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+                Ok(())
+            },
+            ":byte" => {
+                if self.peek() == Some("{") {
+                    self.next_token()?;
+                    let body = self.block()?;
+                    let value = self.calc(&body)?;
+                    self.emit_byte(value.floor() as i64 as u8)
+                } else {
+                    let byte = self.byte()?;
+                    self.emit_byte(byte)
+                }
+            },
+            ":pointer" => {
+                let at = self.here;
+                let address = self.address(at, Patch::Word)?;
+                self.emit(address)
+            },
+            ":org" => {
+                let address = self.value()?.floor() as i64;
+                if !(i64::from(START)..i64::from(END)).contains(&address) {
+                    return self.error(format!("Can't place code at {:#X}", address));
+                }
+                self.here = address as u16;
+                Ok(())
+            },
+            ":next" => {
+                self.next = Some(self.next_token()?);
+                Ok(())
+            },
+            ":unpack" => {
+                let nibble = self.nibble()?;
+                let high = u16::from(self.aliases["unpack-hi"]) << 8;
+                let low = u16::from(self.aliases["unpack-lo"]) << 8;
+                let at = self.here;
+                let address = self.address(at, Patch::UnpackHigh(nibble))?;
+                if let Some(fixup) = self.fixups.last_mut().filter(|fixup| fixup.address == at) {
+                    // The second instruction needs patching too
+                    let label = fixup.label.clone();
+                    self.fixups.push(Fixup { address: at + 2, label, patch: Patch::UnpackLow, line: self.line });
+                }
+                self.emit(0x6000 | high | u16::from(nibble) << 4 | address >> 8)?;
+                self.emit(0x6000 | low | (address & 0xFF))
+            },
+            ":call" => self.emit_with_address(0x2000),
+            ":macro" => {
+                let name = self.next_token()?;
+                let mut args = Vec::new();
+                loop {
+                    let token = self.next_token()?;
+                    if token == "{" {
+                        break;
+                    }
+                    args.push(token);
+                }
+                let body = self.block()?;
+                self.macros.insert(name, Macro { args, body });
+                Ok(())
+            },
+            // Debugger hints, nothing to assemble
+            ":breakpoint" => self.next_token().map(|_| ()),
+            ":monitor" => {
+                self.next_token()?;
+                self.next_token().map(|_| ())
+            },
+            ":assert" => {
+                if self.peek() != Some("{") {
+                    self.next_token()?;
+                }
+                self.expect("{")?;
+                self.block().map(|_| ())
+            },
+            _ => self.error(format!("Unknown directive {}", token)),
+        }
+    }
+
+    fn statement(&mut self) -> io::Result<()> {
+        let token = self.next_token()?;
+        if UNSUPPORTED.contains(&token.as_str()) {
+            return self.error(format!("{} is a SUPER-CHIP or XO-CHIP feature, only CHIP-8 can run here", token));
+        }
+        if token.starts_with(':') {
+            return self.directive(&token);
+        }
+        if let Some(register) = register_name(&token).or_else(|| self.aliases.get(&token).copied()) {
+            return self.register_statement(register);
+        }
+
+        match token.as_str() {
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | u16::from(x) << 8)
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    return self.error(format!("{} with a range is an XO-CHIP feature", token));
+                }
+                self.emit(if token == "save" { 0xF055 } else { 0xF065 } | u16::from(x) << 8)
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | u16::from(x) << 8 | u16::from(y) << 4 | u16::from(n))
+            },
+            "jump" => self.emit_with_address(0x1000),
+            "jump0" => self.emit_with_address(0xB000),
+            "native" => self.emit_with_address(0x0000),
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(if token == "delay" { 0xF015 } else { 0xF018 } | u16::from(x) << 8)
+            },
+            "i" => {
+                let op = self.next_token()?;
+                match (op.as_str(), self.peek()) {
+                    (":=", Some("hex")) => {
+                        self.next_token()?;
+                        let x = self.register()?;
+                        self.emit(0xF029 | u16::from(x) << 8)
+                    },
+                    (":=", _) => self.emit_with_address(0xA000),
+                    ("+=", _) => {
+                        let x = self.register()?;
+                        self.emit(0xF01E | u16::from(x) << 8)
+                    },
+                    _ => self.error(format!("Unknown operator {}", op)),
+                }
+            },
+            "if" => {
+                let condition = self.condition()?;
+                match self.next_token()?.as_str() {
+                    "then" => self.emit_skip(condition, false),
+                    "begin" => {
+                        self.emit_skip(condition, true)?;
+                        self.branches.push(self.here);
+                        self.emit(0x1000)
+                    },
+                    other => self.error(format!("Expected then or begin, got {}", other)),
+                }
+            },
+            "else" => {
+                let jump = match self.branches.pop() {
+                    Some(jump) => jump,
+                    None => return self.error("else without if ... begin".to_string()),
+                };
+                self.branches.push(self.here);
+                self.emit(0x1000)?;
+                self.patch_jump(jump, self.here)
+            },
+            "end" => match self.branches.pop() {
+                Some(jump) => self.patch_jump(jump, self.here),
+                None => self.error("end without if ... begin".to_string()),
+            },
+            "loop" => {
+                self.loops.push((self.here, Vec::new()));
+                Ok(())
+            },
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_skip(condition, true)?;
+                match self.loops.last_mut() {
+                    Some((_, exits)) => exits.push(self.here),
+                    None => return self.error("while outside a loop".to_string()),
+                }
+                self.emit(0x1000)
+            },
+            "again" => {
+                let (start, exits) = match self.loops.pop() {
+                    Some(open) => open,
+                    None => return self.error("again without loop".to_string()),
+                };
+                self.emit(0x1000 | start)?;
+                for exit in exits {
+                    self.patch_jump(exit, self.here)?;
+                }
+                Ok(())
+            },
+            _ => {
+                if let Some(definition) = self.macros.get(&token).cloned() {
+                    return self.expand(definition);
+                }
+                match parse_number(&token).or_else(|| self.constants.get(&token).copied()) {
+                    // Numbers on their own are data
+                    Some(value) => self.emit_byte(value.floor() as i64 as u8),
+                    // Anything else is a subroutine call
+                    None => {
+                        self.tokens.push_front(Token { text: token, line: self.line });
+                        self.emit_with_address(0x2000)
+                    },
+                }
+            },
+        }
+    }
+
+    fn expand(&mut self, definition: Macro) -> io::Result<()> {
+        self.expanded += definition.body.len();
+        if self.expanded > MAX_EXPANDED_TOKENS {
+            return self.error(format!("Macros expanded to more than {} tokens, is one calling itself?", MAX_EXPANDED_TOKENS));
+        }
+        let mut args = HashMap::new();
+        for name in &definition.args {
+            args.insert(name.clone(), self.next_token()?);
+        }
+        for token in definition.body.iter().rev() {
+            let text = args.get(token).cloned().unwrap_or_else(|| token.clone());
+            self.tokens.push_front(Token { text, line: self.line });
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<Vec<u8>> {
+        if !self.loops.is_empty() {
+            return self.error("loop without again".to_string());
+        }
+        if !self.branches.is_empty() {
+            return self.error("if ... begin without end".to_string());
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let address = match self.labels.get(&fixup.label) {
+                Some(address) => *address,
+                None => return self.error(format!("Undefined name {}", fixup.label)),
+            };
+            let offset = usize::from(fixup.address - START);
+            let bytes = match fixup.patch {
+                Patch::Address => [self.rom[offset] & 0xF0 | (address >> 8) as u8, address as u8],
+                Patch::Word => [(address >> 8) as u8, address as u8],
+                Patch::UnpackHigh(nibble) => [self.rom[offset], nibble << 4 | (address >> 8) as u8],
+                Patch::UnpackLow => [self.rom[offset], address as u8],
+            };
+            self.rom[offset..offset + 2].copy_from_slice(&bytes);
+        }
+
+        let main = match self.labels.get("main") {
+            Some(main) => *main,
+            None => return self.error("The program has no main label".to_string()),
+        };
+        if main != START {
+            self.patch_jump(START, main)?;
+        }
+        Ok(self.rom)
+    }
+}
+
+pub fn assemble(source: &str) -> io::Result<Vec<u8>> {
+    let mut assembler = Assembler::new(source);
+    while !assembler.tokens.is_empty() {
+        assembler.statement()?;
+    }
+    assembler.finish()
+}
@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/* Counts where a ROM spends its time, fed by the bus after every
+ * instruction. Time is counted in COSMAC VIP machine cycles, what
+ * each instruction would have cost on the VIP, whatever the timing
+ * mode is.
+ *
+ * Calls are followed through 2NNN and 00EE, so the time also goes
+ * to the subroutine (and its callers) it was spent in.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AddressProfile {
+    pub opcode: u16,
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClassProfile {
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FunctionProfile {
+    // Where the subroutine starts, the entry PC for the main program
+    pub address: u16,
+    pub calls: u64,
+    // Spent on the subroutine itself
+    pub self_cycles: u64,
+    // Spent on it and everything it called
+    pub total_cycles: u64,
+}
+
+// One 60hz frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameProfile {
+    pub instructions: u64,
+    pub cycles: u64,
+    // Spent waiting on a key, the vertical blank or halted
+    pub idle_cycles: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Profiler {
+    addresses: BTreeMap<u16, AddressProfile>,
+    classes: BTreeMap<&'static str, ClassProfile>,
+    calls: HashMap<u16, u64>,
+    // Subroutines being run right now, the main program first
+    path: Vec<u16>,
+    // Cycles spent on each call path
+    stacks: HashMap<Vec<u16>, u64>,
+    frames: Vec<FrameProfile>,
+    frame: FrameProfile,
+}
+
+// What kind of instruction it is, as written on the usual opcode tables
+pub fn opcode_class(opcode: u16) -> &'static str {
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            _ => "0NNN",
+        },
+        0x1 => "1NNN",
+        0x2 => "2NNN",
+        0x3 => "3XNN",
+        0x4 => "4XNN",
+        0x5 => "5XY0",
+        0x6 => "6XNN",
+        0x7 => "7XNN",
+        0x8 => match opcode & 0xF {
+            0x0 => "8XY0",
+            0x1 => "8XY1",
+            0x2 => "8XY2",
+            0x3 => "8XY3",
+            0x4 => "8XY4",
+            0x5 => "8XY5",
+            0x6 => "8XY6",
+            0x7 => "8XY7",
+            0xE => "8XYE",
+            _ => "????",
+        },
+        0x9 => "9XY0",
+        0xA => "ANNN",
+        0xB => "BNNN",
+        0xC => "CXNN",
+        0xD => "DXYN",
+        0xE => match opcode & 0xFF {
+            0x9E => "EX9E",
+            0xA1 => "EXA1",
+            _ => "????",
+        },
+        _ => match opcode & 0xFF {
+            0x07 => "FX07",
+            0x0A => "FX0A",
+            0x15 => "FX15",
+            0x18 => "FX18",
+            0x1E => "FX1E",
+            0x29 => "FX29",
+            0x33 => "FX33",
+            0x55 => "FX55",
+            0x65 => "FX65",
+            _ => "????",
+        },
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+impl Profiler {
+    pub fn new(entry_pc: u16) -> Self {
+        Profiler {
+            addresses: BTreeMap::new(),
+            classes: BTreeMap::new(),
+            calls: HashMap::new(),
+            path: vec![entry_pc],
+            stacks: HashMap::new(),
+            frames: Vec::new(),
+            frame: FrameProfile::default(),
+        }
+    }
+
+    // The machine was powered back on, the counts are kept
+    pub fn restart(&mut self, entry_pc: u16) {
+        self.path = vec![entry_pc];
+    }
+
+    /* An instruction ran, the call path only moves if it
+     * didn't stop the machine (a CALL with the stack full)
+     */
+    pub fn record(&mut self, pc: u16, opcode: u16, cycles: u32, faulted: bool) {
+        let cycles = u64::from(cycles);
+        let address = self.addresses.entry(pc).or_default();
+        address.opcode = opcode;
+        address.executions += 1;
+        address.cycles += cycles;
+
+        let class = self.classes.entry(opcode_class(opcode)).or_default();
+        class.executions += 1;
+        class.cycles += cycles;
+
+        *self.stacks.entry(self.path.clone()).or_default() += cycles;
+
+        self.frame.instructions += 1;
+        self.frame.cycles += cycles;
+
+        if faulted {
+            return;
+        }
+        if opcode >> 12 == 0x2 {
+            let target = opcode & 0x0FFF;
+            self.path.push(target);
+            *self.calls.entry(target).or_default() += 1;
+        } else if opcode == 0x00EE && self.path.len() > 1 {
+            self.path.pop();
+        }
+    }
+
+    // Time that went by without running an instruction
+    pub fn record_idle(&mut self, cycles: u32) {
+        self.frame.cycles += u64::from(cycles);
+        self.frame.idle_cycles += u64::from(cycles);
+    }
+
+    // Called on every 60hz timer tick
+    pub fn end_frame(&mut self) {
+        self.frames.push(std::mem::take(&mut self.frame));
+    }
+
+    // Busiest first
+    pub fn hot_spots(&self) -> Vec<(u16, AddressProfile)> {
+        let mut addresses: Vec<_> = self.addresses.iter().map(|(pc, profile)| (*pc, *profile)).collect();
+        addresses.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        addresses
+    }
+
+    pub fn opcode_classes(&self) -> Vec<(&'static str, ClassProfile)> {
+        let mut classes: Vec<_> = self.classes.iter().map(|(class, profile)| (*class, *profile)).collect();
+        classes.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        classes
+    }
+
+    // Most total time first, the main program is the one with no calls
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut functions: BTreeMap<u16, FunctionProfile> = BTreeMap::new();
+        for (path, cycles) in &self.stacks {
+            for (depth, address) in path.iter().enumerate() {
+                // Recursion shouldn't count the same time twice
+                if path[..depth].contains(address) {
+                    continue;
+                }
+                let function = functions.entry(*address).or_insert(FunctionProfile {
+                    address: *address,
+                    calls: self.calls.get(address).copied().unwrap_or(0),
+                    self_cycles: 0,
+                    total_cycles: 0,
+                });
+                function.total_cycles += cycles;
+            }
+            if let Some(address) = path.last() {
+                functions.get_mut(address).unwrap().self_cycles += cycles;
+            }
+        }
+
+        let mut functions: Vec<_> = functions.into_values().collect();
+        functions.sort_by(|a, b| b.total_cycles.cmp(&a.total_cycles).then(a.address.cmp(&b.address)));
+        functions
+    }
+
+    // Only the frames that ended, the one running isn't there yet
+    pub fn frames(&self) -> &[FrameProfile] {
+        &self.frames
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.addresses.values().map(|profile| profile.cycles).sum()
+    }
+
+    /* One line per call path, in the format flamegraph.pl and
+     * inferno take: "0x200;0x2A4;0x310 1234"
+     */
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(path, cycles)| {
+                let names: Vec<String> = path.iter().map(|address| format!("{:#05X}", address)).collect();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        let mut folded = lines.join("\n");
+        folded.push('\n');
+        folded
+    }
+
+    // The top entries of every table, as text
+    pub fn report(&self, top: usize) -> String {
+        let total = self.total_cycles();
+        let mut report = String::new();
+        let instructions: u64 = self.addresses.values().map(|profile| profile.executions).sum();
+        writeln!(report, "Instructions: {}, cycles: {}", instructions, total).unwrap();
+
+        writeln!(report, "\nHot spots:").unwrap();
+        writeln!(report, "  {:<5}  {:<6}  {:>12}  {:>12}  {:>6}", "PC", "Opcode", "Executions", "Cycles", "%").unwrap();
+        for (pc, profile) in self.hot_spots().iter().take(top) {
+            writeln!(report, "  {:#05X}  {:04X}    {:>12}  {:>12}  {:>6.2}",
+                pc, profile.opcode, profile.executions, profile.cycles, percent(profile.cycles, total)).unwrap();
+        }
+
+        writeln!(report, "\nOpcode classes:").unwrap();
+        writeln!(report, "  {:>6}  {:>12}  {:>12}  {:>6}", "Class", "Executions", "Cycles", "%").unwrap();
+        for (class, profile) in self.opcode_classes().iter().take(top) {
+            writeln!(report, "  {:>6}  {:>12}  {:>12}  {:>6.2}",
+                class, profile.executions, profile.cycles, percent(profile.cycles, total)).unwrap();
+        }
+
+        writeln!(report, "\nSubroutines:").unwrap();
+        writeln!(report, "  {:<5}  {:>8}  {:>12}  {:>6}  {:>12}  {:>6}", "Entry", "Calls", "Self", "%", "Total", "%").unwrap();
+        for function in self.functions().iter().take(top) {
+            writeln!(report, "  {:#05X}  {:>8}  {:>12}  {:>6.2}  {:>12}  {:>6.2}",
+                function.address, function.calls,
+                function.self_cycles, percent(function.self_cycles, total),
+                function.total_cycles, percent(function.total_cycles, total)).unwrap();
+        }
+
+        writeln!(report, "\nFrames: {}", self.frames.len()).unwrap();
+        if !self.frames.is_empty() {
+            let per_frame: Vec<u64> = self.frames.iter().map(|frame| frame.instructions).collect();
+            let cycles: u64 = self.frames.iter().map(|frame| frame.cycles).sum();
+            let idle: u64 = self.frames.iter().map(|frame| frame.idle_cycles).sum();
+            writeln!(report, "  Instructions per frame: min {}, average {:.1}, max {}",
+                per_frame.iter().min().unwrap(),
+                per_frame.iter().sum::<u64>() as f64 / per_frame.len() as f64,
+                per_frame.iter().max().unwrap()).unwrap();
+            writeln!(report, "  Busy {:.2}%, idle {:.2}% (waiting on keys, the vertical blank or halted)",
+                percent(cycles - idle, cycles), percent(idle, cycles)).unwrap();
+
+            let mut busiest: Vec<(usize, &FrameProfile)> = self.frames.iter().enumerate().collect();
+            busiest.sort_by(|a, b| b.1.instructions.cmp(&a.1.instructions).then(a.0.cmp(&b.0)));
+            writeln!(report, "  Busiest frames:").unwrap();
+            for (index, frame) in busiest.iter().take(top.min(5)) {
+                writeln!(report, "    #{}: {} instructions, {} cycles, {} idle",
+                    index, frame.instructions, frame.cycles, frame.idle_cycles).unwrap();
+            }
+        }
+        report
+    }
+}
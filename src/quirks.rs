@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/* Behaviours that changed between interpreters.
+ * Defaults match what this emulator always did, ROMs that
+ * rely on the original COSMAC VIP behaviour can turn them on.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    // DXYN waits for the next vertical blank before continuing,
+    // so at most one sprite gets drawn per frame
+    pub display_wait: bool,
+    // FX0A only returns once the key is released again,
+    // instead of as soon as it is pressed
+    pub key_wait_release: bool,
+    // 8XY6/8XYE shift VY into VX, instead of shifting VX in place
+    pub shift_vy: bool,
+    // 8XY1/8XY2/8XY3 reset VF, the VIP did them on the ALU that sets it
+    pub logic_reset_vf: bool,
+    // What FX55/FX65 leave on I
+    pub index_increment: IndexIncrement,
+    // BNNN jumps to XNN + VX (CHIP-48 and SUPER-CHIP), instead of NNN + V0
+    pub jump_vx: bool,
+    // Sprites going past the edges come back on the other side, instead of being clipped
+    pub wrap_sprites: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexIncrement {
+    // I stays where it was (SUPER-CHIP)
+    #[default]
+    Unchanged,
+    // I ends on the last register (CHIP-48)
+    ByX,
+    // I ends past the last register (COSMAC VIP)
+    ByXPlusOne,
+}
+
+impl IndexIncrement {
+    // How much I moves after going through V0..VX
+    pub fn amount(&self, x: u16) -> u16 {
+        match self {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => x,
+            IndexIncrement::ByXPlusOne => x + 1,
+        }
+    }
+}
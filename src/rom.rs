@@ -0,0 +1,182 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::octo;
+use crate::romdb::{Colors, RomInfo};
+
+/* ROM loading.
+ *
+ * Files are told apart by their content, not the extension:
+ * zip archives, Octo cartridge GIFs and anything else is taken
+ * as a raw program (.ch8, .sc8, .xo8...).
+ */
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GIF_MAGIC: &[u8] = b"GIF8";
+// Entries picked automatically when a zip has a single one of them
+const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+pub struct Rom {
+    // File name, or the entry name for zip archives
+    pub name: String,
+    pub data: Vec<u8>,
+    // Options of Octo cartridges, to run them the way they were made
+    pub options: Option<OctoOptions>,
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    ROM_EXTENSIONS.iter().any(|ext| name.ends_with(&format!(".{}", ext)))
+}
+
+/* Loads a ROM from the path, entry picks which file to use
+ * on zip archives, it can be left out if there is a single ROM on it
+ */
+pub fn load(path: &Path, entry: Option<&str>) -> io::Result<Rom> {
+    let data = fs::read(path)?;
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    from_bytes(name, data, entry)
+}
+
+// Same as load, for files already read
+pub fn from_bytes(name: String, data: Vec<u8>, entry: Option<&str>) -> io::Result<Rom> {
+    if data.starts_with(ZIP_MAGIC) {
+        load_zip_entry(data, entry)
+    } else {
+        from_file_data(name, data)
+    }
+}
+
+fn from_file_data(name: String, data: Vec<u8>) -> io::Result<Rom> {
+    if data.starts_with(GIF_MAGIC) {
+        // Cartridges carry the Octo source, not the assembled program
+        let cartridge = read_cartridge(&data)?;
+        let data = octo::assemble(&cartridge.program)
+            .map_err(|err| invalid_data(format!("Could not assemble the Octo cartridge {}: {}", name, err)))?;
+        Ok(Rom { name, data, options: Some(cartridge.options) })
+    } else {
+        Ok(Rom { name, data, options: None })
+    }
+}
+
+pub fn list_zip_entries(path: &Path) -> io::Result<Vec<String>> {
+    let archive = zip::ZipArchive::new(File::open(path)?)?;
+    Ok(archive.file_names().map(|name| name.to_owned()).collect())
+}
+
+fn load_zip_entry(data: Vec<u8>, entry: Option<&str>) -> io::Result<Rom> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+
+    let name = match entry {
+        Some(entry) => entry.to_owned(),
+        None => {
+            let roms: Vec<&str> = archive.file_names().filter(|name| is_rom_name(name)).collect();
+            match roms.as_slice() {
+                [single] => single.to_string(),
+                [] => return Err(invalid_data("No CHIP-8 ROM found in the archive")),
+                _ => return Err(invalid_data(format!(
+                    "The archive has several ROMs, pick one of: {}", roms.join(", ")))),
+            }
+        }
+    };
+
+    let mut file = archive.by_name(&name)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    from_file_data(name, data)
+}
+
+/* Options Octo stores with the program, only what can
+ * map to something in this emulator is kept.
+ */
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OctoOptions {
+    #[serde(default)]
+    pub tickrate: Option<u32>,
+    #[serde(default)]
+    pub fill_color: Option<String>,
+    #[serde(default)]
+    pub background_color: Option<String>,
+    #[serde(default)]
+    pub buzz_color: Option<String>,
+    #[serde(default)]
+    pub quiet_color: Option<String>,
+    #[serde(default)]
+    pub shift_quirks: Option<bool>,
+    #[serde(default)]
+    pub load_store_quirks: Option<bool>,
+    #[serde(default)]
+    pub clip_quirks: Option<bool>,
+    #[serde(default)]
+    pub jump_quirks: Option<bool>,
+    #[serde(default)]
+    pub logic_quirks: Option<bool>,
+    #[serde(default)]
+    pub v_blank_quirks: Option<bool>,
+}
+
+impl OctoOptions {
+    /* The options as a database entry, so cartridges get their
+     * speed, palette and quirks the same way known ROMs do
+     */
+    pub fn rom_info(&self, hash: String) -> RomInfo {
+        let colors = match (&self.background_color, &self.fill_color) {
+            (Some(background), Some(fill)) => Some(Colors {
+                pixels: vec![background.clone(), fill.clone()],
+                buzzer: self.buzz_color.clone(),
+                silence: self.quiet_color.clone(),
+            }),
+            _ => None,
+        };
+        let mut info = RomInfo { hash, tickrate: self.tickrate, colors, ..RomInfo::default() };
+        info.quirks.vblank = self.v_blank_quirks;
+        info.quirks.shift = self.shift_quirks;
+        info.quirks.memory_leave_i_unchanged = self.load_store_quirks;
+        // Octo clips sprites when the quirk is on, the database says if they wrap
+        info.quirks.wrap = self.clip_quirks.map(|clip| !clip);
+        info.quirks.jump = self.jump_quirks;
+        info.quirks.logic = self.logic_quirks;
+        info
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Cartridge {
+    pub program: String,
+    #[serde(default)]
+    pub options: OctoOptions,
+}
+
+/* Octo cartridges hide a JSON payload inside the first frame of the
+ * GIF, 2 bits on the low bits of each pixel palette index (most
+ * significant pair first), so 4 pixels per byte.
+ * The payload starts with its length as a big endian u32.
+ */
+pub fn read_cartridge(gif: &[u8]) -> io::Result<Cartridge> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(gif).map_err(invalid_data)?;
+    let frame = decoder.read_next_frame()
+        .map_err(invalid_data)?
+        .ok_or_else(|| invalid_data("The GIF has no frames"))?;
+
+    let bytes: Vec<u8> = frame.buffer.chunks_exact(4)
+        .map(|pixels| pixels.iter().fold(0, |byte, pixel| (byte << 2) | (pixel & 0x3)))
+        .collect();
+    if bytes.len() < 4 {
+        return Err(invalid_data("Not an Octo cartridge"));
+    }
+
+    let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let payload = bytes.get(4..4 + size).ok_or_else(|| invalid_data("Not an Octo cartridge"))?;
+    serde_json::from_slice(payload).map_err(|_| invalid_data("Not an Octo cartridge"))
+}
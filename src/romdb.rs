@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::machine::{MachineConfig, Variant};
+use crate::memory::MemoryLayout;
+use crate::gamepad::{Axis, AxisBinding, ButtonBinding, GamepadProfile};
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::timing::ClockRate;
+
+/* ROM metadata, following the community chip-8-database format
+ * (https://github.com/chip-8/chip-8-database), the bundled copy
+ * lives under data/chip-8-database.
+ * ROMs are identified by the SHA-1 of their bytes.
+ */
+const PLATFORMS: &str = include_str!("../data/chip-8-database/platforms.json");
+const PROGRAMS: &str = include_str!("../data/chip-8-database/programs.json");
+const HASHES: &str = include_str!("../data/chip-8-database/sha1-hashes.json");
+
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Every quirk is optional, so the ROM entries can override only some
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuirkSet {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+impl QuirkSet {
+    fn merge(&self, over: &QuirkSet) -> QuirkSet {
+        QuirkSet {
+            shift: over.shift.or(self.shift),
+            memory_increment_by_x: over.memory_increment_by_x.or(self.memory_increment_by_x),
+            memory_leave_i_unchanged: over.memory_leave_i_unchanged.or(self.memory_leave_i_unchanged),
+            wrap: over.wrap.or(self.wrap),
+            jump: over.jump.or(self.jump),
+            vblank: over.vblank.or(self.vblank),
+            logic: over.logic.or(self.logic),
+        }
+    }
+
+    // Name and value of every quirk that is set, in the database naming
+    pub fn list(&self) -> Vec<(&'static str, bool)> {
+        let all = [
+            ("shift", self.shift),
+            ("memoryIncrementByX", self.memory_increment_by_x),
+            ("memoryLeaveIUnchanged", self.memory_leave_i_unchanged),
+            ("wrap", self.wrap),
+            ("jump", self.jump),
+            ("vblank", self.vblank),
+            ("logic", self.logic),
+        ];
+        all.iter().filter_map(|(name, value)| value.map(|value| (*name, value))).collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub default_tickrate: Option<u32>,
+    #[serde(default)]
+    pub quirks: QuirkSet,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Colors {
+    // Background first, then the foreground colour(s)
+    #[serde(default)]
+    pub pixels: Vec<String>,
+    #[serde(default)]
+    pub buzzer: Option<String>,
+    #[serde(default)]
+    pub silence: Option<String>,
+}
+
+/* A single ROM, as found under a program "roms" entry.
+ * title and quirks are not upstream fields, they only
+ * make sense on the local overrides file.
+ */
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RomEntry {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub embedded_title: Option<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    #[serde(default)]
+    pub quirky_platforms: BTreeMap<String, QuirkSet>,
+    #[serde(default)]
+    pub quirks: QuirkSet,
+    #[serde(default)]
+    pub tickrate: Option<u32>,
+    #[serde(default)]
+    pub start_address: Option<u16>,
+    #[serde(default)]
+    pub colors: Option<Colors>,
+    // Hints like "up": 5, "a": 6
+    #[serde(default)]
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl RomEntry {
+    fn merge(self, over: RomEntry) -> RomEntry {
+        let mut quirky_platforms = self.quirky_platforms;
+        quirky_platforms.extend(over.quirky_platforms);
+        let mut keys = self.keys;
+        keys.extend(over.keys);
+
+        RomEntry {
+            title: over.title.or(self.title),
+            file: over.file.or(self.file),
+            embedded_title: over.embedded_title.or(self.embedded_title),
+            platforms: if over.platforms.is_empty() { self.platforms } else { over.platforms },
+            quirky_platforms,
+            quirks: self.quirks.merge(&over.quirks),
+            tickrate: over.tickrate.or(self.tickrate),
+            start_address: over.start_address.or(self.start_address),
+            colors: over.colors.or(self.colors),
+            keys,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Program {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub release: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub roms: BTreeMap<String, RomEntry>,
+}
+
+// Everything known about a ROM, with the platform and overrides resolved
+#[derive(Clone, Debug, Default)]
+pub struct RomInfo {
+    pub hash: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub authors: Vec<String>,
+    pub platform: Option<Platform>,
+    pub quirks: QuirkSet,
+    // Instructions per frame
+    pub tickrate: Option<u32>,
+    pub start_address: Option<u16>,
+    pub colors: Option<Colors>,
+    pub keys: BTreeMap<String, u8>,
+}
+
+fn parse_color(color: &str) -> Option<[u8; 4]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8, 255])
+}
+
+impl RomInfo {
+    // The variant closest to the platform the ROM was made for
+    pub fn variant(&self) -> Option<Variant> {
+        match self.platform.as_ref()?.id.as_str() {
+            "originalChip8" | "hybridVIP" | "chip8x" => Some(Variant::Vip),
+            "modernChip8" => Some(Variant::Chip8),
+            "chip48" => Some(Variant::Chip48),
+            "superchip1" | "superchip" => Some(Variant::SuperChip),
+            // XO-CHIP and MegaChip need instructions this emulator doesn't have
+            _ => None,
+        }
+    }
+
+    // Quirks, speed and load address, the variant is picked by MachineConfig::resolve
+    pub fn apply_config(&self, config: &mut MachineConfig) {
+        self.apply_quirks(&mut config.quirks);
+        self.apply_layout(&mut config.layout);
+        if let Some(rate) = self.clock_rate() {
+            config.rate = rate;
+        }
+    }
+
+    // Quirks the database leaves out keep what the machine had
+    pub fn apply_quirks(&self, quirks: &mut Quirks) {
+        let set = &self.quirks;
+        if let Some(vblank) = set.vblank {
+            quirks.display_wait = vblank;
+        }
+        // The database says when VX is shifted in place
+        if let Some(shift) = set.shift {
+            quirks.shift_vy = !shift;
+        }
+        if let Some(logic) = set.logic {
+            quirks.logic_reset_vf = logic;
+        }
+        if let Some(jump) = set.jump {
+            quirks.jump_vx = jump;
+        }
+        if let Some(wrap) = set.wrap {
+            quirks.wrap_sprites = wrap;
+        }
+        // Leaving I alone wins, then increment by X, both off is the VIP X + 1
+        match (set.memory_leave_i_unchanged, set.memory_increment_by_x) {
+            (Some(true), _) => quirks.index_increment = IndexIncrement::Unchanged,
+            (_, Some(true)) => quirks.index_increment = IndexIncrement::ByX,
+            (Some(false), _) => quirks.index_increment = IndexIncrement::ByXPlusOne,
+            _ => {},
+        }
+    }
+
+    pub fn clock_rate(&self) -> Option<ClockRate> {
+        self.tickrate.map(ClockRate::InstructionsPerFrame)
+    }
+
+    // ROMs with a start address load and start running there
+    pub fn apply_layout(&self, layout: &mut MemoryLayout) {
+        if let Some(start_address) = self.start_address {
+            layout.load_address = start_address;
+            layout.entry_pc = start_address;
+        }
+    }
+
+    // Foreground and background colours, as RGBA
+    pub fn palette(&self) -> Option<([u8; 4], [u8; 4])> {
+        let pixels = &self.colors.as_ref()?.pixels;
+        let off = parse_color(pixels.first()?)?;
+        let on = parse_color(pixels.get(1)?)?;
+        Some((on, off))
+    }
+
+    // Builds a gamepad profile out of the key hints, if there are any
+    pub fn gamepad_profile(&self) -> Option<GamepadProfile> {
+        let key = |name: &str| self.keys.get(name).copied();
+        if self.keys.is_empty() {
+            return None;
+        }
+
+        // Axes are always replaced, a missing hint leaves that side unbound
+        let mut profile = GamepadProfile::default();
+        for (x, y) in [(Axis::X, Axis::Y), (Axis::PovX, Axis::PovY)].iter() {
+            profile.axes.push(AxisBinding { axis: *x, negative: key("left"), positive: key("right") });
+            profile.axes.push(AxisBinding { axis: *y, negative: key("up"), positive: key("down") });
+        }
+        // Buttons without a hint keep the preset ones
+        for (button, name) in ["a", "b"].iter().enumerate() {
+            if let Some(key) = key(name) {
+                profile.buttons.push(ButtonBinding { button: button as u32, key });
+            }
+        }
+        Some(profile)
+    }
+}
+
+pub struct RomDatabase {
+    platforms: Vec<Platform>,
+    programs: Vec<Program>,
+    hashes: BTreeMap<String, usize>,
+    overrides: BTreeMap<String, RomEntry>,
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+impl RomDatabase {
+    pub fn new(platforms: &str, programs: &str, hashes: &str) -> io::Result<RomDatabase> {
+        Ok(RomDatabase {
+            platforms: serde_json::from_str(platforms).map_err(invalid_data)?,
+            programs: serde_json::from_str(programs).map_err(invalid_data)?,
+            hashes: serde_json::from_str(hashes).map_err(invalid_data)?,
+            overrides: BTreeMap::new(),
+        })
+    }
+
+    pub fn bundled() -> RomDatabase {
+        RomDatabase::new(PLATFORMS, PROGRAMS, HASHES).expect("Bundled ROM database is invalid")
+    }
+
+    /* Local overrides, a JSON object from SHA-1 to a ROM entry:
+     *   { "<sha1>": { "title": "My game", "platforms": ["originalChip8"], "tickrate": 20 } }
+     * A missing file is not an error.
+     */
+    pub fn load_overrides(&mut self, path: &Path) -> io::Result<()> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let overrides: BTreeMap<String, RomEntry> = serde_json::from_str(&text).map_err(invalid_data)?;
+        for (hash, entry) in overrides {
+            self.overrides.insert(hash.to_lowercase(), entry);
+        }
+        Ok(())
+    }
+
+    // Programs on the bundled list, the overrides are not counted
+    pub fn program_count(&self) -> usize {
+        self.programs.len()
+    }
+
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|platform| platform.id == id)
+    }
+
+    pub fn lookup_rom(&self, rom: &[u8]) -> Option<RomInfo> {
+        self.lookup(&sha1_hex(rom))
+    }
+
+    pub fn lookup(&self, hash: &str) -> Option<RomInfo> {
+        let hash = hash.to_lowercase();
+        let program = self.hashes.get(&hash).and_then(|idx| self.programs.get(*idx));
+        let entry = program.and_then(|program| program.roms.get(&hash)).cloned();
+        let over = self.overrides.get(&hash).cloned();
+
+        let entry = match (entry, over) {
+            (Some(entry), Some(over)) => entry.merge(over),
+            (Some(entry), None) => entry,
+            (None, Some(over)) => over,
+            (None, None) => return None,
+        };
+
+        // The first listed platform is the one the ROM was made for
+        let platform = entry.platforms.first().and_then(|id| self.platform(id)).cloned();
+        let mut quirks = platform.as_ref().map(|platform| platform.quirks).unwrap_or_default();
+        if let Some(quirky) = platform.as_ref().and_then(|platform| entry.quirky_platforms.get(&platform.id)) {
+            quirks = quirks.merge(quirky);
+        }
+        quirks = quirks.merge(&entry.quirks);
+
+        Some(RomInfo {
+            title: entry.title.or_else(|| program.map(|program| program.title.clone())),
+            description: program.and_then(|program| program.description.clone()),
+            release: program.and_then(|program| program.release.clone()),
+            authors: program.map(|program| program.authors.clone()).unwrap_or_default(),
+            tickrate: entry.tickrate.or_else(|| platform.as_ref().and_then(|platform| platform.default_tickrate)),
+            platform,
+            quirks,
+            start_address: entry.start_address,
+            colors: entry.colors,
+            keys: entry.keys,
+            hash,
+        })
+    }
+}
@@ -0,0 +1,427 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::bus::Bus;
+use crate::keyboard::State;
+use crate::rom;
+use crate::romdb;
+use crate::savestate::SaveState;
+use crate::timing::TIMER_HZ;
+
+/* JSON-RPC 2.0 remote control, one request per line:
+ *
+ *   {"jsonrpc": "2.0", "id": 1, "method": "step", "params": {"count": 10}}
+ *
+ * over a local TCP or Unix socket, for scripts and test tooling.
+ * Like the GDB stub it is polled by the frontend, requests run
+ * between frames against the Bus.
+ */
+
+// Standard JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// Anything failing on our side (files, ROMs...)
+const SERVER_ERROR: i64 = -32000;
+
+// A single request can't keep the frontend busy for long, larger counts are cut down
+const MAX_STEPS: u64 = 1_000_000;
+// A minute of emulated time
+const MAX_FRAMES: u64 = 3600;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+fn invalid_params<S: ToString>(message: S) -> RpcError {
+    RpcError { code: INVALID_PARAMS, message: message.to_string() }
+}
+
+fn server_error<S: ToString>(message: S) -> RpcError {
+    RpcError { code: SERVER_ERROR, message: message.to_string() }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buffer),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buffer),
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.write_all(bytes),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write_all(bytes),
+        }
+    }
+}
+
+struct Client {
+    stream: Stream,
+    // Bytes received that don't make a full line yet
+    input: Vec<u8>,
+}
+
+pub struct RpcServer {
+    listener: Listener,
+    clients: Vec<Client>,
+}
+
+impl RpcServer {
+    /* "tcp:127.0.0.1:7070", "unix:/tmp/chip8.sock" or
+     * just "127.0.0.1:7070" for TCP
+     */
+    pub fn bind(address: &str) -> io::Result<RpcServer> {
+        let listener = if let Some(path) = address.strip_prefix("unix:") {
+            RpcServer::bind_unix(Path::new(path))?
+        } else {
+            let address = address.strip_prefix("tcp:").unwrap_or(address);
+            let listener = TcpListener::bind(address)?;
+            listener.set_nonblocking(true)?;
+            Listener::Tcp(listener)
+        };
+        Ok(RpcServer { listener, clients: Vec::new() })
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(path: &Path) -> io::Result<Listener> {
+        // A socket left over by a server that was killed, nobody answers on it
+        if path.exists() && UnixStream::connect(path).is_err() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Listener::Unix(listener, path.to_owned()))
+    }
+
+    #[cfg(not(unix))]
+    fn bind_unix(_path: &Path) -> io::Result<Listener> {
+        Err(io::Error::new(ErrorKind::Unsupported, "Unix sockets are not available here"))
+    }
+
+    // Only for TCP, mostly to find the port when bound to port 0
+    pub fn local_port(&self) -> Option<u16> {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.local_addr().ok().map(|address| address.port()),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+
+    fn accept(&mut self) -> io::Result<Option<Stream>> {
+        let accepted = match &self.listener {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        };
+        match accepted {
+            Ok(stream) => {
+                stream.set_nonblocking(true)?;
+                Ok(Some(stream))
+            },
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Accepts clients and answers every full request they sent
+    pub fn poll(&mut self, bus: &mut Bus) -> io::Result<()> {
+        while let Some(stream) = self.accept()? {
+            self.clients.push(Client { stream, input: Vec::new() });
+        }
+
+        let mut buffer = [0; 4096];
+        // Clients that went away (or failed) are dropped
+        self.clients.retain_mut(|client| {
+            loop {
+                match client.stream.read(&mut buffer) {
+                    Ok(0) => return false,
+                    Ok(size) => client.input.extend_from_slice(&buffer[..size]),
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => return false,
+                }
+            }
+
+            while let Some(end) = client.input.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = client.input.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(response) = handle_request(bus, &line) {
+                    // Replies go out whole, even if the client reads slowly
+                    let written = client.stream.set_nonblocking(false)
+                        .and_then(|_| client.stream.write_all(format!("{}\n", response).as_bytes()))
+                        .and_then(|_| client.stream.set_nonblocking(true));
+                    if written.is_err() {
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+        Ok(())
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        // Otherwise the next bind on the same path fails
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/* Handles a single request line, returning the response line
+ * (None for notifications, requests without an id)
+ */
+pub fn handle_request(bus: &mut Bus, line: &str) -> Option<String> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => return Some(error_response(Value::Null, RpcError { code: PARSE_ERROR, message: err.to_string() })),
+    };
+
+    let id = request.get("id").cloned();
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) => method,
+        None => return Some(error_response(id.unwrap_or(Value::Null),
+            RpcError { code: INVALID_REQUEST, message: "Missing method".to_owned() })),
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = call(bus, method, &params);
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }).to_string(),
+        Err(err) => error_response(id, err),
+    })
+}
+
+fn error_response(id: Value, err: RpcError) -> String {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": err.code, "message": err.message } }).to_string()
+}
+
+fn param_u64(params: &Value, name: &str, default: Option<u64>) -> Result<u64, RpcError> {
+    match params.get(name) {
+        Some(value) => value.as_u64().ok_or_else(|| invalid_params(format!("{} must be a positive integer", name))),
+        None => default.ok_or_else(|| invalid_params(format!("Missing {}", name))),
+    }
+}
+
+fn param_u16(params: &Value, name: &str) -> Result<u16, RpcError> {
+    let value = param_u64(params, name, None)?;
+    if value > 0xFFFF {
+        return Err(invalid_params(format!("{} must fit on 16 bits", name)));
+    }
+    Ok(value as u16)
+}
+
+fn param_u8(params: &Value, name: &str) -> Result<u8, RpcError> {
+    let value = param_u64(params, name, None)?;
+    if value > 0xFF {
+        return Err(invalid_params(format!("{} must fit on 8 bits", name)));
+    }
+    Ok(value as u8)
+}
+
+fn param_bytes(params: &Value, name: &str) -> Result<Vec<u8>, RpcError> {
+    let values = params.get(name)
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid_params(format!("{} must be an array of bytes", name)))?;
+    values.iter()
+        .map(|value| value.as_u64().filter(|byte| *byte <= 0xFF).map(|byte| byte as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| invalid_params(format!("{} must be an array of bytes", name)))
+}
+
+fn registers_json(bus: &Bus) -> Value {
+    let registers = bus.registers();
+    json!({
+        "v": registers.v,
+        "i": registers.i,
+        "pc": registers.pc,
+        "sp": registers.sp,
+        "delay": bus.state.delay,
+        "sound": bus.state.sound,
+    })
+}
+
+fn call(bus: &mut Bus, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "load_rom" => {
+            let data = match params.get("path").and_then(Value::as_str) {
+                Some(path) => {
+                    let entry = params.get("entry").and_then(Value::as_str);
+                    rom::load(Path::new(path), entry).map_err(server_error)?.data
+                },
+                None => param_bytes(params, "data")?,
+            };
+            bus.load_rom(&data);
+            Ok(json!({ "size": data.len(), "sha1": romdb::sha1_hex(&data) }))
+        },
+        "reset" => {
+            bus.reset();
+            Ok(Value::Bool(true))
+        },
+        "step" => {
+            // Stops early on a fault, nothing would run anyway
+            let count = param_u64(params, "count", Some(1))?.min(MAX_STEPS);
+            for _ in 0..count {
+                if bus.fault().is_some() {
+                    break;
+                }
+                bus.step();
+            }
+            Ok(registers_json(bus))
+        },
+        "run_frames" => {
+            let count = param_u64(params, "count", Some(1))?.min(MAX_FRAMES);
+            let mut frames = 0;
+            while frames < count && bus.fault().is_none() {
+                bus.run_for(Duration::from_secs_f64(1.0 / TIMER_HZ));
+                frames += 1;
+                if bus.take_breakpoint_hit().is_some() {
+                    break;
+                }
+            }
+            Ok(json!({ "frames": frames }))
+        },
+        "press_key" | "release_key" => {
+            let key = param_u64(params, "key", None)?;
+            if key > 0xF {
+                return Err(invalid_params("key must be between 0 and 15"));
+            }
+            let state = if method == "press_key" { State::PRESSED } else { State::RELEASED };
+            // Seen by the next instruction
+            let now = bus.time();
+            bus.queue_key(key as u8, state, now);
+            Ok(Value::Bool(true))
+        },
+        "get_registers" => Ok(registers_json(bus)),
+        "set_registers" => {
+            // Everything is checked before anything changes
+            let mut registers = bus.registers();
+            if params.get("v").is_some() {
+                let v = param_bytes(params, "v")?;
+                if v.len() > registers.v.len() {
+                    return Err(invalid_params(format!("v has {} registers, there are only {}", v.len(), registers.v.len())));
+                }
+                registers.v[..v.len()].copy_from_slice(&v);
+            }
+            if params.get("i").is_some() {
+                registers.i = param_u16(params, "i")?;
+            }
+            if params.get("pc").is_some() {
+                registers.pc = param_u16(params, "pc")?;
+            }
+            if params.get("sp").is_some() {
+                let depth = bus.config().stack_depth as u64;
+                let sp = param_u64(params, "sp", None)?;
+                if sp > depth {
+                    return Err(invalid_params(format!("sp must be between 0 and the stack depth ({})", depth)));
+                }
+                registers.sp = sp as u16;
+            }
+            let delay = params.get("delay").map(|_| param_u8(params, "delay")).transpose()?;
+            let sound = params.get("sound").map(|_| param_u8(params, "sound")).transpose()?;
+
+            bus.set_registers(&registers);
+            if let Some(delay) = delay {
+                bus.state.delay = delay;
+            }
+            if let Some(sound) = sound {
+                bus.state.sound = sound;
+            }
+            Ok(registers_json(bus))
+        },
+        "read_memory" => {
+            // Goes around the end, never more than the whole memory
+            let size = bus.memory.size() as u64;
+            let address = param_u64(params, "address", None)?;
+            let length = param_u64(params, "length", None)?.min(size);
+            let bytes: Vec<u8> = (0..length)
+                .map(|offset| bus.memory[(address.wrapping_add(offset) % size) as u16])
+                .collect();
+            Ok(json!(bytes))
+        },
+        "write_memory" => {
+            // Goes around the write protection, like a debugger would
+            let size = bus.memory.size() as u64;
+            let address = param_u64(params, "address", None)?;
+            let data = param_bytes(params, "data")?;
+            for (offset, byte) in data.iter().take(size as usize).enumerate() {
+                bus.memory[(address.wrapping_add(offset as u64) % size) as u16] = *byte;
+            }
+            Ok(Value::Bool(true))
+        },
+        "get_vram" => {
+            let vram = bus.get_vram();
+            Ok(json!({
+                "width": vram.width(),
+                "height": vram.height(),
+                "stride": vram.bitmap_stride(),
+                "bitmap": vram.to_bitmap(),
+            }))
+        },
+        "get_status" => {
+            Ok(json!({
+                "fault": bus.fault().map(|fault| fault.to_string()),
+                "time": bus.time().as_secs_f64(),
+                "call_stack": bus.call_stack(),
+            }))
+        },
+        "save_state" => {
+            let state = bus.save_state();
+            match params.get("path").and_then(Value::as_str) {
+                Some(path) => {
+                    state.save(Path::new(path)).map_err(server_error)?;
+                    Ok(Value::Bool(true))
+                },
+                None => serde_json::to_value(&state).map_err(server_error),
+            }
+        },
+        "load_state" => {
+            let state = match (params.get("path").and_then(Value::as_str), params.get("state")) {
+                (Some(path), _) => SaveState::load(Path::new(path)).map_err(server_error)?,
+                (None, Some(state)) => SaveState::from_json(&state.to_string()).map_err(invalid_params)?,
+                (None, None) => return Err(invalid_params("Missing path or state")),
+            };
+            bus.load_state(&state).map_err(invalid_params)?;
+            Ok(registers_json(bus))
+        },
+        _ => Err(RpcError { code: METHOD_NOT_FOUND, message: format!("Unknown method {}", method) }),
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::busstate::BusState;
+use crate::cpu::Registers;
+use crate::keyboard::Keyboard;
+use crate::machine::MachineConfig;
+use crate::timing::Timing;
+
+// Bumped whenever the layout changes, older states are refused
+pub const SAVE_STATE_VERSION: u32 = 1;
+
+/* Everything needed to continue a machine later, stored as JSON.
+ * The machine config comes along, so the state can be loaded
+ * into a Bus built with a different one.
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SaveState {
+    pub version: u32,
+    pub config: MachineConfig,
+    // The ROM as loaded, what reset goes back to
+    pub rom: Vec<u8>,
+    pub registers: Registers,
+    pub stack: Vec<u16>,
+    // CXNN continues from them, see CPU::rng_state
+    pub rng_seed: u64,
+    #[serde(default)]
+    pub rng_draws: u64,
+    pub memory: Vec<u8>,
+    // 1 bit per pixel, see Framebuffer::to_bitmap
+    pub vram: Vec<u8>,
+    pub state: BusState,
+    pub keyboard: Keyboard,
+    pub timing: Timing,
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+impl SaveState {
+    pub fn to_json(&self) -> io::Result<String> {
+        serde_json::to_string(self).map_err(invalid_data)
+    }
+
+    pub fn from_json(text: &str) -> io::Result<SaveState> {
+        let state: SaveState = serde_json::from_str(text).map_err(invalid_data)?;
+        if state.version != SAVE_STATE_VERSION {
+            return Err(invalid_data(format!("Save state version {} is not supported", state.version)));
+        }
+        state.config.validate()?;
+        Ok(state)
+    }
+
+    pub fn load(path: &Path) -> io::Result<SaveState> {
+        SaveState::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json()?)
+    }
+}
@@ -0,0 +1,261 @@
+use std::io;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// Delay and sound timers always count down at 60hz
+pub const TIMER_HZ: f64 = 60.0;
+
+/* Never try to catch up more than this in a single call,
+ * otherwise a long stall (window being dragged, debugger...)
+ * would make the emulator run flat out for a while
+ */
+const MAX_CATCH_UP: f64 = 0.25;
+
+/* COSMAC VIP timing.
+ *
+ * The VIP runs at 1.7609 MHz with 8 clocks per machine cycle, so each
+ * 60hz frame has about 3668 machine cycles, the 1861 display DMA
+ * (32 rows x 4 scanlines x 8 bytes) plus its interrupt handler steal
+ * roughly 1100 of them, what is left is what the interpreter gets.
+ */
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+const VIP_FRAME_OVERHEAD: u32 = 1100;
+// Every instruction goes through the interpreter fetch/decode loop first
+const VIP_FETCH_CYCLES: u32 = 40;
+
+/* Approximate machine cycles each instruction takes on the original
+ * VIP interpreter, based on the published disassemblies of it.
+ * vx is the value of VX before the instruction ran, as both the
+ * sprite alignment (DXYN) and the BCD conversion (FX33) depend on it.
+ */
+pub fn vip_cycles(opcode: u16, skipped: bool, vx: u8) -> u32 {
+    let x = ((opcode >> 8) & 0xF) as u32;
+    let n = (opcode & 0xF) as u32;
+    let skip = if skipped { 4 } else { 0 };
+
+    let cost = match opcode >> 12 {
+        0x0 => match opcode {
+            // Clearing is a loop over the 256 bytes of the display page
+            0x00E0 => 3078,
+            0x00EE => 10,
+            // 0NNN machine code routines can't be timed
+            _ => 0,
+        },
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10 + skip,
+        0x5 | 0x9 => 14 + skip,
+        0x6 => 6,
+        0x7 => 10,
+        0x8 => 44,
+        0xA => 12,
+        0xB => 22,
+        0xC => 36,
+        // Unaligned sprites have to be shifted across two bytes
+        0xD => 26 + n * if vx & 0x7 == 0 { 38 } else { 66 },
+        0xE => 14 + skip,
+        0xF => match opcode & 0xFF {
+            0x07 | 0x15 | 0x18 => 10,
+            // A single pass of the keypad scan
+            0x0A => 19,
+            0x1E | 0x29 => 16,
+            // Done by repeated subtraction, so bigger digits cost more
+            0x33 => 80 + 16 * (vx / 100 + (vx / 10) % 10 + vx % 10) as u32,
+            0x55 | 0x65 => 14 + 14 * (x + 1),
+            _ => 0,
+        },
+        _ => 0,
+    };
+
+    VIP_FETCH_CYCLES + cost
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimingMode {
+    // Every instruction takes the same time, set by the ClockRate
+    #[default]
+    Instructions,
+    // Instructions cost what they did on the COSMAC VIP
+    VipCycles,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockRate {
+    // Instructions per second
+    Hz(u32),
+    // Instructions per 60hz frame, the usual way CHIP-8 speed is described
+    InstructionsPerFrame(u32),
+}
+
+impl ClockRate {
+    // Emulated seconds each instruction takes
+    fn period(&self) -> f64 {
+        match *self {
+            ClockRate::Hz(hz) => 1.0 / hz.max(1) as f64,
+            ClockRate::InstructionsPerFrame(ipf) => 1.0 / (TIMER_HZ * ipf.max(1) as f64),
+        }
+    }
+}
+
+impl Default for ClockRate {
+    fn default() -> Self {
+        ClockRate::InstructionsPerFrame(5)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tick {
+    Cpu,
+    Timers,
+}
+
+/* Decouples the emulated time from the host refresh.
+ *
+ * The frontend feeds the real time elapsed (scaled by the speed
+ * multiplier) and then drains ticks, which come out in emulated
+ * time order, so the CPU and the 60hz timers stay in sync no matter
+ * how many frames the host drops.
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Timing {
+    rate: ClockRate,
+    mode: TimingMode,
+    cpu_period: f64,
+    speed: f64,
+    // Emulated time available to be spent
+    pending: f64,
+    // Emulated time since power on
+    now: f64,
+    // Time left until the next instruction and timer tick
+    cpu_next: f64,
+    timer_next: f64,
+}
+
+impl Timing {
+    pub fn new(rate: ClockRate) -> Self {
+        Self {
+            rate,
+            mode: TimingMode::Instructions,
+            cpu_period: rate.period(),
+            speed: 1.0,
+            pending: 0.0,
+            now: 0.0,
+            cpu_next: 0.0,
+            timer_next: 1.0 / TIMER_HZ,
+        }
+    }
+
+    pub fn rate(&self) -> ClockRate {
+        self.rate
+    }
+
+    pub fn set_rate(&mut self, rate: ClockRate) {
+        self.rate = rate;
+        self.cpu_period = rate.period();
+        self.cpu_next = self.cpu_next.min(self.cpu_period);
+    }
+
+    pub fn mode(&self) -> TimingMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: TimingMode) {
+        self.mode = mode;
+    }
+
+    // Emulated seconds a single machine cycle takes in VIP mode
+    fn vip_cycle_period() -> f64 {
+        1.0 / (TIMER_HZ * (VIP_CYCLES_PER_FRAME - VIP_FRAME_OVERHEAD) as f64)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // 1.0 is real time, > 1.0 fast forward, < 1.0 slow motion
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(0.0);
+    }
+
+    pub fn now(&self) -> Duration {
+        Duration::from_secs_f64(self.now)
+    }
+
+    pub fn advance(&mut self, elapsed: Duration) {
+        let scaled = elapsed.as_secs_f64() * self.speed;
+        self.pending = (self.pending + scaled).min(MAX_CATCH_UP * self.speed.max(1.0));
+    }
+
+    // Makes sure the next tick is due, to run one instruction at a time
+    pub fn allow_next_tick(&mut self) {
+        self.pending = self.pending.max(self.cpu_next.min(self.timer_next));
+    }
+
+    // Drops the time not spent yet, so a pause doesn't get caught up on
+    pub fn discard_pending(&mut self) {
+        self.pending = 0.0;
+    }
+
+    /* Returns the next thing to run, or None once the elapsed time was spent.
+     * After running a Tick::Cpu, its cost has to be reported with consume,
+     * the tick itself only accounts for one instruction (or one VIP cycle)
+     */
+    pub fn next_tick(&mut self) -> Option<Tick> {
+        let next = self.cpu_next.min(self.timer_next);
+        if next > self.pending {
+            return None;
+        }
+
+        self.pending -= next;
+        self.now += next;
+        self.cpu_next -= next;
+        self.timer_next -= next;
+
+        // Timers go first when both land on the same instant
+        if self.timer_next <= 0.0 {
+            self.timer_next += 1.0 / TIMER_HZ;
+            Some(Tick::Timers)
+        } else {
+            self.cpu_next += match self.mode {
+                TimingMode::Instructions => self.cpu_period,
+                TimingMode::VipCycles => Timing::vip_cycle_period(),
+            };
+            Some(Tick::Cpu)
+        }
+    }
+
+    /* Takes the clock from a saved Timing, the rate, mode and speed
+     * stay as they are. Saved values are checked first: an instruction
+     * never waits over a second (1hz), a timer tick over 1/60
+     */
+    pub fn restore_clock(&mut self, saved: &Timing) -> io::Result<()> {
+        let in_range = |value: f64, max: f64| value.is_finite() && value >= 0.0 && value <= max;
+        if !in_range(saved.now, f64::MAX) || !in_range(saved.pending, f64::MAX)
+            || !in_range(saved.cpu_next, 1.0) || !in_range(saved.timer_next, 1.0 / TIMER_HZ) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "The saved clock is out of range"));
+        }
+
+        self.now = saved.now;
+        // Same limit advance has
+        self.pending = saved.pending.min(MAX_CATCH_UP * self.speed.max(1.0));
+        self.cpu_next = saved.cpu_next;
+        self.timer_next = saved.timer_next;
+        Ok(())
+    }
+
+    // Cycles the last Tick::Cpu took, only relevant in VIP mode
+    pub fn consume(&mut self, cycles: u32) {
+        if self.mode == TimingMode::VipCycles && cycles > 1 {
+            self.cpu_next += (cycles - 1) as f64 * Timing::vip_cycle_period();
+        }
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing::new(ClockRate::default())
+    }
+}
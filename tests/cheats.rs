@@ -0,0 +1,142 @@
+use std::convert::TryFrom;
+use std::fs;
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::cheats::{Cheat, CheatFile, CheatTarget, RamSearch, SearchFilter};
+use rusty_chip_8::romdb;
+
+/*
+ * 200: 7001 V0 += 1
+ * 202: A300 I = 0x300
+ * 204: F055 store V0 at 0x300
+ * 206: 1200 loop
+ */
+const ROM: [u8; 8] = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00];
+
+fn run_loop(bus: &mut Bus) {
+    for _ in 0..4 {
+        bus.step();
+    }
+}
+
+#[test]
+fn test_ram_search() {
+    let mut bus = Bus::with_rom(&ROM);
+    let mut search = RamSearch::new(&bus.memory);
+    assert_eq!(search.len(), bus.memory.size());
+
+    run_loop(&mut bus);
+    assert_eq!(search.filter(&bus.memory, SearchFilter::Increased), [0x300]);
+    assert_eq!(search.value(0x300), Some(1));
+
+    // Nothing ran, so it is still the same
+    assert_eq!(search.filter(&bus.memory, SearchFilter::Equal), [0x300]);
+    assert!(search.filter(&bus.memory, SearchFilter::Changed).is_empty());
+}
+
+#[test]
+fn test_ram_search_filters() {
+    let mut bus = Bus::with_rom(&ROM);
+    let mut search = RamSearch::new(&bus.memory);
+    run_loop(&mut bus);
+    run_loop(&mut bus);
+    assert_eq!(search.filter(&bus.memory, SearchFilter::Value(2)), [0x300]);
+
+    bus.memory[0x300] = 0;
+    let mut decreased = search.clone();
+    assert_eq!(decreased.filter(&bus.memory, SearchFilter::Decreased), [0x300]);
+    assert!(search.filter(&bus.memory, SearchFilter::Increased).is_empty());
+}
+
+#[test]
+fn test_cheat_targets() {
+    assert_eq!(CheatTarget::try_from("0x2F0".to_string()), Ok(CheatTarget::Memory(0x2F0)));
+    assert_eq!(CheatTarget::try_from("752".to_string()), Ok(CheatTarget::Memory(0x2F0)));
+    assert_eq!(CheatTarget::try_from("vA".to_string()), Ok(CheatTarget::V(0xA)));
+    assert_eq!(CheatTarget::try_from("I".to_string()), Ok(CheatTarget::I));
+    assert!(CheatTarget::try_from("V10".to_string()).is_err());
+    assert!(CheatTarget::try_from("lives".to_string()).is_err());
+    assert_eq!(CheatTarget::Memory(0x2F0).to_string(), "0x2F0");
+}
+
+#[test]
+fn test_cheats_pin_every_frame() {
+    let mut bus = Bus::with_rom(&ROM);
+    bus.set_cheats(vec![
+        Cheat::new("Counter", CheatTarget::Memory(0x300), 0x42),
+        Cheat::new("V5", CheatTarget::V(5), 7),
+    ]);
+    // Applied as soon as they are set
+    assert_eq!(bus.memory[0x300], 0x42);
+    assert_eq!(bus.registers().v[5], 7);
+
+    run_loop(&mut bus);
+    assert_eq!(bus.memory[0x300], 1);
+    bus.tick_frame_timer();
+    assert_eq!(bus.memory[0x300], 0x42);
+
+    // Kept on reset
+    bus.reset();
+    bus.tick_frame_timer();
+    assert_eq!(bus.registers().v[5], 7);
+}
+
+#[test]
+fn test_cheats_toggle() {
+    let mut bus = Bus::with_rom(&ROM);
+    bus.add_cheat(Cheat::new("Counter", CheatTarget::Memory(0x300), 0x42));
+    bus.add_cheat(Cheat::new("Index", CheatTarget::I, 0x123));
+
+    bus.set_cheats_enabled(false);
+    run_loop(&mut bus);
+    bus.tick_frame_timer();
+    assert_eq!(bus.memory[0x300], 1);
+
+    bus.set_cheats_enabled(true);
+    bus.set_cheat_enabled(0, false);
+    run_loop(&mut bus);
+    bus.tick_frame_timer();
+    assert_eq!(bus.memory[0x300], 2);
+    assert_eq!(bus.registers().i, 0x123);
+    assert!(!bus.cheats()[0].enabled);
+}
+
+#[test]
+fn test_cheat_file() {
+    let hash = romdb::sha1_hex(&ROM);
+    let file = CheatFile::parse(&format!("\
+        [[roms.{0}.cheats]]\n\
+        name = \"Counter\"\n\
+        target = \"0x300\"\n\
+        value = 66\n\
+        \n\
+        [[roms.{0}.cheats]]\n\
+        target = \"V3\"\n\
+        value = 9\n\
+        enabled = false\n", hash)).unwrap();
+    let cheats = file.cheats(&hash);
+    assert_eq!(cheats.len(), 2);
+    assert_eq!(cheats[0], Cheat::new("Counter", CheatTarget::Memory(0x300), 66));
+    assert_eq!((cheats[1].target, cheats[1].enabled), (CheatTarget::V(3), false));
+    assert!(file.cheats("unknown").is_empty());
+
+    assert!(CheatFile::parse("[[roms.abc.cheats]]\ntarget = \"V3\"\n").is_err());
+    assert!(CheatFile::parse("[[roms.abc.cheats]]\ntarget = \"lives\"\nvalue = 1\n").is_err());
+}
+
+#[test]
+fn test_cheat_file_save_and_load() {
+    let path = std::env::temp_dir().join(format!("rusty_chip_8_cheats_{}.toml", std::process::id()));
+    assert_eq!(CheatFile::load(&path).unwrap(), CheatFile::default());
+
+    let mut file = CheatFile::default();
+    file.set_cheats("abc", vec![Cheat::new("Lives", CheatTarget::Memory(0x2F0), 3)]);
+    file.set_cheats("def", vec![Cheat::new("Index", CheatTarget::I, 0x123)]);
+    file.save(&path).unwrap();
+    let mut loaded = CheatFile::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, file);
+
+    loaded.set_cheats("def", Vec::new());
+    assert_eq!(loaded.roms.keys().collect::<Vec<_>>(), ["abc"]);
+}
@@ -0,0 +1,121 @@
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::coverage::SymbolFile;
+use rusty_chip_8::disasm::{disassemble, is_instruction, is_skip};
+
+/*
+ * 200: 6001 V0 = 1
+ * 202: 3001 skip if V0 == 1 (always)
+ * 204: 6002 never runs
+ * 206: 3002 skip if V0 == 2
+ * 208: 7001 V0 += 1
+ * 20A: 1206 jump to 0x206
+ * 20C: F090 sprite data
+ */
+const ROM: [u8; 14] = [0x60, 0x01, 0x30, 0x01, 0x60, 0x02, 0x30, 0x02, 0x70, 0x01, 0x12, 0x06, 0xF0, 0x90];
+
+// Runs 200, 202, 206, 208, 20A, 206, 20A, 206
+fn run() -> Bus {
+    let mut bus = Bus::with_rom(&ROM);
+    bus.enable_coverage();
+    for _ in 0..8 {
+        bus.step();
+    }
+    bus
+}
+
+#[test]
+fn test_disassemble() {
+    assert_eq!(disassemble(0x00E0), "CLS");
+    assert_eq!(disassemble(0x1206), "JP 0x206");
+    assert_eq!(disassemble(0x3A0F), "SE VA, 0x0F");
+    assert_eq!(disassemble(0x8AB4), "ADD VA, VB");
+    assert_eq!(disassemble(0xD125), "DRW V1, V2, 5");
+    assert_eq!(disassemble(0xF265), "LD V2, [I]");
+    assert_eq!(disassemble(0xF090), "DW 0xF090");
+    assert!(!is_instruction(0x8AB9));
+    assert!(is_skip(0xE19E) && is_skip(0x5120) && !is_skip(0x5121) && !is_skip(0x1206));
+}
+
+#[test]
+fn test_coverage_hits_and_branches() {
+    let bus = run();
+    let coverage = bus.coverage().unwrap();
+    assert_eq!(coverage.hits(0x206), 3);
+    assert_eq!(coverage.hits(0x204), 0);
+    assert_eq!(coverage.executed(), vec![0x200, 0x202, 0x206, 0x208, 0x20A]);
+
+    let first = coverage.branch(0x202).unwrap();
+    assert_eq!((first.taken, first.not_taken), (1, 0));
+    let second = coverage.branch(0x206).unwrap();
+    assert_eq!((second.taken, second.not_taken), (2, 1));
+    assert_eq!(coverage.branch(0x200), None);
+}
+
+#[test]
+fn test_coverage_is_kept_on_reset() {
+    let mut bus = run();
+    bus.reset();
+    bus.step();
+    assert_eq!(bus.coverage().unwrap().hits(0x200), 2);
+}
+
+#[test]
+fn test_listing() {
+    let bus = run();
+    let listing = bus.coverage().unwrap().listing(bus.rom(), 0x200, None);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines[0], "; 5 of 6 instructions ran");
+    assert!(lines[3].contains("0x202") && lines[3].contains("SE V0, 0x01") && lines[3].ends_with("taken 1, not taken 0"));
+    assert!(lines[4].trim_start().starts_with("#####") && lines[4].contains("LD V0, 0x02"));
+    assert!(lines[5].trim_start().starts_with('3') && lines[5].ends_with("taken 2, not taken 1"));
+    assert!(lines[8].trim_start().starts_with('-') && lines[8].contains("DW 0xF090"));
+}
+
+#[test]
+fn test_lcov_without_symbols() {
+    let bus = run();
+    let lcov = bus.coverage().unwrap().lcov(bus.rom(), 0x200, None, "game.lst");
+    let lines: Vec<&str> = lcov.lines().collect();
+    assert_eq!(lines[0..2], ["TN:", "SF:game.lst"]);
+    for expected in ["BRDA:4,0,0,1", "BRDA:4,0,1,0", "BRDA:6,0,0,2", "BRDA:6,0,1,1",
+                     "DA:3,1", "DA:5,0", "DA:6,3", "BRF:4", "BRH:3", "LF:6", "LH:5", "end_of_record"] {
+        assert!(lines.contains(&expected), "{} missing from\n{}", expected, lcov);
+    }
+    // The data isn't a line
+    assert!(!lcov.contains("DA:9,"));
+}
+
+#[test]
+fn test_lcov_with_symbols() {
+    let symbols = SymbolFile::parse("\
+        # From the Octo debug info\n\
+        source pong.8o\n\
+        label main 0x200\n\
+        line 0x200 1\n\
+        line 0x202 2\n\
+        line 0x204 2\n\
+        line 0x206 5\n").unwrap();
+    assert_eq!(symbols.label(0x200), Some("main"));
+    assert_eq!(symbols.line(0x206), Some(5));
+
+    let bus = run();
+    let coverage = bus.coverage().unwrap();
+    let lcov = coverage.lcov(bus.rom(), 0x200, Some(&symbols), "game.lst");
+    let lines: Vec<&str> = lcov.lines().collect();
+    for expected in ["SF:pong.8o", "DA:1,1", "DA:2,1", "DA:5,3", "BRDA:2,0,0,1", "BRDA:5,0,1,1", "LF:3", "LH:3"] {
+        assert!(lines.contains(&expected), "{} missing from\n{}", expected, lcov);
+    }
+
+    let listing = coverage.listing(bus.rom(), 0x200, Some(&symbols));
+    assert!(listing.contains("main:\n"));
+    assert!(listing.lines().any(|line| line.contains("0x206") && line.ends_with("; pong.8o:5")));
+}
+
+#[test]
+fn test_symbol_file_errors() {
+    assert!(SymbolFile::parse("label main").is_err());
+    assert!(SymbolFile::parse("line 0x200 twelve").is_err());
+    assert!(SymbolFile::parse("label main 0xZZZ").is_err());
+    assert!(SymbolFile::parse("function main 0x200").is_err());
+    assert_eq!(SymbolFile::parse("\n# nothing\n").unwrap(), SymbolFile::default());
+}
@@ -0,0 +1,40 @@
+use rusty_chip_8::bus::Bus;
+
+// Sets V0 and V1, runs the 8XYN with X = 0 and Y = 1, then gives back V0, V1 and VF
+fn alu(vx: u8, vy: u8, op: u8) -> (u8, u8, u8) {
+    let rom = [0x60, vx, 0x61, vy, 0x80, 0x10 | op];
+    let mut bus = Bus::with_rom(&rom);
+    for _ in 0..3 {
+        bus.step();
+    }
+    let v = bus.registers().v;
+    (v[0], v[1], v[0xF])
+}
+
+#[test]
+fn test_subtract_vy_from_vx() {
+    // VF is 1 when there is no borrow
+    assert_eq!(alu(9, 4, 0x5), (5, 4, 1));
+    assert_eq!(alu(4, 9, 0x5), (0xFB, 9, 0));
+    // Equal values don't borrow either
+    assert_eq!(alu(7, 7, 0x5), (0, 7, 1));
+}
+
+#[test]
+fn test_subtract_vx_from_vy() {
+    // The result goes to VX, VY is left alone
+    assert_eq!(alu(4, 9, 0x7), (5, 9, 1));
+    assert_eq!(alu(9, 4, 0x7), (0xFB, 4, 0));
+    assert_eq!(alu(7, 7, 0x7), (0, 7, 1));
+}
+
+#[test]
+fn test_flag_is_written_after_the_result() {
+    // With VF as VX the flag wins over the result
+    let rom = [0x6F, 9, 0x61, 4, 0x8F, 0x15];
+    let mut bus = Bus::with_rom(&rom);
+    for _ in 0..3 {
+        bus.step();
+    }
+    assert_eq!(bus.registers().v[0xF], 1);
+}
@@ -0,0 +1,141 @@
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::framebuffer::Framebuffer;
+
+// Lit pixels of a row, left to right
+fn lit(framebuffer: &Framebuffer, y: usize) -> Vec<usize> {
+    (0..framebuffer.width()).filter(|&x| framebuffer.get(x, y)).collect()
+}
+
+#[test]
+fn test_sprite_rows_clip_at_the_right_edge() {
+    let mut framebuffer = Framebuffer::new(64, 32);
+    assert!(!framebuffer.draw_sprite_row(60, 0, 0xFF));
+    assert_eq!(lit(&framebuffer, 0), [60, 61, 62, 63]);
+    // Nothing wraps to the left side or leaks into the next row
+    assert_eq!(framebuffer.row(0).count_ones(), 4);
+    assert!(lit(&framebuffer, 1).is_empty());
+
+    // Only the visible part collides
+    assert!(framebuffer.draw_sprite_row(62, 0, 0x80));
+    assert_eq!(lit(&framebuffer, 0), [60, 61, 63]);
+    assert!(!framebuffer.draw_sprite_row(63, 0, 0x7F));
+    assert_eq!(lit(&framebuffer, 0), [60, 61, 63]);
+
+    // Same at the edge of the widest rows
+    let mut framebuffer = Framebuffer::new(128, 64);
+    assert!(!framebuffer.draw_sprite_row(124, 63, 0xFF));
+    assert_eq!(lit(&framebuffer, 63), [124, 125, 126, 127]);
+    assert!(framebuffer.draw_sprite_row(127, 63, 0xFF));
+    assert_eq!(lit(&framebuffer, 63), [124, 125, 126]);
+}
+
+#[test]
+fn test_sprites_stop_at_the_bottom_edge() {
+    // V0 = 60, V1 = 30, I = glyph of 8 (all 5 rows lit), draw it twice
+    let rom = [0x60, 60, 0x61, 30, 0x62, 0x08, 0xF2, 0x29, 0xD0, 0x15, 0xD0, 0x15];
+    let mut bus = Bus::with_rom(&rom);
+    for _ in 0..5 {
+        bus.step();
+    }
+    let vram = bus.get_vram();
+    assert_eq!(lit(vram, 30), [60, 61, 62, 63]);
+    assert_eq!(lit(vram, 31), [60, 63]);
+    // The rest of the glyph doesn't wrap to the top
+    assert!(lit(vram, 0).is_empty() && lit(vram, 1).is_empty());
+    assert_eq!(bus.registers().v[0xF], 0);
+
+    // Drawing it again only collides with what was drawn
+    bus.step();
+    assert_eq!(bus.registers().v[0xF], 1);
+    assert!((0..32).all(|y| lit(bus.get_vram(), y).is_empty()));
+}
+
+#[test]
+fn test_dirty_rows_are_merged_into_ranges() {
+    let mut framebuffer = Framebuffer::new(64, 32);
+    // Everything starts dirty
+    assert!(framebuffer.was_updated());
+    assert_eq!(framebuffer.take_dirty_rows(), vec![0..32]);
+    assert!(!framebuffer.was_updated());
+    assert!(framebuffer.take_dirty_rows().is_empty());
+
+    for y in [1, 2, 3, 7, 31] {
+        framebuffer.draw_sprite_row(0, y, 0x80);
+    }
+    framebuffer.set(5, 9, true);
+    assert!(framebuffer.was_updated());
+    assert_eq!(framebuffer.take_dirty_rows(), [1..4, 7..8, 9..10, 31..32]);
+
+    // Setting a pixel to what it already is, or drawing nothing, changes nothing
+    framebuffer.set(5, 9, true);
+    framebuffer.set(6, 10, false);
+    framebuffer.draw_sprite_row(0, 12, 0x00);
+    framebuffer.draw_sprite_row(64, 13, 0xFF);
+    assert!(!framebuffer.was_updated());
+    assert!(framebuffer.take_dirty_rows().is_empty());
+
+    // Clearing only touches the rows that had something
+    framebuffer.draw_sprite_row(0, 8, 0x80);
+    framebuffer.clear();
+    assert_eq!(framebuffer.take_dirty_rows(), [1..4, 7..10, 31..32]);
+}
+
+#[test]
+fn test_bitmap_round_trip() {
+    // 100 pixels take 13 bytes a row, the last one half used
+    let mut framebuffer = Framebuffer::new(100, 3);
+    assert_eq!(framebuffer.bitmap_stride(), 13);
+    framebuffer.set(0, 0, true);
+    framebuffer.set(9, 1, true);
+    framebuffer.set(99, 2, true);
+
+    let bitmap = framebuffer.to_bitmap();
+    assert_eq!(bitmap.len(), 13 * 3);
+    // Leftmost pixel on the most significant bit
+    assert_eq!(bitmap[0], 0x80);
+    assert_eq!(bitmap[13 + 1], 0x40);
+    assert_eq!(bitmap[26 + 12], 0x10);
+
+    let mut copy = Framebuffer::new(100, 3);
+    copy.take_dirty_rows();
+    copy.load_bitmap(&bitmap);
+    assert_eq!(copy.to_bitmap(), bitmap);
+    assert!((0..3).all(|y| copy.row(y) == framebuffer.row(y)));
+    assert_eq!(copy.take_dirty_rows(), vec![0..3]);
+
+    // Bits past the width are dropped, missing rows come back blank
+    let mut short = Framebuffer::new(100, 3);
+    short.load_bitmap(&[0xFF; 13]);
+    assert_eq!(short.row(0).count_ones(), 100);
+    assert_eq!(short.row(1), 0);
+    assert_eq!(short.to_bitmap()[12], 0xF0);
+}
+
+#[test]
+fn test_rgba_layout() {
+    const ON: [u8; 4] = [1, 2, 3, 4];
+    const OFF: [u8; 4] = [9, 9, 9, 255];
+    let mut framebuffer = Framebuffer::new(10, 4);
+    framebuffer.set(0, 0, true);
+    framebuffer.set(9, 2, true);
+    framebuffer.set(3, 3, true);
+
+    // Row after row, 4 bytes per pixel, x + y * width
+    let rgba = framebuffer.to_rgba(ON, OFF);
+    assert_eq!(rgba.len(), 10 * 4 * 4);
+    let pixel = |x: usize, y: usize| &rgba[(y * 10 + x) * 4..(y * 10 + x + 1) * 4];
+    assert_eq!(pixel(0, 0), ON);
+    assert_eq!(pixel(1, 0), OFF);
+    assert_eq!(pixel(9, 2), ON);
+    assert_eq!(pixel(0, 3), OFF);
+    assert_eq!(pixel(3, 3), ON);
+    assert_eq!(rgba.chunks(4).filter(|&pixel| pixel == ON).count(), 3);
+
+    // A band starts at its first row
+    let band = framebuffer.rows_to_rgba(2..4, ON, OFF);
+    assert_eq!(band, rgba[2 * 10 * 4..]);
+
+    let mut out = vec![0; 10 * 4];
+    framebuffer.write_rgba(2..3, ON, OFF, &mut out);
+    assert_eq!(out, rgba[2 * 10 * 4..3 * 10 * 4]);
+}
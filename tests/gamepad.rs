@@ -0,0 +1,41 @@
+use rusty_chip_8::gamepad::*;
+use rusty_chip_8::keyboard::State;
+
+#[test]
+fn dpad_preset_maps_directions_and_buttons() {
+    let mut mapper = GamepadMapper::new(&GamepadProfile::default());
+
+    assert_eq!(mapper.process(GamepadEvent::AxisMoved(Axis::PovY, -100.0)), vec![(0x5, State::PRESSED)]);
+    assert_eq!(mapper.process(GamepadEvent::AxisMoved(Axis::X, 100.0)), vec![(0x9, State::PRESSED)]);
+    assert_eq!(mapper.process(GamepadEvent::ButtonPressed(0)), vec![(0x6, State::PRESSED)]);
+    assert_eq!(mapper.process(GamepadEvent::ButtonReleased(0)), vec![(0x6, State::RELEASED)]);
+    assert!(mapper.process(GamepadEvent::ButtonPressed(7)).is_empty());
+}
+
+#[test]
+fn axis_respects_deadzone_and_switches_sides() {
+    let mut profile = GamepadProfile::preset(GamepadPreset::Paddle);
+    profile.deadzone = 30.0;
+    let mut mapper = GamepadMapper::new(&profile);
+
+    assert!(mapper.process(GamepadEvent::AxisMoved(Axis::Y, -20.0)).is_empty());
+    assert_eq!(mapper.process(GamepadEvent::AxisMoved(Axis::Y, -40.0)), vec![(0x1, State::PRESSED)]);
+    // Still on the same side, nothing new
+    assert!(mapper.process(GamepadEvent::AxisMoved(Axis::Y, -90.0)).is_empty());
+    assert_eq!(mapper.process(GamepadEvent::AxisMoved(Axis::Y, 80.0)),
+               vec![(0x1, State::RELEASED), (0x4, State::PRESSED)]);
+    assert_eq!(mapper.process(GamepadEvent::AxisMoved(Axis::Y, 0.0)), vec![(0x4, State::RELEASED)]);
+    // Paddle has nothing on the horizontal axis
+    assert!(mapper.process(GamepadEvent::AxisMoved(Axis::X, 100.0)).is_empty());
+}
+
+#[test]
+fn profile_bindings_replace_preset() {
+    let mut profile = GamepadProfile::default();
+    profile.buttons.push(ButtonBinding { button: 0, key: 0xA });
+    profile.axes.push(AxisBinding { axis: Axis::PovY, negative: Some(0x8), positive: Some(0x5) });
+    let mut mapper = GamepadMapper::new(&profile);
+
+    assert_eq!(mapper.process(GamepadEvent::ButtonPressed(0)), vec![(0xA, State::PRESSED)]);
+    assert_eq!(mapper.process(GamepadEvent::AxisMoved(Axis::PovY, -100.0)), vec![(0x8, State::PRESSED)]);
+}
@@ -0,0 +1,169 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::gdb::{target_xml, GdbStub};
+
+// Minimal client side of the protocol, replies are read up to the checksum
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if reply.is_empty() => continue,
+                b'#' => break,
+                other => reply.push(other),
+            }
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(reply[1..].to_vec()).unwrap()
+    }
+}
+
+#[test]
+fn debugger_steps_and_stops_at_breakpoints() {
+    // 0x200: V0 = 0x12, 0x202: I = 0x300, 0x204: V1 = 0x34, 0x206: jump to itself
+    let rom = [0x60, 0x12, 0xA3, 0x00, 0x61, 0x34, 0x12, 0x06];
+    let mut bus = Bus::with_rom(&rom);
+    let mut stub = GdbStub::bind("127.0.0.1:0").unwrap();
+    let port = stub.local_port().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap() };
+        assert!(client.request("qSupported:swbreak+").contains("qXfer:features:read+"));
+        assert!(client.request("qXfer:features:read:target.xml:0,fff").contains("name=\"pc\""));
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("p11"), "0002");
+
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p0"), "12");
+        assert_eq!(client.request("Z0,206,2"), "OK");
+        // No reply until it hits the breakpoint
+        write!(client.stream, "$c#63").unwrap();
+        assert_eq!(client.reply(), "S05");
+        assert_eq!(client.request("p11"), "0602");
+
+        let registers = client.request("g");
+        assert_eq!(&registers[..4], "1234");
+        assert_eq!(&registers[32..], "0003060200");
+        assert_eq!(client.request("P1=56"), "OK");
+        assert_eq!(client.request("M300,2:abcd"), "OK");
+        assert_eq!(client.request("m2fe,4"), "0000abcd");
+        assert_eq!(client.request("D"), "OK");
+    });
+
+    stub.wait_for_debugger().unwrap();
+    while !client.is_finished() {
+        stub.poll(&mut bus).unwrap();
+        if stub.is_running() {
+            bus.run_for(Duration::from_millis(16));
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    client.join().unwrap();
+
+    assert_eq!(bus.registers().v[1], 0x56);
+    assert_eq!(bus.memory[0x301], 0xCD);
+}
+
+#[test]
+fn debugger_addresses_past_the_end_wrap() {
+    let mut bus = Bus::with_rom(&[0x12, 0x00]);
+    let mut stub = GdbStub::bind("127.0.0.1:0").unwrap();
+    let port = stub.local_port().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap() };
+        // Writes and reads go around the end of the memory
+        assert_eq!(client.request("Mffe,4:60070102"), "OK");
+        assert_eq!(client.request("mfff,2"), "0701");
+        assert_eq!(client.request("mffffffffffffffff,2"), "0701");
+        // Never more than the whole memory
+        assert_eq!(client.request("m0,ffffffff").len(), 0x1000 * 2);
+
+        // The PC is kept inside the memory, and steps past the end wrap
+        assert_eq!(client.request("P11=feff"), "OK");
+        assert_eq!(client.request("p11"), "fe0f");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p0"), "07");
+        assert_eq!(client.request("p11"), "0000");
+        assert_eq!(client.request("sffff"), "S05");
+        assert_eq!(client.request("D"), "OK");
+    });
+
+    stub.wait_for_debugger().unwrap();
+    while !client.is_finished() {
+        stub.poll(&mut bus).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    client.join().unwrap();
+}
+
+#[test]
+fn target_description_numbers_the_registers() {
+    let mut bus = Bus::with_rom(&[0x12, 0x00]);
+    let mut stub = GdbStub::bind("127.0.0.1:0").unwrap();
+    let port = stub.local_port().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap() };
+        // Read in small chunks, m means there is more, l that it was the last one
+        let mut xml = String::new();
+        loop {
+            let reply = client.request(&format!("qXfer:features:read:target.xml:{:x},40", xml.len()));
+            xml.push_str(&reply[1..]);
+            if reply.starts_with('l') {
+                break;
+            }
+            assert!(reply.starts_with('m'), "{}", reply);
+        }
+        assert_eq!(client.request("D"), "OK");
+        xml
+    });
+
+    stub.wait_for_debugger().unwrap();
+    while !client.is_finished() {
+        stub.poll(&mut bus).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    let xml = client.join().unwrap();
+    assert_eq!(xml, target_xml());
+
+    // No architecture, every register in order with its number and size
+    assert!(!xml.contains("<architecture>"));
+    let registers: Vec<(String, String, String)> = xml.lines()
+        .filter(|line| line.trim_start().starts_with("<reg "))
+        .map(|line| {
+            let attribute = |name: &str| {
+                let start = line.find(&format!("{}=\"", name)).unwrap() + name.len() + 2;
+                line[start..].split('"').next().unwrap().to_string()
+            };
+            (attribute("name"), attribute("bitsize"), attribute("regnum"))
+        })
+        .collect();
+    assert_eq!(registers.len(), 19);
+    for (reg, (name, bitsize, regnum)) in registers.iter().take(16).enumerate() {
+        assert_eq!((name.as_str(), bitsize.as_str()), (format!("v{:x}", reg).as_str(), "8"));
+        assert_eq!(regnum, &reg.to_string());
+    }
+    let others: Vec<(&str, &str, &str)> = registers[16..].iter()
+        .map(|(name, bitsize, regnum)| (name.as_str(), bitsize.as_str(), regnum.as_str()))
+        .collect();
+    assert_eq!(others, [("i", "16", "16"), ("pc", "16", "17"), ("sp", "8", "18")]);
+}
@@ -0,0 +1,82 @@
+use rusty_chip_8::gym::{ActionSet, Env, EnvConfig, ScoreEncoding, ScoreReader, VecEnv};
+
+/*
+ * Scores a point every time key 5 goes down:
+ * 200: 6105 V1 = 5
+ * 202: E19E skip if key V1 is down
+ * 204: 1202 back to waiting
+ * 206: A300 I = 0x300
+ * 208: F065 V0 = score
+ * 20A: 7001 V0 += 1
+ * 20C: F055 score = V0
+ * 20E: E1A1 skip if key V1 is up
+ * 210: 120E wait for the release
+ * 212: 1202 again
+ */
+const SCORE_ROM: [u8; 20] = [
+    0x61, 0x05, 0xE1, 0x9E, 0x12, 0x02, 0xA3, 0x00, 0xF0, 0x65,
+    0x70, 0x01, 0xF0, 0x55, 0xE1, 0xA1, 0x12, 0x0E, 0x12, 0x02,
+];
+
+fn score_config() -> EnvConfig {
+    EnvConfig::default()
+        .with_frame_skip(2)
+        .with_actions(ActionSet::from_keys(&[0x5]))
+        .with_score(ScoreReader::new(vec![0x300], ScoreEncoding::Binary))
+        .with_game_over(0x300, 3)
+}
+
+#[test]
+fn test_env_rewards_and_done() {
+    let mut env = Env::new(&SCORE_ROM, score_config()).unwrap();
+    assert_eq!(env.actions().len(), 2);
+
+    let observation = env.reset();
+    assert_eq!(observation.len(), 64 * 32);
+
+    // Holding the key only scores once
+    let steps: Vec<(f64, bool)> = [1, 1, 0, 1, 0, 1].iter()
+        .map(|action| {
+            let (_, reward, done) = env.step(*action);
+            (reward, done)
+        })
+        .collect();
+    assert_eq!(steps, vec![(1.0, false), (0.0, false), (0.0, false), (1.0, false), (0.0, false), (1.0, true)]);
+    // The last step stopped as soon as the game was over
+    assert_eq!(env.frames(), 11);
+
+    env.reset();
+    assert!(!env.is_done());
+    assert_eq!(env.bus().memory[0x300], 0);
+}
+
+#[test]
+fn test_env_max_frames_and_bcd() {
+    let config = score_config()
+        .with_max_frames(4)
+        .with_score(ScoreReader::new(vec![0x300, 0x301], ScoreEncoding::Bcd));
+    let mut env = Env::new(&SCORE_ROM, config).unwrap();
+    env.bus().memory[0x301] = 7;
+    let (_, reward, done) = env.step(0);
+    assert_eq!((reward, done), (7.0, false));
+
+    env.bus().memory[0x301] = 9;
+    let (_, reward, done) = env.step(1);
+    // 07 -> 19 is 12 points
+    assert_eq!(reward, 12.0);
+    assert!(done);
+}
+
+#[test]
+fn test_vec_env_matches_single() {
+    let actions = [[1, 0, 1, 1], [0, 1, 0, 1], [1, 1, 1, 0]];
+    let mut single: Vec<Env> = (0..4).map(|_| Env::new(&SCORE_ROM, score_config()).unwrap()).collect();
+    let mut vec_env = VecEnv::new(&SCORE_ROM, score_config(), 4).unwrap().with_threads(3);
+    assert_eq!(vec_env.reset().len(), 4);
+
+    for step in actions.iter() {
+        let expected: Vec<_> = single.iter_mut().zip(step).map(|(env, action)| env.step(*action)).collect();
+        assert_eq!(vec_env.step(step), expected);
+    }
+    assert_eq!(vec_env.env(0).bus().memory[0x300], 2);
+}
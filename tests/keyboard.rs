@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::gamepad::{Axis, GamepadEvent, GamepadMapper, GamepadProfile};
+use rusty_chip_8::keyboard::{HeldKeys, InputEvent, KeySource, KeyWait, Keyboard, State};
+use rusty_chip_8::machine::MachineConfig;
+use rusty_chip_8::quirks::Quirks;
+
+// 200: F30A wait for a key into V3, 202: 1202 loop
+const WAIT_KEY: [u8; 4] = [0xF3, 0x0A, 0x12, 0x02];
+
+fn wait_key_bus(key_wait_release: bool) -> Bus {
+    let quirks = Quirks { key_wait_release, ..Quirks::default() };
+    Bus::with_config(&WAIT_KEY, MachineConfig::default().with_quirks(quirks)).unwrap()
+}
+
+#[test]
+fn test_key_wait_on_press() {
+    let mut keyboard = Keyboard::new();
+    keyboard.begin_key_wait(false);
+    assert_eq!(keyboard.key_wait(), KeyWait::WaitingPress { ignored: 0, on_release: false });
+    assert_eq!(keyboard.take_waited_key(), None);
+
+    keyboard.process_key(0x5, State::PRESSED);
+    assert_eq!(keyboard.key_wait(), KeyWait::Done { key: 0x5 });
+    assert_eq!(keyboard.take_waited_key(), Some(0x5));
+    assert_eq!(keyboard.key_wait(), KeyWait::Idle);
+}
+
+#[test]
+fn test_key_wait_ignores_held_keys() {
+    let mut keyboard = Keyboard::new();
+    keyboard.process_key(0x3, State::PRESSED);
+    keyboard.begin_key_wait(false);
+
+    // Key repeat of the held key
+    keyboard.process_key(0x3, State::PRESSED);
+    assert_eq!(keyboard.take_waited_key(), None);
+
+    keyboard.process_key(0x3, State::RELEASED);
+    keyboard.process_key(0x3, State::PRESSED);
+    assert_eq!(keyboard.take_waited_key(), Some(0x3));
+}
+
+#[test]
+fn test_key_wait_on_release() {
+    let mut keyboard = Keyboard::new();
+    keyboard.begin_key_wait(true);
+    keyboard.process_key(0x7, State::PRESSED);
+    assert_eq!(keyboard.key_wait(), KeyWait::WaitingRelease { key: 0x7 });
+
+    // Other keys don't matter once one was picked
+    keyboard.process_key(0x2, State::PRESSED);
+    keyboard.process_key(0x2, State::RELEASED);
+    assert_eq!(keyboard.take_waited_key(), None);
+
+    keyboard.process_key(0x7, State::RELEASED);
+    assert_eq!(keyboard.take_waited_key(), Some(0x7));
+}
+
+#[test]
+fn test_fx0a_returns_on_press() {
+    let mut bus = wait_key_bus(false);
+    bus.step();
+    bus.step();
+    assert!(bus.state.lock_until_pressed);
+    assert_eq!(bus.registers().pc, 0x202);
+
+    bus.queue_key(0xA, State::PRESSED, bus.time());
+    bus.step();
+    assert!(!bus.state.lock_until_pressed);
+    assert_eq!(bus.registers().v[3], 0xA);
+}
+
+#[test]
+fn test_fx0a_waits_for_release_on_vip() {
+    let mut bus = wait_key_bus(true);
+    bus.step();
+    bus.queue_key(0xA, State::PRESSED, bus.time());
+    bus.step();
+    bus.step();
+    assert!(bus.state.lock_until_pressed);
+
+    bus.queue_key(0xA, State::RELEASED, bus.time());
+    bus.step();
+    assert!(!bus.state.lock_until_pressed);
+    assert_eq!(bus.registers().v[3], 0xA);
+}
+
+fn event(key: u8, state: State, millis: u64) -> InputEvent {
+    InputEvent { key, state, time: Duration::from_millis(millis) }
+}
+
+#[test]
+fn test_events_apply_in_time_order() {
+    let mut keyboard = Keyboard::new();
+    keyboard.queue_event(event(0x2, State::PRESSED, 20));
+    keyboard.queue_event(event(0x1, State::PRESSED, 10));
+    // Same time as the press, stays after it
+    keyboard.queue_event(event(0x1, State::RELEASED, 10));
+    assert_eq!(keyboard.pending_events(), 3);
+
+    keyboard.apply_events_until(Duration::from_millis(5));
+    assert_eq!(keyboard.pending_events(), 3);
+
+    keyboard.apply_events_until(Duration::from_millis(10));
+    assert_eq!(keyboard.pending_events(), 1);
+    assert_eq!(keyboard.get_last_pressed_key(), 0x1);
+
+    keyboard.apply_events_until(Duration::from_millis(20));
+    assert_eq!(keyboard.pending_events(), 0);
+    assert_eq!(keyboard.get_last_pressed_key(), 0x2);
+    assert!(keyboard.is_key_pressed(0x2));
+}
+
+#[test]
+fn test_taps_are_seen_once() {
+    let mut keyboard = Keyboard::new();
+    keyboard.process_key(0x4, State::PRESSED);
+    keyboard.process_key(0x4, State::RELEASED);
+    assert!(keyboard.is_key_pressed(0x4));
+    assert!(!keyboard.is_key_pressed(0x4));
+
+    // Held keys read as pressed every time
+    keyboard.process_key(0x6, State::PRESSED);
+    assert!(keyboard.is_key_pressed(0x6));
+    assert!(keyboard.is_key_pressed(0x6));
+}
+
+#[test]
+fn test_unseen_taps_expire() {
+    let mut keyboard = Keyboard::new();
+    keyboard.process_key(0x4, State::PRESSED);
+    keyboard.process_key(0x4, State::RELEASED);
+
+    // Still there through the end of the frame it happened in
+    keyboard.expire_taps();
+    keyboard.process_key(0x8, State::PRESSED);
+    keyboard.process_key(0x8, State::RELEASED);
+    keyboard.expire_taps();
+    assert!(!keyboard.is_key_pressed(0x4));
+    assert!(keyboard.is_key_pressed(0x8));
+
+    // Nobody polled it for a whole frame
+    keyboard.process_key(0x9, State::PRESSED);
+    keyboard.process_key(0x9, State::RELEASED);
+    keyboard.expire_taps();
+    keyboard.expire_taps();
+    assert!(!keyboard.is_key_pressed(0x9));
+}
+
+#[test]
+fn test_taps_expire_on_the_timers() {
+    let mut bus = wait_key_bus(false);
+    bus.keyboard.process_key(0x4, State::PRESSED);
+    bus.keyboard.process_key(0x4, State::RELEASED);
+    bus.tick_frame_timer();
+    bus.tick_frame_timer();
+    assert!(!bus.keyboard.is_key_pressed(0x4));
+}
+
+#[test]
+fn test_keys_past_f_are_masked() {
+    let mut keyboard = Keyboard::new();
+    keyboard.process_key(0x13, State::PRESSED);
+    assert!(keyboard.is_key_pressed(0x3));
+    assert!(keyboard.is_key_pressed(0x23));
+
+    // 200: 6520 V5 = 0x20, 202: E59E skip if key V5, 204: 1204, 206: 1206
+    let rom = [0x65, 0x20, 0xE5, 0x9E, 0x12, 0x04, 0x12, 0x06];
+    let mut bus = Bus::with_rom(&rom);
+    bus.keyboard.process_key(0x0, State::PRESSED);
+    bus.step();
+    bus.step();
+    assert_eq!(bus.registers().pc, 0x206);
+}
+
+#[test]
+fn test_key_held_by_many_sources() {
+    let mut held = HeldKeys::new();
+    let q = || KeySource::Key("Q".to_string());
+    let w = || KeySource::Key("W".to_string());
+
+    // Two host keys on 5, only the first press and the last release go through
+    assert_eq!(held.update(q(), 0x5, State::PRESSED), Some(State::PRESSED));
+    assert_eq!(held.update(w(), 0x5, State::PRESSED), None);
+    // Key repeat is not a new press
+    assert_eq!(held.update(q(), 0x5, State::PRESSED), None);
+    assert_eq!(held.update(q(), 0x5, State::RELEASED), None);
+    assert!(held.is_held(0x5));
+    // Releasing something that wasn't holding it changes nothing
+    assert_eq!(held.update(q(), 0x5, State::RELEASED), None);
+    assert_eq!(held.update(w(), 0x5, State::RELEASED), Some(State::RELEASED));
+    assert!(!held.is_held(0x5));
+
+    // Keyboard and gamepad button on the same key
+    assert_eq!(held.update(q(), 0x6, State::PRESSED), Some(State::PRESSED));
+    assert_eq!(held.update(KeySource::GamepadButton(0), 0x6, State::PRESSED), None);
+    assert_eq!(held.update(q(), 0x6, State::RELEASED), None);
+    assert_eq!(held.update(KeySource::GamepadButton(0), 0x6, State::RELEASED), Some(State::RELEASED));
+
+    held.update(q(), 0x1, State::PRESSED);
+    held.clear();
+    assert!(!held.is_held(0x1));
+}
+
+#[test]
+fn test_stick_and_hat_on_the_same_key() {
+    // The dpad preset puts up on 5 for both the stick and the hat
+    let mut mapper = GamepadMapper::new(&GamepadProfile::default());
+    let mut held = HeldKeys::new();
+    let mut keyboard = Keyboard::new();
+    let mut send = |event: GamepadEvent, keyboard: &mut Keyboard| {
+        let axis = match event {
+            GamepadEvent::AxisMoved(axis, _) => axis,
+            _ => unreachable!(),
+        };
+        for (key, state) in mapper.process(event) {
+            if let Some(state) = held.update(KeySource::GamepadAxis(axis), key, state) {
+                keyboard.process_key(key as usize, state);
+            }
+        }
+    };
+
+    send(GamepadEvent::AxisMoved(Axis::Y, -100.0), &mut keyboard);
+    send(GamepadEvent::AxisMoved(Axis::PovY, -100.0), &mut keyboard);
+    send(GamepadEvent::AxisMoved(Axis::Y, 0.0), &mut keyboard);
+    // The hat still holds it
+    assert!(keyboard.is_key_pressed(0x5));
+    send(GamepadEvent::AxisMoved(Axis::PovY, 0.0), &mut keyboard);
+    assert!(!keyboard.is_key_pressed(0x5));
+}
@@ -0,0 +1,94 @@
+use std::fs;
+
+use rusty_chip_8::keymap::{KeyMap, KeymapConfig, Layout};
+
+#[test]
+fn test_presets() {
+    let qwerty = KeyMap::preset(Layout::Qwerty);
+    assert_eq!(qwerty.lookup("Num1"), Some(0x1));
+    assert_eq!(qwerty.lookup("Q"), Some(0x4));
+    assert_eq!(qwerty.lookup("X"), Some(0x0));
+    assert_eq!(qwerty.lookup("V"), Some(0xF));
+    assert_eq!(qwerty.lookup("Up"), None);
+
+    let azerty = KeyMap::preset(Layout::Azerty);
+    assert_eq!(azerty.lookup("A"), Some(0x4));
+    assert_eq!(azerty.lookup("W"), Some(0xA));
+
+    let dvorak = KeyMap::preset(Layout::Dvorak);
+    assert_eq!(dvorak.lookup("Quote"), Some(0x4));
+    assert_eq!(dvorak.lookup("SemiColon"), Some(0xA));
+}
+
+#[test]
+fn test_bind_moves_host_keys() {
+    let mut keymap = KeyMap::preset(Layout::Qwerty);
+    keymap.bind("Up", 0x5);
+    keymap.bind("Up", 0x2);
+    assert_eq!(keymap.lookup("Up"), Some(0x2));
+    assert_eq!(keymap.host_keys(0x5), ["W"]);
+
+    keymap.clear_key(0x2);
+    assert_eq!(keymap.lookup("Up"), None);
+    assert_eq!(keymap.lookup("Num2"), None);
+}
+
+#[test]
+fn test_global_and_rom_bindings() {
+    let config = KeymapConfig::parse(r#"
+        layout = "azerty"
+
+        [bindings]
+        5 = ["Z", "Up"]
+
+        [roms."pong.ch8"]
+        layout = "qwerty"
+
+        [roms."pong.ch8".bindings]
+        1 = ["W", "Up"]
+        4 = ["S", "Down"]
+    "#).unwrap();
+
+    let global = config.keymap_for("maze.ch8").unwrap();
+    assert_eq!(global.lookup("A"), Some(0x4));
+    // Listed keys replace what the layout had
+    assert_eq!(global.lookup("Up"), Some(0x5));
+    assert_eq!(global.host_keys(0x5), ["Up", "Z"]);
+
+    let pong = config.keymap_for("pong.ch8").unwrap();
+    // The ROM layout wins, the global bindings still apply on top
+    assert_eq!(pong.lookup("A"), Some(0x7));
+    assert_eq!(pong.lookup("Z"), Some(0x5));
+    // Q was on 4 on the qwerty layout, the ROM bindings replaced it
+    assert_eq!(pong.lookup("Q"), None);
+    assert_eq!(pong.host_keys(0x1), ["Up", "W"]);
+    assert_eq!(pong.host_keys(0x4), ["Down", "S"]);
+    assert_eq!(pong.lookup("Num1"), None);
+    assert_eq!(pong.lookup("Down"), Some(0x4));
+}
+
+#[test]
+fn test_invalid_keymaps() {
+    assert!(KeymapConfig::parse("layout = \"colemak\"").is_err());
+    assert!(KeymapConfig::parse("[bindings]\nG = [\"Q\"]").is_err());
+    // Bad ROM overrides are caught on load, not once the ROM starts
+    assert!(KeymapConfig::parse("[roms.\"pong.ch8\".bindings]\n10 = [\"Q\"]").is_err());
+    assert_eq!(KeymapConfig::parse("").unwrap(), KeymapConfig::default());
+}
+
+#[test]
+fn test_rom_keymap_is_saved() {
+    let path = std::env::temp_dir().join(format!("rusty_chip_8_keymap_{}.toml", std::process::id()));
+    assert_eq!(KeymapConfig::load(&path).unwrap(), KeymapConfig::default());
+
+    let mut keymap = KeyMap::preset(Layout::Qwerty);
+    keymap.bind("Up", 0x1);
+    let mut config = KeymapConfig::default();
+    config.set_rom_keymap("pong.ch8", &keymap);
+    config.save(&path).unwrap();
+
+    let loaded = KeymapConfig::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.keymap_for("pong.ch8").unwrap(), keymap);
+    assert_eq!(loaded.keymap_for("maze.ch8").unwrap(), KeyMap::preset(Layout::Qwerty));
+}
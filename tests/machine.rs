@@ -0,0 +1,275 @@
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::fault::Fault;
+use rusty_chip_8::font::FontSet;
+use rusty_chip_8::debug::DebugEvent;
+use rusty_chip_8::machine::*;
+use rusty_chip_8::memory::BoundsPolicy;
+use rusty_chip_8::savestate::SaveState;
+use rusty_chip_8::timing::ClockRate;
+
+#[test]
+fn config_round_trips_through_toml() {
+    let config = MachineConfig::new(Variant::Eti660)
+        .with_stack_depth(24)
+        .with_seed(1234)
+        .with_rate(ClockRate::Hz(700));
+
+    let text = config.to_toml().unwrap();
+    assert_eq!(MachineConfig::from_toml(&text).unwrap(), config);
+}
+
+#[test]
+fn missing_values_take_the_variant_defaults() {
+    let config = MachineConfig::from_toml("variant = \"vip\"\nseed = 7\n[layout]\nfont_base = 0x50\n").unwrap();
+
+    assert_eq!(config.stack_depth, 12);
+    assert_eq!(config.seed, Some(7));
+    assert_eq!(config.layout.font_base, 0x50);
+    assert_eq!(config.layout.font, FontSet::Vip);
+    assert_eq!(config.layout.load_address, 0x200);
+    assert!(MachineConfig::from_toml("memory_size = 16").is_err());
+}
+
+#[test]
+fn bus_is_built_from_the_config() {
+    // V0 = random, I = 0x300, store V0 at I
+    let rom = [0xC0, 0xFF, 0xA3, 0x00, 0xF0, 0x55];
+    let config = MachineConfig::new(Variant::Eti660).with_seed(42);
+    let mut buses = [Bus::with_config(&rom, config.clone()).unwrap(), Bus::with_config(&rom, config).unwrap()];
+
+    for bus in buses.iter_mut() {
+        assert_eq!(bus.memory.size(), 0x1000);
+        assert_eq!(bus.memory[0x600], 0xC0);
+        for _ in 0..3 {
+            bus.tick_frame_cpu();
+        }
+    }
+    // Same seed, same random number
+    assert_eq!(buses[0].memory[0x300], buses[1].memory[0x300]);
+}
+
+#[test]
+fn stack_overflow_stops_the_machine() {
+    // 0x200: call 0x202, 0x202: call 0x204, 0x204: call 0x204 forever
+    let rom = [0x22, 0x02, 0x22, 0x04, 0x22, 0x04];
+    let mut bus = Bus::with_config(&rom, MachineConfig::new(Variant::Vip)).unwrap();
+
+    for _ in 0..20 {
+        bus.tick_frame_cpu();
+    }
+    assert_eq!(bus.fault(), Some(Fault::StackOverflow { pc: 0x204, depth: 12 }));
+    let call_stack = bus.call_stack();
+    assert_eq!(call_stack.len(), 12);
+    assert_eq!(call_stack[0].call_site, 0x200);
+    assert_eq!(call_stack[0].return_address, 0x202);
+    assert_eq!(call_stack[11].call_site, 0x204);
+}
+
+#[test]
+fn return_without_call_is_a_fault() {
+    let mut bus = Bus::with_rom(&[0x00, 0xEE]);
+    bus.tick_frame_cpu();
+    assert_eq!(bus.fault(), Some(Fault::StackUnderflow { pc: 0x200 }));
+}
+
+#[test]
+fn invalid_instructions_are_a_fault() {
+    // Each one after V0 = 1, the PC stays on it and nothing else runs
+    for opcode in [0x5011u16, 0x9011, 0x800F, 0xE000, 0xF0FF] {
+        let rom = [0x60, 0x01, (opcode >> 8) as u8, opcode as u8, 0x60, 0x02];
+        let mut bus = Bus::with_rom(&rom);
+        for _ in 0..3 {
+            bus.tick_frame_cpu();
+        }
+        assert_eq!(bus.fault(), Some(Fault::InvalidInstruction { pc: 0x202, opcode }));
+        assert_eq!(bus.registers().pc, 0x202);
+        assert_eq!(bus.registers().v[0], 1);
+    }
+}
+
+// I = 0xFFE, V0..V3 = 1..4 stored at I, past the end of the 4K
+const STORE_AT_END: [u8; 14] = [0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04, 0xAF, 0xFE, 0xF3, 0x55, 0x00, 0x00];
+
+fn run_store_at_end(policy: BoundsPolicy) -> Bus {
+    let mut bus = Bus::with_config(&STORE_AT_END, MachineConfig::default().with_bounds_policy(policy)).unwrap();
+    for _ in 0..6 {
+        bus.tick_frame_cpu();
+    }
+    bus
+}
+
+#[test]
+fn bounds_policies() {
+    let bus = run_store_at_end(BoundsPolicy::Wrap);
+    assert_eq!((bus.memory[0xFFE], bus.memory[0xFFF], bus.memory[0x000], bus.memory[0x001]), (1, 2, 3, 4));
+    assert_eq!(bus.fault(), None);
+
+    let bus = run_store_at_end(BoundsPolicy::Clamp);
+    assert_eq!((bus.memory[0xFFE], bus.memory[0xFFF]), (1, 4));
+    assert_eq!(bus.fault(), None);
+
+    let bus = run_store_at_end(BoundsPolicy::Fault);
+    assert_eq!((bus.memory[0xFFE], bus.memory[0xFFF], bus.memory[0x000]), (1, 2, 0xF0));
+    assert_eq!(bus.fault(), Some(Fault::OutOfBounds { pc: 0x20A, address: 0x1000 }));
+}
+
+#[test]
+fn write_protect_drops_interpreter_writes() {
+    // I = 0x100, V0 = 0x42 stored at I
+    let rom = [0xA1, 0x00, 0x60, 0x42, 0xF0, 0x55];
+    let mut bus = Bus::with_config(&rom, MachineConfig::default().with_write_protect(true)).unwrap();
+    for _ in 0..3 {
+        bus.tick_frame_cpu();
+    }
+
+    assert_eq!(bus.memory[0x100], 0);
+    assert_eq!(bus.take_debug_events(), vec![DebugEvent::ProtectedWrite { pc: 0x204, address: 0x100, value: 0x42 }]);
+    assert!(bus.take_debug_events().is_empty());
+}
+
+#[test]
+fn invalid_configs_are_an_error() {
+    let config = MachineConfig { memory_size: 0, ..MachineConfig::default() };
+    assert!(Bus::with_config(&[], config).is_err());
+    assert!(Bus::with_config(&[], MachineConfig::default().with_stack_depth(0)).is_err());
+    assert!(Bus::with_config(&[], MachineConfig::default().with_stack_depth(MAX_STACK_DEPTH + 1)).is_err());
+    assert!(Bus::with_config(&[], MachineConfig::default().with_stack_depth(MAX_STACK_DEPTH)).is_ok());
+
+    // The display can't go past the SUPER-CHIP high resolution
+    let display = |display_width, display_height| MachineConfig { display_width, display_height, ..MachineConfig::default() };
+    assert!(Bus::with_config(&[], display(MAX_DISPLAY_WIDTH, MAX_DISPLAY_HEIGHT)).is_ok());
+    assert!(Bus::with_config(&[], display(MAX_DISPLAY_WIDTH + 1, 32)).is_err());
+    assert!(Bus::with_config(&[], display(64, MAX_DISPLAY_HEIGHT + 1)).is_err());
+    assert!(Bus::with_config(&[], display(64, usize::MAX)).is_err());
+    assert!(Bus::with_config(&[], display(64, 0)).is_err());
+
+    // The bus stays as it was when a state can't be loaded
+    let mut bus = Bus::with_rom(&[0x60, 0x01]);
+    bus.step();
+    let mut state = bus.save_state();
+    state.config.memory_size = 0;
+    assert!(bus.load_state(&state).is_err());
+    assert_eq!(bus.registers().v[0], 1);
+    let mut state = bus.save_state();
+    state.config.display_height = 1 << 40;
+    assert!(bus.load_state(&state).is_err());
+}
+
+// 0xFFE: V0 = 0x12, then whatever the PC lands on
+fn run_last_instruction(policy: BoundsPolicy) -> Bus {
+    let mut bus = Bus::with_config(&[0x1F, 0xFE], MachineConfig::default().with_bounds_policy(policy)).unwrap();
+    bus.memory[0xFFE] = 0x60;
+    bus.memory[0xFFF] = 0x12;
+    bus.step();
+    bus.step();
+    bus
+}
+
+#[test]
+fn pc_follows_the_bounds_policy() {
+    let bus = run_last_instruction(BoundsPolicy::Wrap);
+    assert_eq!(bus.registers().pc, 0x000);
+    assert_eq!(bus.registers().v[0], 0x12);
+
+    let mut bus = run_last_instruction(BoundsPolicy::Fault);
+    assert_eq!(bus.registers().pc, 0x1000);
+    bus.step();
+    assert_eq!(bus.fault(), Some(Fault::OutOfBounds { pc: 0x1000, address: 0x1000 }));
+}
+
+#[test]
+fn font_past_the_end_of_the_address_space() {
+    // FX29 on a font placed at the very end wraps instead of overflowing
+    let mut config = MachineConfig { memory_size: 0x10000, ..MachineConfig::default() };
+    config.layout.font_base = 0xFFF0;
+    let mut bus = Bus::with_config(&[0x60, 0x0F, 0xF0, 0x29], config).unwrap();
+    bus.step();
+    bus.step();
+    assert_eq!(bus.registers().i, 0xFFF0u16.wrapping_add(0xF * 5));
+}
+
+#[test]
+fn save_state_keeps_the_random_numbers() {
+    // V0 = random, jump back
+    let rom = [0xC0, 0xFF, 0x12, 0x00];
+    let mut bus = Bus::with_rom(&rom);
+    for _ in 0..10 {
+        bus.step();
+        bus.step();
+    }
+
+    // Saving doesn't change what comes next
+    let state = bus.save_state();
+    assert_eq!(state.rng_draws, 10);
+    assert_eq!(bus.save_state().rng_seed, state.rng_seed);
+    let mut saved = Bus::with_rom(&rom);
+    saved.load_state(&state).unwrap();
+    for _ in 0..5 {
+        bus.step();
+        bus.step();
+        saved.step();
+        saved.step();
+        assert_eq!(saved.registers().v[0], bus.registers().v[0]);
+    }
+}
+
+#[test]
+fn huge_random_draw_counts_load_at_once() {
+    let rom = [0xC0, 0xFF, 0x12, 0x00];
+    let mut state = Bus::with_config(&rom, MachineConfig::default().with_seed(1234)).unwrap().save_state();
+    state.rng_draws = u64::MAX;
+    let mut bus = Bus::with_rom(&rom);
+    bus.load_state(&state).unwrap();
+    bus.step();
+    assert_eq!(bus.save_state().rng_draws, 0);
+
+    // Skipping ahead gives what drawing them one by one would have
+    let mut rng = StdRng::seed_from_u64(1234);
+    let expected: Vec<u8> = (0..8).map(|_| rng.gen()).collect();
+    state.rng_draws = 3;
+    bus.load_state(&state).unwrap();
+    let mut drawn = Vec::new();
+    for _ in 0..5 {
+        bus.step();
+        drawn.push(bus.registers().v[0]);
+        bus.step();
+    }
+    assert_eq!(drawn, expected[3..]);
+}
+
+#[test]
+fn save_state_clock_is_checked() {
+    let rom = [0x70, 0x01, 0x12, 0x00];
+    let mut bus = Bus::with_rom(&rom);
+    bus.run_for(Duration::from_millis(100));
+    let json: serde_json::Value = serde_json::from_str(&bus.save_state().to_json().unwrap()).unwrap();
+
+    let with_timing = |field: &str, value: f64| {
+        let mut json = json.clone();
+        json["timing"][field] = value.into();
+        SaveState::from_json(&json.to_string()).unwrap()
+    };
+
+    // The period is rebuilt from the rate of the config, a broken one doesn't matter
+    let mut loaded = Bus::with_rom(&rom);
+    loaded.load_state(&with_timing("cpu_period", 0.0)).unwrap();
+    let v0 = loaded.registers().v[0];
+    loaded.run_for(Duration::from_millis(100));
+    // 30 instructions, half of them adds
+    assert!((14..=16).contains(&loaded.registers().v[0].wrapping_sub(v0)));
+
+    // Anything the clock can't be is refused, and the bus is left alone
+    let registers = loaded.registers();
+    for (field, value) in [("cpu_next", 1e300), ("cpu_next", -1.0), ("timer_next", 1.0), ("now", -1.0)].iter() {
+        assert!(loaded.load_state(&with_timing(field, *value)).is_err(), "{} {}", field, value);
+    }
+    assert_eq!(loaded.registers(), registers);
+
+    // A huge catch up is cut down to what advance allows
+    loaded.load_state(&with_timing("pending", 1e300)).unwrap();
+    loaded.run_for(Duration::from_millis(0));
+}
@@ -0,0 +1,80 @@
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::profiler::opcode_class;
+
+/*
+ * 200: 2206 call 0x206
+ * 202: 2206 call 0x206
+ * 204: 1204 loop forever
+ * 206: 6001 V0 = 1
+ * 208: 7001 V0 += 1
+ * 20A: 00EE return
+ */
+const ROM: [u8; 12] = [0x22, 0x06, 0x22, 0x06, 0x12, 0x04, 0x60, 0x01, 0x70, 0x01, 0x00, 0xEE];
+
+#[test]
+fn test_opcode_classes() {
+    assert_eq!(opcode_class(0x00E0), "00E0");
+    assert_eq!(opcode_class(0x0123), "0NNN");
+    assert_eq!(opcode_class(0x8AB4), "8XY4");
+    assert_eq!(opcode_class(0x8AB9), "????");
+    assert_eq!(opcode_class(0xF265), "FX65");
+}
+
+#[test]
+fn test_profiler_counts_and_calls() {
+    let mut bus = Bus::with_rom(&ROM);
+    bus.enable_profiler();
+    // Both calls, then the loop 4 times
+    for _ in 0..12 {
+        bus.step();
+    }
+
+    let profiler = bus.profiler().unwrap();
+    let hot_spots = profiler.hot_spots();
+    let loop_spot = hot_spots.iter().find(|(pc, _)| *pc == 0x204).unwrap().1;
+    assert_eq!((loop_spot.opcode, loop_spot.executions), (0x1204, 4));
+    let sub_spot = hot_spots.iter().find(|(pc, _)| *pc == 0x206).unwrap().1;
+    assert_eq!(sub_spot.executions, 2);
+
+    let classes = profiler.opcode_classes();
+    let calls = classes.iter().find(|(class, _)| *class == "2NNN").unwrap().1;
+    assert_eq!(calls.executions, 2);
+
+    let functions = profiler.functions();
+    let main = functions.iter().find(|function| function.address == 0x200).unwrap();
+    let sub = functions.iter().find(|function| function.address == 0x206).unwrap();
+    assert_eq!(main.calls, 0);
+    assert_eq!(sub.calls, 2);
+    // Everything happens under the main program
+    assert_eq!(main.total_cycles, profiler.total_cycles());
+    assert_eq!(main.self_cycles + sub.total_cycles, main.total_cycles);
+
+    // Both call paths, the subroutine cycles are those of its 6 instructions
+    let folded = profiler.folded();
+    let lines: Vec<&str> = folded.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("0x200 "));
+    assert_eq!(lines[1], format!("0x200;0x206 {}", sub.total_cycles));
+    assert_eq!(sub.self_cycles, sub.total_cycles);
+
+    let report = profiler.report(5);
+    assert!(report.contains("Hot spots:"));
+    assert!(report.contains("Instructions: 12,"));
+}
+
+#[test]
+fn test_profiler_frames() {
+    let mut bus = Bus::with_rom(&ROM);
+    bus.enable_profiler();
+    bus.run_for(std::time::Duration::from_millis(100));
+
+    let frames = bus.profiler().unwrap().frames();
+    assert_eq!(frames.len(), 6);
+    // 5 instructions per frame by default
+    assert!(frames.iter().all(|frame| frame.instructions == 5 && frame.idle_cycles == 0));
+
+    // Kept across a reset, with the call path starting over
+    bus.reset();
+    assert!(bus.take_profiler().is_some());
+    assert!(bus.profiler().is_none());
+}
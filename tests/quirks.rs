@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::machine::MachineConfig;
+use rusty_chip_8::quirks::{IndexIncrement, Quirks};
+use rusty_chip_8::timing::ClockRate;
+
+/*
+ * 200: D011 draw a row of the 0 glyph at V0, V1
+ * 202: 7101 V1 += 1
+ * 204: 1200 loop
+ */
+const DRAW_LOOP: [u8; 6] = [0xD0, 0x11, 0x71, 0x01, 0x12, 0x00];
+
+fn draw_loop(display_wait: bool) -> Bus {
+    let quirks = Quirks { display_wait, ..Quirks::default() };
+    let config = MachineConfig::default()
+        .with_quirks(quirks)
+        .with_rate(ClockRate::InstructionsPerFrame(30));
+    Bus::with_config(&DRAW_LOOP, config).unwrap()
+}
+
+#[test]
+fn test_draws_freely_without_display_wait() {
+    let mut bus = draw_loop(false);
+    bus.run_for(Duration::from_millis(50));
+    let v1 = bus.registers().v[1];
+    assert!((29..=31).contains(&v1), "V1 = {}", v1);
+}
+
+#[test]
+fn test_display_wait_draws_once_per_frame() {
+    let mut bus = draw_loop(true);
+    bus.run_for(Duration::from_millis(50));
+    let v1 = bus.registers().v[1];
+    assert!((2..=3).contains(&v1), "V1 = {}", v1);
+}
+
+#[test]
+fn test_display_wait_halts_until_vblank() {
+    let mut bus = draw_loop(true);
+    bus.step();
+    assert!(bus.state.wait_vblank);
+    assert_eq!(bus.registers().pc, 0x202);
+
+    // Nothing runs while waiting
+    bus.tick_frame_cpu();
+    assert_eq!(bus.registers().pc, 0x202);
+
+    bus.tick_frame_timer();
+    assert!(!bus.state.wait_vblank);
+    bus.tick_frame_cpu();
+    assert_eq!(bus.registers().pc, 0x204);
+}
+
+// Runs the program for the given steps with the quirks
+fn run_with(quirks: Quirks, program: &[u8], steps: usize) -> Bus {
+    let mut bus = Bus::with_config(program, MachineConfig::default().with_quirks(quirks)).unwrap();
+    for _ in 0..steps {
+        bus.step();
+    }
+    bus
+}
+
+#[test]
+fn test_shift_in_place_or_from_vy() {
+    // V0 = 0x10, V1 = 0x81, then 8016 and 801E
+    let right = [0x60, 0x10, 0x61, 0x81, 0x80, 0x16];
+    let left = [0x60, 0x10, 0x61, 0x81, 0x80, 0x1E];
+
+    let bus = run_with(Quirks::default(), &right, 3);
+    assert_eq!((bus.registers().v[0], bus.registers().v[0xF]), (0x08, 0));
+    let bus = run_with(Quirks::default(), &left, 3);
+    assert_eq!((bus.registers().v[0], bus.registers().v[0xF]), (0x20, 0));
+
+    let vip = Quirks { shift_vy: true, ..Quirks::default() };
+    let bus = run_with(vip, &right, 3);
+    assert_eq!((bus.registers().v[0], bus.registers().v[0xF]), (0x40, 1));
+    let bus = run_with(vip, &left, 3);
+    assert_eq!((bus.registers().v[0], bus.registers().v[0xF]), (0x02, 1));
+    assert_eq!(bus.registers().v[1], 0x81);
+}
+
+#[test]
+fn test_logic_resets_vf() {
+    // VF = 1, V0 |= V1
+    let program = [0x6F, 0x01, 0x80, 0x11];
+    assert_eq!(run_with(Quirks::default(), &program, 2).registers().v[0xF], 1);
+    let quirks = Quirks { logic_reset_vf: true, ..Quirks::default() };
+    assert_eq!(run_with(quirks, &program, 2).registers().v[0xF], 0);
+}
+
+#[test]
+fn test_load_store_index_increment() {
+    // I = 0x300, store V0..V2, then load V0..V1
+    let program = [0xA3, 0x00, 0xF2, 0x55, 0xF1, 0x65];
+    let i_after = |index_increment| {
+        run_with(Quirks { index_increment, ..Quirks::default() }, &program, 3).registers().i
+    };
+    assert_eq!(i_after(IndexIncrement::Unchanged), 0x300);
+    assert_eq!(i_after(IndexIncrement::ByX), 0x303);
+    assert_eq!(i_after(IndexIncrement::ByXPlusOne), 0x305);
+}
+
+#[test]
+fn test_jump_with_offset() {
+    // V0 = 2, V3 = 8, B310
+    let program = [0x60, 0x02, 0x63, 0x08, 0xB3, 0x10];
+    assert_eq!(run_with(Quirks::default(), &program, 3).registers().pc, 0x312);
+    let quirks = Quirks { jump_vx: true, ..Quirks::default() };
+    assert_eq!(run_with(quirks, &program, 3).registers().pc, 0x318);
+}
+
+#[test]
+fn test_sprites_clip_or_wrap() {
+    // V0 = 60, V1 = 30, I = glyph of 8 (all 5 rows lit), draw it
+    let program = [0x60, 60, 0x61, 30, 0x62, 0x08, 0xF2, 0x29, 0xD0, 0x15];
+
+    let bus = run_with(Quirks::default(), &program, 5);
+    let vram = bus.get_vram();
+    assert!(vram.get(63, 30) && !vram.get(0, 30));
+    assert!(!vram.get(60, 0));
+
+    let quirks = Quirks { wrap_sprites: true, ..Quirks::default() };
+    let bus = run_with(quirks, &program, 5);
+    let vram = bus.get_vram();
+    // Top row of the 8 is 0xF0, the glyph fits before the right edge
+    assert!(vram.get(60, 30) && vram.get(63, 30));
+    // The last three rows come back at the top
+    assert!(vram.get(60, 0) && vram.get(63, 0));
+    assert!(vram.get(60, 2) && vram.get(63, 2));
+    assert!(!vram.get(60, 3));
+
+    // V0 = 62, draw a full row so the last pixels go to the left
+    let program = [0xA3, 0x00, 0x60, 62, 0xD0, 0x11];
+    let mut bus = Bus::with_config(&program, MachineConfig::default().with_quirks(quirks)).unwrap();
+    bus.memory[0x300] = 0xFF;
+    for _ in 0..3 {
+        bus.step();
+    }
+    let lit: Vec<usize> = (0..64).filter(|&x| bus.get_vram().get(x, 0)).collect();
+    assert_eq!(lit, [0, 1, 2, 3, 4, 5, 62, 63]);
+}
@@ -0,0 +1,261 @@
+use std::borrow::Cow;
+use std::io::{Cursor, Write};
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::machine::{ConfigOverrides, MachineConfig};
+use rusty_chip_8::octo::assemble;
+use rusty_chip_8::quirks::{IndexIncrement, Quirks};
+use rusty_chip_8::rom;
+use rusty_chip_8::romdb::sha1_hex;
+use rusty_chip_8::timing::ClockRate;
+
+const PONG: [u8; 2] = [0x12, 0x00];
+const MAZE: [u8; 2] = [0x12, 0x02];
+
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in files {
+        writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+// Same encoding as Octo: the length and the JSON, 2 bits per pixel
+fn cartridge(json: &str) -> Vec<u8> {
+    let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+    payload.extend_from_slice(json.as_bytes());
+    let mut pixels: Vec<u8> = payload.iter()
+        .flat_map(|byte| vec![byte >> 6, (byte >> 4) & 0x3, (byte >> 2) & 0x3, byte & 0x3])
+        .collect();
+    let width = 64;
+    let height = pixels.len().div_ceil(width);
+    pixels.resize(width * height, 0);
+
+    let palette = [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255];
+    let mut gif = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut gif, width as u16, height as u16, &palette).unwrap();
+        let frame = gif::Frame {
+            width: width as u16,
+            height: height as u16,
+            buffer: Cow::Owned(pixels),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).unwrap();
+    }
+    gif
+}
+
+fn run(program: &[u8], steps: usize) -> Bus {
+    let mut bus = Bus::with_rom(program);
+    for _ in 0..steps {
+        bus.step();
+    }
+    bus
+}
+
+#[test]
+fn test_raw_rom() {
+    let rom = rom::from_bytes("pong.ch8".to_string(), PONG.to_vec(), None).unwrap();
+    assert_eq!(rom.name, "pong.ch8");
+    assert_eq!(rom.data, PONG);
+    assert!(rom.options.is_none());
+}
+
+#[test]
+fn test_zip_with_a_single_rom() {
+    let archive = zip(&[("README.txt", b"Have fun"), ("games/pong.ch8", &PONG)]);
+    let rom = rom::from_bytes("games.zip".to_string(), archive, None).unwrap();
+    assert_eq!(rom.name, "games/pong.ch8");
+    assert_eq!(rom.data, PONG);
+}
+
+#[test]
+fn test_zip_entry_choice() {
+    let archive = zip(&[("pong.ch8", &PONG), ("maze.ch8", &MAZE)]);
+    let err = rom::from_bytes("games.zip".to_string(), archive.clone(), None).err().unwrap();
+    assert!(err.to_string().contains("pong.ch8"), "{}", err);
+
+    let rom = rom::from_bytes("games.zip".to_string(), archive.clone(), Some("maze.ch8")).unwrap();
+    assert_eq!(rom.data, MAZE);
+    // Entries that are not on the archive
+    assert!(rom::from_bytes("games.zip".to_string(), archive, Some("tetris.ch8")).is_err());
+
+    let archive = zip(&[("README.txt", b"Nothing to run")]);
+    assert!(rom::from_bytes("empty.zip".to_string(), archive, None).is_err());
+}
+
+#[test]
+fn test_cartridge_decoding() {
+    let gif = cartridge(r##"{
+        "program": ": main v0 := 7 loop again",
+        "options": { "tickrate": 20, "fillColor": "#FF8000", "backgroundColor": "#101010", "vBlankQuirks": true }
+    }"##);
+    let rom = rom::read_cartridge(&gif).unwrap();
+    assert_eq!(rom.program, ": main v0 := 7 loop again");
+    assert_eq!(rom.options.tickrate, Some(20));
+
+    assert!(rom::read_cartridge(&cartridge("not json")).is_err());
+}
+
+#[test]
+fn test_cartridge_runs_with_its_options() {
+    let gif = cartridge(r##"{
+        "program": ": main v0 := 7 loop again",
+        "options": { "tickrate": 20, "fillColor": "#FF8000", "backgroundColor": "#101010", "vBlankQuirks": true }
+    }"##);
+    let rom = rom::from_bytes("game.gif".to_string(), gif, None).unwrap();
+    assert_eq!(rom.data, [0x60, 0x07, 0x12, 0x02]);
+
+    let info = rom.options.unwrap().rom_info(sha1_hex(&rom.data));
+    assert_eq!(info.palette(), Some(([0xFF, 0x80, 0x00, 255], [0x10, 0x10, 0x10, 255])));
+    let config = MachineConfig::resolve(None, None, Some(&info), &ConfigOverrides::default());
+    assert_eq!(config.rate, ClockRate::InstructionsPerFrame(20));
+    assert!(config.quirks.display_wait);
+}
+
+#[test]
+fn test_cartridge_quirks_reach_the_machine() {
+    let quirks_of = |options: &str| {
+        let gif = cartridge(&format!(r#"{{ "program": ": main loop again", "options": {} }}"#, options));
+        let rom = rom::from_bytes("game.gif".to_string(), gif, None).unwrap();
+        let info = rom.options.unwrap().rom_info(sha1_hex(&rom.data));
+        MachineConfig::resolve(None, None, Some(&info), &ConfigOverrides::default()).quirks
+    };
+
+    // What Octo does with every quirk off is the COSMAC VIP behaviour
+    let quirks = quirks_of(r#"{ "shiftQuirks": false, "loadStoreQuirks": false, "clipQuirks": false,
+        "jumpQuirks": false, "logicQuirks": true, "vBlankQuirks": true }"#);
+    assert_eq!(quirks, Quirks {
+        display_wait: true,
+        shift_vy: true,
+        logic_reset_vf: true,
+        index_increment: IndexIncrement::ByXPlusOne,
+        wrap_sprites: true,
+        ..Quirks::default()
+    });
+
+    let quirks = quirks_of(r#"{ "shiftQuirks": true, "loadStoreQuirks": true, "clipQuirks": true,
+        "jumpQuirks": true, "logicQuirks": false, "vBlankQuirks": false }"#);
+    assert_eq!(quirks, Quirks { jump_vx: true, ..Quirks::default() });
+}
+
+#[test]
+fn test_cartridge_in_a_zip() {
+    let gif = cartridge(r#"{ "program": ": main loop again" }"#);
+    let archive = zip(&[("game.gif", &gif), ("pong.ch8", &PONG)]);
+    let rom = rom::from_bytes("games.zip".to_string(), archive, Some("game.gif")).unwrap();
+    assert_eq!(rom.data, [0x12, 0x00]);
+    assert!(rom.options.is_some());
+}
+
+#[test]
+fn test_cartridge_that_does_not_assemble() {
+    let gif = cartridge(r#"{ "program": ": main hires loop again" }"#);
+    let err = rom::from_bytes("game.gif".to_string(), gif, None).err().unwrap();
+    assert!(err.to_string().contains("SUPER-CHIP"), "{}", err);
+}
+
+#[test]
+fn test_assemble_basics() {
+    // main comes first, no jump needed
+    assert_eq!(assemble(": main v0 := 5 v1 += 2 loop again").unwrap(), [0x60, 0x05, 0x71, 0x02, 0x12, 0x04]);
+    // Otherwise 0x200 jumps to it
+    assert_eq!(assemble(": data 0x12 0x34 : main i := data loop again").unwrap(),
+        [0x12, 0x04, 0x12, 0x34, 0xA2, 0x02, 0x12, 0x06]);
+    // Labels can be used before they are defined, a bare name is a call
+    assert_eq!(assemble(": main sub loop again : sub v0 := 1 ;").unwrap(),
+        [0x22, 0x04, 0x12, 0x02, 0x60, 0x01, 0x00, 0xEE]);
+    assert_eq!(assemble(": main if v0 == 3 then v1 := 1 # a comment\nsprite v0 v1 5").unwrap(),
+        [0x40, 0x03, 0x61, 0x01, 0xD0, 0x15]);
+    assert_eq!(assemble(": main if v0 != v1 begin v2 := 1 else v2 := 2 end").unwrap(),
+        [0x90, 0x10, 0x12, 0x08, 0x62, 0x01, 0x12, 0x0A, 0x62, 0x02]);
+}
+
+#[test]
+fn test_assembled_comparisons() {
+    let program = assemble("
+        : main
+            v0 := 5
+            v1 := 9
+            if v0 < v1 then v2 += 1
+            if v1 > v0 then v2 += 2
+            if v0 >= 5 then v2 += 4
+            if v0 <= 4 then v2 += 8
+            if v1 < 9 then v2 += 16
+            if v1 <= v0 then v2 += 32
+        : halt
+            jump halt
+    ").unwrap();
+    let bus = run(&program, 40);
+    assert_eq!(bus.registers().v[2], 7);
+}
+
+#[test]
+fn test_assembled_loops() {
+    let program = assemble("
+        : main
+            loop
+                v0 += 1
+            while v0 != 10
+            again
+            v1 := 1
+        : halt
+            jump halt
+    ").unwrap();
+    let bus = run(&program, 60);
+    assert_eq!(bus.registers().v[0], 10);
+    assert_eq!(bus.registers().v[1], 1);
+}
+
+#[test]
+fn test_assemble_constants_and_macros() {
+    let program = assemble("
+        :const SPEED 3
+        :calc DOUBLE { SPEED * ( 1 + 1 ) }
+        :alias counter v4
+        :macro add-twice reg { reg += SPEED reg += SPEED }
+        : main
+            add-twice counter
+            v5 := DOUBLE
+            :unpack 0xA sprite-data
+            loop again
+        : sprite-data
+            0xFF
+    ").unwrap();
+    let bus = run(&program, 5);
+    let v = bus.registers().v;
+    assert_eq!((v[4], v[5]), (6, 6));
+    assert_eq!((v[0], v[1]), (0xA2, 0x0C));
+}
+
+#[test]
+fn test_assemble_errors() {
+    let err = assemble(": main\njump nowhere").err().unwrap();
+    assert!(err.to_string().starts_with("Line 2"), "{}", err);
+    assert!(assemble(": start loop again").is_err());
+    assert!(assemble(": main v0 := 300").is_err());
+    assert!(assemble(": main loop v0 += 1").is_err());
+    assert!(assemble(": main scroll-down 4").is_err());
+}
+
+#[test]
+fn test_assembler_limits() {
+    // Macros that never stop expanding
+    let err = assemble(":macro forever { forever } : main forever").err().unwrap();
+    assert!(err.to_string().contains("calling itself"), "{}", err);
+    assert!(assemble(":macro twice { twice twice } : main twice").is_err());
+    let gif = cartridge(r#"{ "program": ":macro forever { forever } : main forever" }"#);
+    assert!(rom::from_bytes("game.gif".to_string(), gif, None).is_err());
+
+    // Deep expressions are refused, reasonable ones still work
+    let nested = |depth: usize| format!(":calc X {{ {} 1 {} }} : main v0 := X", "( ".repeat(depth), ") ".repeat(depth));
+    assert_eq!(assemble(&nested(50)).unwrap(), [0x60, 0x01]);
+    let err = assemble(&nested(100_000)).err().unwrap();
+    assert!(err.to_string().contains("nested"), "{}", err);
+    let chain = format!(":calc X {{ 1 {} }} : main v0 := X", "+ 1 ".repeat(100_000));
+    assert!(assemble(&chain).is_err());
+    let unary = format!(":calc X {{ {} 1 }} : main v0 := X", "- ".repeat(100_000));
+    assert!(assemble(&unary).is_err());
+}
@@ -0,0 +1,183 @@
+use std::fs;
+
+use rusty_chip_8::machine::{ConfigOverrides, MachineConfig, Variant};
+use rusty_chip_8::quirks::{IndexIncrement, Quirks};
+use rusty_chip_8::romdb::{sha1_hex, RomDatabase, RomInfo};
+use rusty_chip_8::timing::ClockRate;
+
+const ROM: [u8; 4] = [0x12, 0x00, 0x12, 0x00];
+
+const PLATFORMS: &str = r#"[
+    { "id": "originalChip8", "name": "Cosmac VIP", "defaultTickrate": 15, "quirks": { "vblank": true, "logic": true } },
+    { "id": "modernChip8", "name": "Modern CHIP-8", "defaultTickrate": 12, "quirks": { "vblank": false } },
+    { "id": "xochip", "name": "XO-CHIP", "defaultTickrate": 100, "quirks": { "vblank": false } }
+]"#;
+
+// The hashes get filled in with the one of ROM
+fn database() -> RomDatabase {
+    let hash = sha1_hex(&ROM);
+    let programs = r##"[
+        { "title": "Other", "roms": {} },
+        { "title": "Loop", "authors": ["Someone"], "roms": { "HASH": {
+            "file": "loop.ch8",
+            "platforms": ["originalChip8", "modernChip8"],
+            "quirkyPlatforms": { "originalChip8": { "vblank": false } },
+            "startAddress": 1536,
+            "colors": { "pixels": ["#000000", "#ff8000"] },
+            "keys": { "up": 5 }
+        } } }
+    ]"##.replace("HASH", &hash);
+    let hashes = format!(r#"{{ "{}": 1 }}"#, hash);
+    RomDatabase::new(PLATFORMS, &programs, &hashes).unwrap()
+}
+
+#[test]
+fn test_sha1() {
+    assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
+
+#[test]
+fn test_bundled_platforms() {
+    let database = RomDatabase::bundled();
+    let vip = database.platform("originalChip8").unwrap();
+    assert_eq!(vip.default_tickrate, Some(15));
+    assert_eq!(vip.quirks.vblank, Some(true));
+}
+
+#[test]
+fn test_lookup_by_hash() {
+    let database = database();
+    assert!(database.lookup_rom(&[0x00, 0xE0]).is_none());
+
+    let info = database.lookup_rom(&ROM).unwrap();
+    assert_eq!(info.title.as_deref(), Some("Loop"));
+    assert_eq!(info.authors, ["Someone"]);
+    // The first platform is the one it was made for
+    assert_eq!(info.platform.as_ref().unwrap().id, "originalChip8");
+    assert_eq!(info.variant(), Some(Variant::Vip));
+    // The quirky platform entry wins over the platform
+    assert_eq!(info.quirks.vblank, Some(false));
+    assert_eq!(info.quirks.logic, Some(true));
+    assert_eq!(info.tickrate, Some(15));
+    assert_eq!(info.start_address, Some(0x600));
+    assert_eq!(info.palette(), Some(([0xFF, 0x80, 0x00, 255], [0, 0, 0, 255])));
+
+    // Hashes aren't case sensitive
+    assert!(database.lookup(&sha1_hex(&ROM).to_uppercase()).is_some());
+}
+
+#[test]
+fn test_overrides_file() {
+    let mut database = database();
+    let path = std::env::temp_dir().join(format!("rusty_chip_8_romdb_{}.json", std::process::id()));
+    fs::write(&path, format!(r#"{{
+        "{}": {{ "title": "My loop", "platforms": ["modernChip8"], "tickrate": 20 }},
+        "0000000000000000000000000000000000000000": {{ "title": "Local only" }}
+    }}"#, sha1_hex(&ROM).to_uppercase())).unwrap();
+    database.load_overrides(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let info = database.lookup_rom(&ROM).unwrap();
+    assert_eq!(info.title.as_deref(), Some("My loop"));
+    assert_eq!(info.variant(), Some(Variant::Chip8));
+    assert_eq!(info.tickrate, Some(20));
+    // Whatever the override doesn't say stays
+    assert_eq!(info.start_address, Some(0x600));
+
+    let local = database.lookup("0000000000000000000000000000000000000000").unwrap();
+    assert_eq!(local.title.as_deref(), Some("Local only"));
+    assert!(local.platform.is_none());
+}
+
+#[test]
+fn test_platforms_to_variants() {
+    let database = RomDatabase::new(PLATFORMS, r#"[{ "title": "X", "roms": { "abc": { "platforms": ["xochip"] } } }]"#,
+        r#"{ "abc": 0 }"#).unwrap();
+    let info = database.lookup("abc").unwrap();
+    assert_eq!(info.variant(), None);
+    assert_eq!(MachineConfig::resolve(None, None, Some(&info), &ConfigOverrides::default()).variant, Variant::Chip8);
+}
+
+#[test]
+fn test_database_picks_the_machine() {
+    let info = database().lookup_rom(&ROM).unwrap();
+    let config = MachineConfig::resolve(None, None, Some(&info), &ConfigOverrides::default());
+    // The VIP defaults, with the ROM settings on top
+    assert_eq!(config.variant, Variant::Vip);
+    assert_eq!(config.stack_depth, 12);
+    assert!(config.quirks.key_wait_release);
+    assert!(!config.quirks.display_wait);
+    assert_eq!(config.rate, ClockRate::InstructionsPerFrame(15));
+    assert_eq!((config.layout.load_address, config.layout.entry_pc), (0x600, 0x600));
+}
+
+#[test]
+fn test_file_then_database_then_command_line() {
+    let info = database().lookup_rom(&ROM).unwrap();
+    let file = MachineConfig::from_toml("variant = \"chip8\"\nstack_depth = 4\n[rate]\nhz = 500\n").unwrap();
+    let overrides = ConfigOverrides {
+        display_wait: true,
+        entry_pc: Some(0x602),
+        ..ConfigOverrides::default()
+    };
+
+    let config = MachineConfig::resolve(Some(file.clone()), Some(Variant::SuperChip), Some(&info), &overrides);
+    // The file is the base, not the variant or the platform
+    assert_eq!(config.variant, Variant::Chip8);
+    assert_eq!(config.stack_depth, 4);
+    // The database goes on top of the file
+    assert_eq!(config.rate, ClockRate::InstructionsPerFrame(15));
+    assert_eq!(config.layout.load_address, 0x600);
+    // And the command line on top of everything
+    assert!(config.quirks.display_wait);
+    assert_eq!(config.layout.entry_pc, 0x602);
+
+    let overrides = ConfigOverrides { rate: Some(ClockRate::Hz(1000)), ..ConfigOverrides::default() };
+    let config = MachineConfig::resolve(Some(file), None, Some(&info), &overrides);
+    assert_eq!(config.rate, ClockRate::Hz(1000));
+}
+
+#[test]
+fn test_variant_given_by_hand_wins_over_the_platform() {
+    let info = database().lookup_rom(&ROM).unwrap();
+    let config = MachineConfig::resolve(None, Some(Variant::Chip48), Some(&info), &ConfigOverrides::default());
+    assert_eq!(config.variant, Variant::Chip48);
+    assert_eq!(config.stack_depth, 16);
+    // The ROM speed still wins over the variant one
+    assert_eq!(config.rate, ClockRate::InstructionsPerFrame(15));
+
+    let config = MachineConfig::resolve(None, Some(Variant::Vip), None, &ConfigOverrides::default());
+    assert_eq!(config, MachineConfig::new(Variant::Vip));
+}
+
+#[test]
+fn test_every_database_quirk_is_applied() {
+    let database = RomDatabase::bundled();
+    let quirks_of = |platform: &str| {
+        let info = RomInfo { quirks: database.platform(platform).unwrap().quirks, ..RomInfo::default() };
+        let mut quirks = Quirks::default();
+        info.apply_quirks(&mut quirks);
+        quirks
+    };
+
+    assert_eq!(quirks_of("originalChip8"), Quirks {
+        display_wait: true,
+        shift_vy: true,
+        logic_reset_vf: true,
+        index_increment: IndexIncrement::ByXPlusOne,
+        ..Quirks::default()
+    });
+    assert_eq!(quirks_of("chip48"), Quirks { jump_vx: true, index_increment: IndexIncrement::ByX, ..Quirks::default() });
+    assert_eq!(quirks_of("superchip"), Quirks { jump_vx: true, ..Quirks::default() });
+    assert_eq!(quirks_of("xochip"), Quirks {
+        shift_vy: true,
+        wrap_sprites: true,
+        index_increment: IndexIncrement::ByXPlusOne,
+        ..Quirks::default()
+    });
+
+    // What the entry leaves out stays as it was
+    let mut quirks = Quirks { jump_vx: true, index_increment: IndexIncrement::ByX, ..Quirks::default() };
+    RomInfo::default().apply_quirks(&mut quirks);
+    assert_eq!(quirks, Quirks { jump_vx: true, index_increment: IndexIncrement::ByX, ..Quirks::default() });
+}
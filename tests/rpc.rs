@@ -0,0 +1,176 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::rpc::RpcServer;
+
+// Sends a request and returns its result, panicking on errors
+fn call<S: Write>(stream: &mut S, reader: &mut dyn BufRead, id: u64, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+    writeln!(stream, "{}", request).unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let response: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(response["id"], id);
+    if let Some(error) = response.get("error") {
+        panic!("{} failed: {}", method, error);
+    }
+    response["result"].clone()
+}
+
+fn serve_until_done(server: &mut RpcServer, bus: &mut Bus, client: thread::JoinHandle<()>) {
+    while !client.is_finished() {
+        server.poll(bus).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    client.join().unwrap();
+}
+
+#[test]
+fn client_drives_the_machine_over_tcp() {
+    // 0x200: V0 = 0 (font glyph), I = font, draw it at V0,V0, wait for a key into V1, jump to itself
+    let rom = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0xF1, 0x0A, 0x12, 0x08];
+    let mut bus = Bus::with_rom(&rom);
+    let mut server = RpcServer::bind("tcp:127.0.0.1:0").unwrap();
+    let port = server.local_port().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let reader: &mut dyn BufRead = &mut reader;
+
+        let registers = call(&mut stream, reader, 1, "step", json!({ "count": 3 }));
+        assert_eq!(registers["pc"], 0x206);
+
+        let vram = call(&mut stream, reader, 2, "get_vram", json!({}));
+        assert_eq!((vram["width"].as_u64(), vram["height"].as_u64(), vram["stride"].as_u64()), (Some(64), Some(32), Some(8)));
+        // First rows of the 0 glyph, 8 bytes per row
+        assert_eq!(vram["bitmap"][0], 0xF0);
+        assert_eq!(vram["bitmap"][8], 0x90);
+
+        let state = call(&mut stream, reader, 3, "save_state", json!({}));
+
+        // Keys held before FX0A starts waiting are ignored, so wait first
+        call(&mut stream, reader, 4, "run_frames", json!({ "count": 1 }));
+        call(&mut stream, reader, 5, "press_key", json!({ "key": 7 }));
+        call(&mut stream, reader, 6, "run_frames", json!({ "count": 1 }));
+        assert_eq!(call(&mut stream, reader, 7, "get_registers", json!({}))["v"][1], 7);
+
+        call(&mut stream, reader, 8, "write_memory", json!({ "address": 0x300, "data": [1, 2, 3] }));
+        assert_eq!(call(&mut stream, reader, 9, "read_memory", json!({ "address": 0x2FF, "length": 4 })), json!([0, 1, 2, 3]));
+
+        // Back to before the key press
+        let registers = call(&mut stream, reader, 10, "load_state", json!({ "state": state }));
+        assert_eq!((registers["pc"].as_u64(), registers["v"][1].as_u64()), (Some(0x206), Some(0)));
+        assert_eq!(call(&mut stream, reader, 11, "read_memory", json!({ "address": 0x300, "length": 1 })), json!([0]));
+
+        call(&mut stream, reader, 12, "reset", json!({}));
+        assert_eq!(call(&mut stream, reader, 13, "get_registers", json!({}))["pc"], 0x200);
+
+        // Errors keep the connection usable
+        writeln!(stream, "{}", json!({ "jsonrpc": "2.0", "id": 14, "method": "fly" })).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&line).unwrap()["error"]["code"], -32601);
+        call(&mut stream, reader, 15, "set_registers", json!({ "v": [9], "pc": 0x204 }));
+        assert_eq!(call(&mut stream, reader, 16, "get_registers", json!({}))["v"][0], 9);
+    });
+
+    serve_until_done(&mut server, &mut bus, client);
+}
+
+#[cfg(unix)]
+#[test]
+fn client_over_unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("rusty_chip_8_rpc_{}.sock", std::process::id()));
+    let mut bus = Bus::with_rom(&[0x60, 0x2A]);
+    let mut server = RpcServer::bind(&format!("unix:{}", path.display())).unwrap();
+
+    let client_path = path.clone();
+    let client = thread::spawn(move || {
+        let mut stream = UnixStream::connect(client_path).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        assert_eq!(call(&mut stream, &mut reader, 1, "step", json!({}))["v"][0], 0x2A);
+    });
+
+    serve_until_done(&mut server, &mut bus, client);
+    drop(server);
+    assert!(!path.exists());
+}
+
+#[test]
+fn out_of_range_requests_are_bounded() {
+    // 0x200: I += V0, 0x202: jump to itself
+    let mut bus = Bus::with_rom(&[0xF0, 0x1E, 0x12, 0x02]);
+    let mut server = RpcServer::bind("tcp:127.0.0.1:0").unwrap();
+    let port = server.local_port().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let reader: &mut dyn BufRead = &mut reader;
+
+        // Memory goes around the end, and never more than all of it
+        call(&mut stream, reader, 1, "write_memory", json!({ "address": 0xFFF, "data": [7, 8] }));
+        assert_eq!(call(&mut stream, reader, 2, "read_memory", json!({ "address": 0xFFF, "length": 2 })), json!([7, 8]));
+        assert_eq!(call(&mut stream, reader, 3, "read_memory", json!({ "address": u64::MAX, "length": 1 })), json!([7]));
+        let all = call(&mut stream, reader, 4, "read_memory", json!({ "address": 0, "length": u64::MAX }));
+        assert_eq!(all.as_array().unwrap().len(), 0x1000);
+
+        // Registers past 16 bits are refused, I wraps on FX1E
+        writeln!(stream, "{}", json!({ "jsonrpc": "2.0", "id": 5, "method": "set_registers", "params": { "pc": 0x10000 } })).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&line).unwrap()["error"]["code"], -32602);
+        call(&mut stream, reader, 6, "set_registers", json!({ "v": [2], "i": 0xFFFF }));
+        assert_eq!(call(&mut stream, reader, 7, "step", json!({}))["i"], 0x001);
+
+        assert_eq!(call(&mut stream, reader, 8, "run_frames", json!({ "count": u64::MAX }))["frames"], 3600);
+    });
+
+    serve_until_done(&mut server, &mut bus, client);
+}
+
+#[test]
+fn set_registers_refuses_what_does_not_fit() {
+    let mut bus = Bus::with_rom(&[0x12, 0x00]);
+    let mut server = RpcServer::bind("tcp:127.0.0.1:0").unwrap();
+    let port = server.local_port().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let reader: &mut dyn BufRead = &mut reader;
+
+        let before = call(&mut stream, reader, 1, "get_registers", json!({}));
+        // Each one along with a valid pc, which must not be set either
+        let refused = [
+            json!({ "v": vec![1; 17] }),
+            json!({ "sp": 17 }),
+            json!({ "delay": 256 }),
+            json!({ "sound": 256 }),
+        ];
+        for (id, params) in refused.iter().enumerate() {
+            let mut params = params.clone();
+            params["pc"] = json!(0x300);
+            writeln!(stream, "{}", json!({ "jsonrpc": "2.0", "id": id, "method": "set_registers", "params": params })).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(serde_json::from_str::<Value>(&line).unwrap()["error"]["code"], -32602, "{}", params);
+        }
+        assert_eq!(call(&mut stream, reader, 10, "get_registers", json!({})), before);
+
+        // The edges still fit
+        let registers = call(&mut stream, reader, 11, "set_registers",
+            json!({ "v": vec![1; 16], "sp": 16, "delay": 255, "sound": 255 }));
+        assert_eq!((registers["v"][15].clone(), registers["sp"].clone()), (json!(1), json!(16)));
+    });
+
+    serve_until_done(&mut server, &mut bus, client);
+}
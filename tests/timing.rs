@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::machine::MachineConfig;
+use rusty_chip_8::timing::{vip_cycles, ClockRate, Tick, Timing, TimingMode};
+
+// 200: 7001 V0 += 1, 202: 1200 loop, one V0 step every 2 instructions
+const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+// Spends what was given, counting (instructions, timer ticks)
+fn drain(timing: &mut Timing, elapsed: Duration) -> (u32, u32) {
+    timing.advance(elapsed);
+    let (mut cpu, mut timers) = (0, 0);
+    while let Some(tick) = timing.next_tick() {
+        match tick {
+            Tick::Cpu => cpu += 1,
+            Tick::Timers => timers += 1,
+        }
+    }
+    (cpu, timers)
+}
+
+#[test]
+fn test_instructions_per_frame() {
+    let mut timing = Timing::new(ClockRate::InstructionsPerFrame(10));
+    let (cpu, timers) = drain(&mut timing, Duration::from_millis(100));
+    // 6 frames of 10, give or take the one on the edge
+    assert!((59..=61).contains(&cpu), "{} instructions", cpu);
+    assert!((5..=6).contains(&timers), "{} timer ticks", timers);
+}
+
+#[test]
+fn test_hz() {
+    let mut timing = Timing::new(ClockRate::Hz(1000));
+    let (cpu, timers) = drain(&mut timing, Duration::from_millis(200));
+    assert!((199..=201).contains(&cpu), "{} instructions", cpu);
+    assert!((11..=12).contains(&timers), "{} timer ticks", timers);
+}
+
+#[test]
+fn test_timers_tick_first_on_the_same_instant() {
+    let mut timing = Timing::new(ClockRate::InstructionsPerFrame(1));
+    timing.advance(Duration::from_secs_f64(1.5 / 60.0));
+    assert_eq!(timing.next_tick(), Some(Tick::Cpu));
+    // Both are due at 1/60
+    assert_eq!(timing.next_tick(), Some(Tick::Timers));
+    assert_eq!(timing.next_tick(), Some(Tick::Cpu));
+    assert_eq!(timing.next_tick(), None);
+}
+
+#[test]
+fn test_catch_up_is_capped() {
+    let mut timing = Timing::new(ClockRate::Hz(1000));
+    // A stall of 5 seconds only gets a quarter of a second back
+    let (cpu, _) = drain(&mut timing, Duration::from_secs(5));
+    assert!((249..=251).contains(&cpu), "{} instructions", cpu);
+}
+
+#[test]
+fn test_speed_multiplier() {
+    let mut timing = Timing::new(ClockRate::Hz(1000));
+    timing.set_speed(2.0);
+    let (fast, _) = drain(&mut timing, Duration::from_millis(100));
+    assert!((199..=201).contains(&fast), "{} instructions", fast);
+
+    timing.set_speed(0.5);
+    let (slow, _) = drain(&mut timing, Duration::from_millis(100));
+    assert!((49..=51).contains(&slow), "{} instructions", slow);
+}
+
+#[test]
+fn test_discard_pending() {
+    let mut timing = Timing::new(ClockRate::Hz(1000));
+    timing.advance(Duration::from_millis(100));
+    // The first instruction is due right at power on
+    assert_eq!(timing.next_tick(), Some(Tick::Cpu));
+    timing.discard_pending();
+    assert_eq!(timing.next_tick(), None);
+}
+
+#[test]
+fn test_bus_follows_the_rate() {
+    let config = MachineConfig::default().with_rate(ClockRate::InstructionsPerFrame(20));
+    let mut bus = Bus::with_config(&COUNTER, config).unwrap();
+    bus.run_for(Duration::from_millis(50));
+    // 3 frames of 20 instructions, half of them add
+    let v0 = bus.registers().v[0];
+    assert!((29..=31).contains(&v0), "V0 = {}", v0);
+    assert!(bus.time() <= Duration::from_millis(50));
+}
+
+#[test]
+fn test_vip_cycles() {
+    // 40 of them are the fetch/decode loop
+    assert_eq!(vip_cycles(0x1200, false, 0), 52);
+    assert_eq!(vip_cycles(0x3001, true, 0), vip_cycles(0x3001, false, 0) + 4);
+    assert_eq!(vip_cycles(0x00E0, false, 0), 3118);
+    // Unaligned sprites cost more per row
+    assert_eq!(vip_cycles(0xD015, false, 8), 40 + 26 + 5 * 38);
+    assert_eq!(vip_cycles(0xD015, false, 3), 40 + 26 + 5 * 66);
+    // BCD follows the digits
+    assert!(vip_cycles(0xF033, false, 199) > vip_cycles(0xF033, false, 100));
+    assert_eq!(vip_cycles(0xF355, false, 0), 40 + 14 + 14 * 4);
+}
+
+#[test]
+fn test_vip_cycle_budget() {
+    let config = MachineConfig::default().with_mode(TimingMode::VipCycles);
+    let mut bus = Bus::with_config(&COUNTER, config).unwrap();
+    bus.run_for(Duration::from_millis(50));
+    // 3 frames of 2568 cycles, the loop takes 50 + 52 of them
+    let v0 = bus.registers().v[0];
+    assert!((73..=77).contains(&v0), "V0 = {}", v0);
+
+    // The rate doesn't matter on VIP timing
+    let config = MachineConfig::default()
+        .with_mode(TimingMode::VipCycles)
+        .with_rate(ClockRate::InstructionsPerFrame(1000));
+    let mut bus = Bus::with_config(&COUNTER, config).unwrap();
+    bus.run_for(Duration::from_millis(50));
+    assert_eq!(bus.registers().v[0], v0);
+}
+
+#[test]
+fn test_vip_clear_takes_longer() {
+    // 200: 00E0 clear, 202: 7001 V0 += 1, 204: 1200 loop
+    let rom = [0x00, 0xE0, 0x70, 0x01, 0x12, 0x00];
+    let config = MachineConfig::default().with_mode(TimingMode::VipCycles);
+    let mut bus = Bus::with_config(&rom, config).unwrap();
+    bus.run_for(Duration::from_millis(50));
+    // Under one clear per frame
+    let v0 = bus.registers().v[0];
+    assert!((2..=3).contains(&v0), "V0 = {}", v0);
+}
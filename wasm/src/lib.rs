@@ -0,0 +1,76 @@
+/* WebAssembly build of the emulator, for embedding it on web pages.
+ *
+ * Everything comes in as bytes and goes out as bytes, there is no
+ * window and no files. From JS:
+ *
+ *   const chip8 = new Chip8(romBytes);
+ *   function frame() {
+ *       chip8.run_frame();
+ *       const pixels = chip8.framebuffer(); // RGBA, width * height * 4
+ *       context.putImageData(new ImageData(new Uint8ClampedArray(pixels.buffer),
+ *                                          chip8.width(), chip8.height()), 0, 0);
+ *       requestAnimationFrame(frame);
+ *   }
+ */
+use std::time::Duration;
+
+use wasm_bindgen::prelude::*;
+
+use rusty_chip_8::bus::Bus;
+use rusty_chip_8::keyboard::State;
+use rusty_chip_8::timing::ClockRate;
+
+const PIXEL_ON: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const PIXEL_OFF: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+#[wasm_bindgen]
+pub struct Chip8 {
+    bus: Bus,
+}
+
+#[wasm_bindgen]
+impl Chip8 {
+    // The default machine running the given ROM
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8]) -> Chip8 {
+        Chip8 { bus: Bus::with_rom(rom) }
+    }
+
+    pub fn reset(&mut self) {
+        self.bus.reset();
+    }
+
+    // Runs 1/60 of a second worth of instructions and timer ticks
+    pub fn run_frame(&mut self) {
+        self.bus.run_for(Duration::from_secs_f64(1.0 / 60.0));
+    }
+
+    // key is 0x0-0xF, seen by the machine on its next instruction
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let state = if pressed { State::PRESSED } else { State::RELEASED };
+        let time = self.bus.time();
+        self.bus.queue_key(key, state, time);
+    }
+
+    pub fn set_instructions_per_frame(&mut self, ipf: u32) {
+        self.bus.timing.set_rate(ClockRate::InstructionsPerFrame(ipf));
+    }
+
+    pub fn width(&self) -> usize {
+        self.bus.get_vram().width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.bus.get_vram().height()
+    }
+
+    // RGBA8888, becomes a Uint8Array on JS
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.bus.get_vram().to_rgba(PIXEL_ON, PIXEL_OFF)
+    }
+
+    // The page should be beeping while this is true
+    pub fn sound_active(&self) -> bool {
+        self.bus.state.sound > 0
+    }
+}
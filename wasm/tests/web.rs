@@ -0,0 +1,40 @@
+// Runs under Node with wasm-pack test --node, and natively with cargo test
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::*;
+
+use rusty_chip_8_wasm::Chip8;
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn test_runs_from_bytes() {
+    /*
+     * 200: 6310 V3 = 0x10
+     * 202: F318 sound = V3
+     * 204: F00A V0 = key
+     * 206: F029 I = glyph of V0
+     * 208: D005 draw it at (V0, V0)
+     * 20A: 120A loop forever
+     */
+    let rom = [0x63, 0x10, 0xF3, 0x18, 0xF0, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x0A];
+    let mut chip8 = Chip8::new(&rom);
+    assert_eq!((chip8.width(), chip8.height()), (64, 32));
+
+    chip8.run_frame();
+    assert!(chip8.sound_active());
+    let pixels = chip8.framebuffer();
+    assert_eq!(pixels.len(), 64 * 32 * 4);
+    assert!(pixels.chunks(4).all(|pixel| pixel == [0, 0, 0, 0xFF]));
+
+    // Key 0 draws the 0 glyph at the top left, its first row is full
+    chip8.set_key(0, true);
+    chip8.run_frame();
+    let pixels = chip8.framebuffer();
+    assert_eq!(&pixels[..4], &[0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(&pixels[4 * 4..5 * 4], &[0, 0, 0, 0xFF]);
+
+    // The sound timer runs out after 16 frames
+    for _ in 0..16 {
+        chip8.run_frame();
+    }
+    assert!(!chip8.sound_active());
+}